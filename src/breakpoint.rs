@@ -1,70 +1,157 @@
-use std::ffi::c_void;
-
-use nix::sys::ptrace::{self, AddressType};
-use nix::unistd::Pid;
+use crate::error::Result;
+use crate::target::Target;
 
 pub struct Breakpoint {
-    pid: Pid,
-    addr: Location,
-    enabled: bool,
-    old_instruction: isize,
+    pub num: u32,
+    pub location: Location,
+    /// Runtime address the breakpoint is inserted at
+    pub addr: u64,
+    pub enabled: bool,
+    /// Expression that must be non-zero for a hit to stop the program
+    pub condition: Option<String>,
+    old_instruction: u8,
 }
 
 #[derive(Clone, Debug, Hash, PartialEq, Eq)]
 pub enum Location {
     Address(isize),
-    // TODO: Support these options
     Function(String),
+    /// An expression giving the address, such as `$rip + 4`
+    Expression(String),
+    /// A line in the source file being listed, or else the selected frame's
     Line(u64),
+    /// A line in a source file, such as `main.c:12`
+    FileLine(String, u64),
 }
 
 impl Breakpoint {
-    const BKPT_OPCODE: isize = 0xcc;
-    const OPCODE_BITMASK: isize = 0xff;
+    /// `int3`, which stops the program with a SIGTRAP
+    pub(crate) const BKPT_OPCODE: u8 = 0xcc;
 
-    pub fn new(pid: Pid, addr: Location) -> Breakpoint {
+    pub fn new(num: u32, location: Location, addr: u64) -> Breakpoint {
         Breakpoint {
-            pid,
+            num,
+            location,
             addr,
             enabled: false,
+            condition: None,
             old_instruction: 0,
         }
     }
 
-    pub fn enable(&mut self) {
-        let ptr = match self.addr {
-            Location::Address(addr) => {
-                println!("Got address {:08x}", addr);
-                addr as AddressType
-            }
-            Location::Function(_) => todo!(),
-            Location::Line(_) => todo!(),
-        };
-
-        let data = ptrace::read(self.pid, ptr).unwrap() as isize;
-
-        let old_int = data & Self::OPCODE_BITMASK;
-        let bkpt = (data & !Self::OPCODE_BITMASK) | Self::BKPT_OPCODE;
-
-        unsafe {
-            // TODO: Sanity check
-            ptrace::write(self.pid, ptr, &bkpt as *const _ as *mut c_void).unwrap();
-        }
+    /// Replaces the first byte of the instruction at the breakpoint's address
+    /// with an `int3`, or has a remote target insert it
+    pub fn enable(&mut self, target: &mut dyn Target) -> Result<()> {
+        let old_int = target.insert_breakpoint(self.addr)?;
 
         self.old_instruction = old_int;
         self.enabled = true;
+        Ok(())
     }
 
-    pub fn disable(&mut self) {
-        let ptr = &self.addr as *const _ as AddressType;
-        let data = ptrace::read(self.pid, ptr).unwrap() as isize;
-
-        let prev_data = (data & !Self::OPCODE_BITMASK) | self.old_instruction;
-        unsafe {
-            ptrace::write(self.pid, ptr, &prev_data as *const _ as *mut c_void).unwrap();
+    /// Puts the original byte back in place of the breakpoint's `int3` in
+    /// memory read from `start`, so it shows the program's own code
+    pub fn mask(&self, start: u64, bytes: &mut [u8]) {
+        if !self.enabled {
+            return;
+        }
+        let offset = self.addr.checked_sub(start);
+        if let Some(byte) = offset.and_then(|offset| bytes.get_mut(offset as usize)) {
+            *byte = self.old_instruction;
         }
+    }
+
+    pub fn disable(&mut self, target: &mut dyn Target) -> Result<()> {
+        target.remove_breakpoint(self.addr, self.old_instruction)?;
 
         self.old_instruction = 0;
         self.enabled = false;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockTarget;
+
+    const CODE: [u8; 4] = [0x55, 0x48, 0x89, 0xe5];
+
+    fn breakpoint(addr: u64) -> Breakpoint {
+        Breakpoint::new(1, Location::Address(addr as isize), addr)
+    }
+
+    #[test]
+    fn enable_replaces_first_byte_with_int3() {
+        let mut target = MockTarget::new().map(0x1000, &CODE);
+        let mut bp = breakpoint(0x1001);
+
+        bp.enable(&mut target).unwrap();
+
+        assert!(bp.enabled);
+        assert_eq!(
+            target.read_memory(0x1000, 4).unwrap(),
+            [0x55, 0xcc, 0x89, 0xe5]
+        );
+    }
+
+    #[test]
+    fn disable_restores_original_byte() {
+        let mut target = MockTarget::new().map(0x1000, &CODE);
+        let mut bp = breakpoint(0x1001);
+
+        bp.enable(&mut target).unwrap();
+        bp.disable(&mut target).unwrap();
+
+        assert!(!bp.enabled);
+        assert_eq!(target.read_memory(0x1000, 4).unwrap(), CODE);
+    }
+
+    #[test]
+    fn enable_fails_on_unmapped_address() {
+        let mut target = MockTarget::new().map(0x1000, &CODE);
+        let mut bp = breakpoint(0x2000);
+
+        assert!(bp.enable(&mut target).is_err());
+        assert!(!bp.enabled);
+    }
+
+    #[test]
+    fn mask_hides_int3() {
+        let mut target = MockTarget::new().map(0x1000, &CODE);
+        let mut bp = breakpoint(0x1002);
+        bp.enable(&mut target).unwrap();
+
+        let mut bytes = target.read_memory(0x1000, 4).unwrap();
+        bp.mask(0x1000, &mut bytes);
+
+        assert_eq!(bytes, CODE);
+    }
+
+    #[test]
+    fn mask_ignores_breakpoints_outside_bytes() {
+        let mut target = MockTarget::new().map(0x1000, &CODE);
+        let mut bp = breakpoint(0x1000);
+        bp.enable(&mut target).unwrap();
+
+        // Starting after the breakpoint
+        let mut bytes = target.read_memory(0x1001, 3).unwrap();
+        bp.mask(0x1001, &mut bytes);
+        assert_eq!(bytes, CODE[1..]);
+
+        // Ending before it
+        let mut bytes = vec![0xcc; 2];
+        bp.mask(0xffe, &mut bytes);
+        assert_eq!(bytes, [0xcc, 0xcc]);
+    }
+
+    #[test]
+    fn mask_does_nothing_when_disabled() {
+        let bp = breakpoint(0x1000);
+        let mut bytes = vec![0xcc];
+
+        bp.mask(0x1000, &mut bytes);
+
+        assert_eq!(bytes, [0xcc]);
     }
 }
@@ -0,0 +1,517 @@
+use std::fs::File;
+use std::io;
+use std::mem::{self, size_of};
+use std::ops::Range;
+use std::os::unix::fs::FileExt;
+use std::path::{Path, PathBuf};
+
+use nix::libc::{user_fpregs_struct, user_regs_struct};
+use nix::sys::signal::Signal;
+use object::elf::{
+    FileHeader64, Ident, NoteHeader64, ProgramHeader64, ELFCLASS64, ELFDATA2LSB, ELFMAG,
+    ELFOSABI_SYSV, EM_X86_64, ET_CORE, EV_CURRENT, NT_AUXV, NT_FILE, NT_PRFPREG, NT_PRSTATUS, PF_R,
+    PF_W, PF_X, PT_LOAD, PT_NOTE,
+};
+use object::endian::{LittleEndian as LE, U16, U32, U64};
+use object::read::elf::{FileHeader, ProgramHeader};
+use object::read::ReadCache;
+use object::{pod, Endianness};
+
+use crate::error::{Error, Result};
+use crate::memory;
+use crate::target::{Mapping, Region, StopEvent, Target, Thread};
+
+/// An ELF core dump of a crashed or saved process, which can be inspected but
+/// not run
+pub struct CoreFile {
+    /// The core, which saved memory is read from as it's needed
+    file: File,
+    /// Memory saved in the core
+    segments: Vec<Segment>,
+    /// Threads in the order they were saved, the one that crashed first
+    pub threads: Vec<Thread>,
+    /// Index of the thread registers are read from
+    selected: usize,
+    /// Files mapped into the process, whose contents the core usually leaves
+    /// out, such as code
+    files: Vec<Mapping>,
+    /// The signal that ended the process, if it crashed
+    pub signal: Option<Signal>,
+    /// The contents of the `NT_AUXV` note
+    auxv: Vec<u8>,
+}
+
+/// A `PT_LOAD` segment, whose saved contents can be shorter than its range
+struct Segment {
+    range: Range<u64>,
+    writable: bool,
+    executable: bool,
+    /// Where its contents are in the core
+    offset: u64,
+    /// How much of it, from the start, the core saves
+    saved: u64,
+}
+
+/// Offsets into the `elf_prstatus` struct of an `NT_PRSTATUS` note
+const PRSTATUS_CURSIG: usize = 12;
+const PRSTATUS_PID: usize = 32;
+const PRSTATUS_REGS: usize = 112;
+const PRSTATUS_FPVALID: usize = 328;
+const PRSTATUS_SIZE: usize = 336;
+
+/// Alignment of the memory saved in cores, which the kernel keeps page aligned
+const PAGE_SIZE: u64 = 4096;
+
+impl CoreFile {
+    /// Opens a core dump of `executable`, which is used in place of the path
+    /// the core records for it, so cores from other machines can be loaded
+    pub fn open(path: &Path, executable: &Path) -> Result<CoreFile> {
+        let invalid =
+            |e: object::Error| Error::Elf(format!("Cannot parse {}: {e}", path.display()));
+        let unreadable = |e: io::Error| Error::Elf(format!("Cannot read {}: {e}", path.display()));
+        let file = File::open(path).map_err(unreadable)?;
+        let size = file.metadata().map_err(unreadable)?.len();
+        // Only the headers and notes are read through the cache, as the
+        // memory saved can be much larger
+        let cache = ReadCache::new(file.try_clone().map_err(unreadable)?);
+        let data = &cache;
+        let header = FileHeader64::<Endianness>::parse(data).map_err(invalid)?;
+        let endian = header.endian().map_err(invalid)?;
+        if header.e_type(endian) != ET_CORE {
+            return Err(Error::Elf(format!("{} is not a core file", path.display())));
+        }
+
+        let mut core = CoreFile {
+            file,
+            segments: Vec::new(),
+            threads: Vec::new(),
+            selected: 0,
+            files: Vec::new(),
+            signal: None,
+            auxv: Vec::new(),
+        };
+        for phdr in header.program_headers(endian, data).map_err(invalid)? {
+            match phdr.p_type(endian) {
+                PT_LOAD => {
+                    let start = phdr.p_vaddr(endian);
+                    let (offset, saved) = (phdr.p_offset(endian), phdr.p_filesz(endian));
+                    let end = start
+                        .checked_add(phdr.p_memsz(endian))
+                        .ok_or_else(|| invalid_core(path))?;
+                    if offset.checked_add(saved).is_none_or(|end| end > size) {
+                        return Err(invalid_core(path));
+                    }
+                    core.segments.push(Segment {
+                        range: start..end,
+                        writable: phdr.p_flags(endian) & PF_W != 0,
+                        executable: phdr.p_flags(endian) & PF_X != 0,
+                        offset,
+                        saved,
+                    });
+                }
+                PT_NOTE => {
+                    let Some(mut notes) = phdr.notes(endian, data).map_err(invalid)? else {
+                        continue;
+                    };
+                    while let Some(note) = notes.next().map_err(invalid)? {
+                        if note.name() != b"CORE" {
+                            continue;
+                        }
+                        core.add_note(note.n_type(endian), note.desc())
+                            .ok_or_else(|| invalid_core(path))?;
+                    }
+                }
+                _ => {}
+            }
+        }
+        if core.threads.is_empty() {
+            return Err(Error::Elf(format!("{} has no threads", path.display())));
+        }
+
+        // Code is mapped from files, so mark which mappings are executable
+        for file in &mut core.files {
+            file.executable = core
+                .segments
+                .iter()
+                .any(|segment| segment.executable && segment.range.start == file.range.start);
+            if file.path.file_name() == executable.file_name() {
+                file.path = executable.to_path_buf();
+            }
+        }
+        Ok(core)
+    }
+
+    fn add_note(&mut self, kind: u32, desc: &[u8]) -> Option<()> {
+        match kind {
+            NT_PRSTATUS => {
+                let signal =
+                    u16::from_le_bytes(desc.get(PRSTATUS_CURSIG..)?.get(..2)?.try_into().ok()?);
+                let tid = i32::from_le_bytes(desc.get(PRSTATUS_PID..)?.get(..4)?.try_into().ok()?);
+                if self.threads.is_empty() && signal != 0 {
+                    self.signal = Signal::try_from(signal as i32).ok();
+                }
+                self.threads.push(Thread {
+                    tid,
+                    regs: from_bytes(desc.get(PRSTATUS_REGS..)?)?,
+                    fpregs: None,
+                });
+            }
+            // Each thread's floating point registers follow its status
+            NT_PRFPREG => self.threads.last_mut()?.fpregs = Some(from_bytes(desc)?),
+            NT_FILE => self.files = parse_files(desc)?,
+            NT_AUXV => self.auxv = desc.to_vec(),
+            _ => {}
+        }
+        Some(())
+    }
+
+    /// Reads as much memory as possible at `addr` from one place, either the
+    /// core or a mapped file, appending it to `bytes`
+    fn read_run(&self, addr: u64, len: usize, bytes: &mut Vec<u8>) -> Option<()> {
+        let saved = self.segments.iter().find_map(|segment| {
+            let offset = addr.checked_sub(segment.range.start)?;
+            (offset < segment.saved).then(|| (segment.offset + offset, segment.saved - offset))
+        });
+        if let Some((offset, saved)) = saved {
+            let mut buf = vec![0; len.min(saved.try_into().unwrap_or(usize::MAX))];
+            self.file.read_exact_at(&mut buf, offset).ok()?;
+            bytes.extend_from_slice(&buf);
+            return Some(());
+        }
+
+        let file = self.files.iter().find(|file| file.range.contains(&addr))?;
+        let len = len.min((file.range.end - addr) as usize);
+        let mut buf = vec![0; len];
+        let offset = file.offset + (addr - file.range.start);
+        File::open(&file.path)
+            .ok()?
+            .read_exact_at(&mut buf, offset)
+            .ok()?;
+        bytes.extend_from_slice(&buf);
+        Some(())
+    }
+}
+
+impl Target for CoreFile {
+    fn read_memory(&self, addr: u64, len: usize) -> Result<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(len);
+        while bytes.len() < len {
+            let at = addr + bytes.len() as u64;
+            self.read_run(at, len - bytes.len(), &mut bytes)
+                .ok_or_else(|| Error::Eval(format!("Cannot access memory at {at:#x}")))?;
+        }
+        Ok(bytes)
+    }
+
+    fn write_memory(&mut self, _addr: u64, _bytes: &[u8]) -> Result<()> {
+        Err(read_only())
+    }
+
+    fn regs(&self) -> Result<user_regs_struct> {
+        Ok(self.threads[self.selected].regs)
+    }
+
+    fn set_regs(&mut self, _regs: user_regs_struct) -> Result<()> {
+        Err(read_only())
+    }
+
+    fn fpregs(&self) -> Result<user_fpregs_struct> {
+        self.threads[self.selected]
+            .fpregs
+            .ok_or_else(|| Error::NotFound("The core file has no floating point registers".into()))
+    }
+
+    fn set_fpregs(&mut self, _regs: user_fpregs_struct) -> Result<()> {
+        Err(read_only())
+    }
+
+    fn resume(&mut self) -> Result<()> {
+        Err(read_only())
+    }
+
+    fn step(&mut self) -> Result<()> {
+        Err(read_only())
+    }
+
+    fn wait(&mut self) -> Result<StopEvent> {
+        Err(read_only())
+    }
+
+    fn mappings(&self) -> Vec<Mapping> {
+        self.files.clone()
+    }
+
+    fn threads(&self) -> Result<Vec<Thread>> {
+        let mut threads = self.threads.clone();
+        let selected = threads.remove(self.selected);
+        threads.insert(0, selected);
+        Ok(threads)
+    }
+
+    fn select_thread(&mut self, tid: i32) -> Result<()> {
+        self.selected = self
+            .threads
+            .iter()
+            .position(|thread| thread.tid == tid)
+            .ok_or_else(|| Error::NotFound(format!("No thread {tid}")))?;
+        Ok(())
+    }
+
+    fn regions(&self) -> Vec<Region> {
+        self.segments
+            .iter()
+            .map(|segment| {
+                let file = self.files.iter().find(|file| file.range == segment.range);
+                Region {
+                    range: segment.range.clone(),
+                    readable: true,
+                    writable: segment.writable,
+                    executable: segment.executable,
+                    offset: file.map_or(0, |file| file.offset),
+                    path: file.map(|file| file.path.clone()),
+                }
+            })
+            .collect()
+    }
+
+    fn auxv(&self) -> Result<Vec<u8>> {
+        Ok(self.auxv.clone())
+    }
+}
+
+/// Saves the state of a stopped program as an ELF core, which `CoreFile` and
+/// other debuggers can load. Like the kernel, memory mapped from files is only
+/// saved if it's writable, as the rest can be read from the files
+pub fn write(target: &dyn Target, path: &Path) -> Result<()> {
+    let threads = target.threads()?;
+    let regions = target.regions();
+    if regions.is_empty() {
+        return Err(Error::State("The program's memory can't be saved".into()));
+    }
+
+    // The kernel's order: the first thread's status, then notes about the
+    // whole process, then the first thread's floating point registers and
+    // the other threads
+    let mut notes = Vec::new();
+    for (i, thread) in threads.iter().enumerate() {
+        add_note(&mut notes, NT_PRSTATUS, &prstatus(thread));
+        if i == 0 {
+            add_note(&mut notes, NT_AUXV, &target.auxv()?);
+            add_note(&mut notes, NT_FILE, &files_note(&regions));
+        }
+        if let Some(fpregs) = &thread.fpregs {
+            add_note(&mut notes, NT_PRFPREG, pod::bytes_of(&Raw(*fpregs)));
+        }
+    }
+
+    let headers_len =
+        size_of::<FileHeader64<LE>>() + (regions.len() + 1) * size_of::<ProgramHeader64<LE>>();
+    let data_start = ((headers_len + notes.len()) as u64).next_multiple_of(PAGE_SIZE);
+
+    let failed = |e: io::Error| Error::State(format!("Cannot write {}: {e}", path.display()));
+    let file = File::create(path).map_err(failed)?;
+
+    // Memory is copied straight to the file, after which the headers saying
+    // how much of each region was saved go in front of it
+    let mut offset = data_start;
+    let mut saved = Vec::with_capacity(regions.len());
+    for region in &regions {
+        // Some special regions, such as `[vvar]`, can't be read
+        let len = match region.readable && (region.writable || region.path.is_none()) {
+            true => write_region(target, region, &file, offset).map_err(failed)?,
+            false => 0,
+        };
+        saved.push(len);
+        offset += len;
+    }
+
+    let mut headers = Vec::new();
+    headers.extend_from_slice(pod::bytes_of(&file_header(regions.len() + 1)));
+    let note = program_header(PT_NOTE, 0, headers_len as u64, 0, notes.len() as u64, 4);
+    headers.extend_from_slice(pod::bytes_of(&note));
+    let mut offset = data_start;
+    for (region, saved) in regions.iter().zip(saved) {
+        let flags = [
+            (region.readable, PF_R),
+            (region.writable, PF_W),
+            (region.executable, PF_X),
+        ]
+        .iter()
+        .filter(|(set, _)| *set)
+        .fold(0, |flags, (_, flag)| flags | flag);
+        let size = region.range.end - region.range.start;
+        let mut phdr = program_header(PT_LOAD, flags, offset, region.range.start, saved, PAGE_SIZE);
+        phdr.p_memsz = U64::new(LE, size);
+        headers.extend_from_slice(pod::bytes_of(&phdr));
+        offset += saved;
+    }
+    headers.extend_from_slice(&notes);
+    headers.resize(data_start as usize, 0);
+    file.write_all_at(&headers, 0).map_err(failed)
+}
+
+/// Copies a region of memory to the file at `offset` a chunk at a time,
+/// stopping at the first chunk that can't be read. Gives the bytes copied
+fn write_region(target: &dyn Target, region: &Region, file: &File, offset: u64) -> io::Result<u64> {
+    let mut addr = region.range.start;
+    while addr < region.range.end {
+        let len = (region.range.end - addr).min(memory::MAX_READ_LEN as u64);
+        let data = match target.read_memory(addr, len as usize) {
+            Ok(data) if !data.is_empty() => data,
+            _ => break,
+        };
+        file.write_all_at(&data, offset + (addr - region.range.start))?;
+        addr += data.len() as u64;
+    }
+    Ok(addr - region.range.start)
+}
+
+fn file_header(phnum: usize) -> FileHeader64<LE> {
+    FileHeader64 {
+        e_ident: Ident {
+            magic: ELFMAG,
+            class: ELFCLASS64,
+            data: ELFDATA2LSB,
+            version: EV_CURRENT,
+            os_abi: ELFOSABI_SYSV,
+            abi_version: 0,
+            padding: [0; 7],
+        },
+        e_type: U16::new(LE, ET_CORE),
+        e_machine: U16::new(LE, EM_X86_64),
+        e_version: U32::new(LE, EV_CURRENT.into()),
+        e_entry: U64::new(LE, 0),
+        e_phoff: U64::new(LE, size_of::<FileHeader64<LE>>() as u64),
+        e_shoff: U64::new(LE, 0),
+        e_flags: U32::new(LE, 0),
+        e_ehsize: U16::new(LE, size_of::<FileHeader64<LE>>() as u16),
+        e_phentsize: U16::new(LE, size_of::<ProgramHeader64<LE>>() as u16),
+        e_phnum: U16::new(LE, phnum as u16),
+        e_shentsize: U16::new(LE, 0),
+        e_shnum: U16::new(LE, 0),
+        e_shstrndx: U16::new(LE, 0),
+    }
+}
+
+fn program_header(
+    kind: u32,
+    flags: u32,
+    offset: u64,
+    addr: u64,
+    size: u64,
+    align: u64,
+) -> ProgramHeader64<LE> {
+    ProgramHeader64 {
+        p_type: U32::new(LE, kind),
+        p_flags: U32::new(LE, flags),
+        p_offset: U64::new(LE, offset),
+        p_vaddr: U64::new(LE, addr),
+        p_paddr: U64::new(LE, 0),
+        p_filesz: U64::new(LE, size),
+        p_memsz: U64::new(LE, size),
+        p_align: U64::new(LE, align),
+    }
+}
+
+/// Appends a note named `CORE`, padding its name and contents to 4 bytes
+fn add_note(notes: &mut Vec<u8>, kind: u32, desc: &[u8]) {
+    let name = b"CORE\0";
+    let header = NoteHeader64 {
+        n_namesz: U32::new(LE, name.len() as u32),
+        n_descsz: U32::new(LE, desc.len() as u32),
+        n_type: U32::new(LE, kind),
+    };
+    notes.extend_from_slice(pod::bytes_of(&header));
+    notes.extend_from_slice(name);
+    notes.resize(notes.len().next_multiple_of(4), 0);
+    notes.extend_from_slice(desc);
+    notes.resize(notes.len().next_multiple_of(4), 0);
+}
+
+/// Builds the `elf_prstatus` struct of a thread, leaving out what the
+/// debugger doesn't know, such as its parent and times
+fn prstatus(thread: &Thread) -> Vec<u8> {
+    let mut status = vec![0; PRSTATUS_SIZE];
+    status[PRSTATUS_PID..PRSTATUS_PID + 4].copy_from_slice(&thread.tid.to_le_bytes());
+    let regs = Raw(thread.regs);
+    let regs = pod::bytes_of(&regs);
+    status[PRSTATUS_REGS..PRSTATUS_REGS + regs.len()].copy_from_slice(regs);
+    let fpvalid = thread.fpregs.is_some() as u32;
+    status[PRSTATUS_FPVALID..PRSTATUS_FPVALID + 4].copy_from_slice(&fpvalid.to_le_bytes());
+    status
+}
+
+/// Builds an `NT_FILE` note from the regions mapped from files
+fn files_note(regions: &[Region]) -> Vec<u8> {
+    let files: Vec<_> = regions
+        .iter()
+        .filter_map(|region| Some((region, region.path.as_ref()?)))
+        .collect();
+    let mut words = vec![files.len() as u64, PAGE_SIZE];
+    for (region, _) in &files {
+        words.extend([
+            region.range.start,
+            region.range.end,
+            region.offset / PAGE_SIZE,
+        ]);
+    }
+
+    let mut desc: Vec<u8> = words.iter().flat_map(|word| word.to_le_bytes()).collect();
+    for (_, path) in &files {
+        desc.extend_from_slice(path.as_os_str().as_encoded_bytes());
+        desc.push(0);
+    }
+    desc
+}
+
+fn read_only() -> Error {
+    Error::State("A core file can't be changed or run".into())
+}
+
+fn invalid_core(path: &Path) -> Error {
+    Error::Elf(format!("{} is not a valid core file", path.display()))
+}
+
+/// Parses an `NT_FILE` note: a count and page size, then the start, end and
+/// offset in pages of each mapping, then their paths
+fn parse_files(desc: &[u8]) -> Option<Vec<Mapping>> {
+    let words: Vec<u64> = desc
+        .chunks_exact(8)
+        .map(|word| u64::from_le_bytes(word.try_into().unwrap()))
+        .collect();
+    let (count, page_size) = (usize::try_from(*words.first()?).ok()?, *words.get(1)?);
+    let names_start = count.checked_mul(3)?.checked_add(2)?;
+    let entries = words.get(2..names_start)?;
+    let names = desc.get(names_start.checked_mul(8)?..)?.split(|&b| b == 0);
+
+    entries
+        .chunks_exact(3)
+        .zip(names)
+        .map(|(entry, name)| {
+            Some(Mapping {
+                range: entry[0]..entry[1],
+                executable: false,
+                offset: entry[2].checked_mul(page_size)?,
+                path: PathBuf::from(String::from_utf8_lossy(name).into_owned()),
+            })
+        })
+        .collect()
+}
+
+/// A register set, which can be written out as bytes
+#[derive(Clone, Copy)]
+#[repr(transparent)]
+struct Raw<T>(T);
+
+// Only used for the register structs, which are plain integers without padding
+unsafe impl pod::Pod for Raw<user_regs_struct> {}
+unsafe impl pod::Pod for Raw<user_fpregs_struct> {}
+
+/// Reads a plain C struct, such as a register set, from the start of `bytes`
+fn from_bytes<T: Copy>(bytes: &[u8]) -> Option<T> {
+    if bytes.len() < mem::size_of::<T>() {
+        return None;
+    }
+    // Only used for structs of integers, for which any bytes are valid
+    Some(unsafe { std::ptr::read_unaligned(bytes.as_ptr() as *const T) })
+}
@@ -0,0 +1,708 @@
+//! A Debug Adapter Protocol server, which lets editors such as VS Code, Helix
+//! and nvim-dap debug programs through bkpt, speaking JSON over stdio
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::{BufRead, BufReader, Read, Write};
+use std::os::fd::{AsRawFd, FromRawFd, OwnedFd};
+use std::sync::atomic::{AtomicI32, AtomicUsize, Ordering};
+use std::sync::mpsc::{self, Receiver};
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use nix::errno::Errno;
+use nix::libc;
+use nix::sys::signal::{kill, Signal};
+use nix::unistd::Pid;
+use serde_json::{json, Value};
+
+use crate::breakpoint::Location;
+use crate::debugger::Debugger;
+use crate::error::{Error, Result};
+use crate::process::Process;
+use crate::target::{NoTarget, StopEvent};
+
+/// Bytes of output forwarded in each output event
+const OUTPUT_CHUNK: usize = 0x1000;
+
+/// The largest message body read, so a bad `Content-Length` is an error
+/// rather than an allocation of that size
+const MAX_MESSAGE_LEN: usize = 16 << 20;
+
+/// Serves DAP on stdin and stdout until the editor disconnects. Anything else
+/// written to stdout, by bkpt or the program, is sent to the editor as output
+/// events, and the program reads nothing from stdin
+pub fn serve_stdio() -> Result<()> {
+    let (input, output, printed) = redirect_stdio()?;
+    let sender = Sender::new(File::from(output));
+
+    let events = sender.clone();
+    thread::spawn(move || {
+        let mut printed = File::from(printed);
+        let mut buf = [0; OUTPUT_CHUNK];
+        while let Ok(len @ 1..) = printed.read(&mut buf) {
+            events.event(
+                "output",
+                json!({
+                    "category": "stdout",
+                    "output": String::from_utf8_lossy(&buf[..len]),
+                }),
+            );
+        }
+    });
+
+    Session::new(sender).serve(BufReader::new(File::from(input)))
+}
+
+/// Serves DAP on a connection until the editor disconnects
+pub fn serve(
+    input: impl BufRead + Send + 'static,
+    output: impl Write + Send + 'static,
+) -> Result<()> {
+    Session::new(Sender::new(output)).serve(input)
+}
+
+/// Moves the editor's or tool's ends of stdin and stdout out of the way of the
+/// program and of `println!`, giving them and a pipe everything printed goes to
+pub(crate) fn redirect_stdio() -> Result<(OwnedFd, OwnedFd, OwnedFd)> {
+    let failed = |e: Errno| Error::State(format!("Cannot redirect stdio: {}", e.desc()));
+    // Closed on exec, so the program doesn't inherit them
+    let dup = |fd| -> Result<OwnedFd> {
+        let fd =
+            Errno::result(unsafe { libc::fcntl(fd, libc::F_DUPFD_CLOEXEC, 0) }).map_err(failed)?;
+        Ok(unsafe { OwnedFd::from_raw_fd(fd) })
+    };
+    let (input, output) = (dup(libc::STDIN_FILENO)?, dup(libc::STDOUT_FILENO)?);
+
+    let mut fds = [0; 2];
+    Errno::result(unsafe { libc::pipe2(fds.as_mut_ptr(), libc::O_CLOEXEC) }).map_err(failed)?;
+    let (printed, print) = unsafe { (OwnedFd::from_raw_fd(fds[0]), OwnedFd::from_raw_fd(fds[1])) };
+    let null =
+        File::open("/dev/null").map_err(|e| Error::State(format!("Cannot open /dev/null: {e}")))?;
+    Errno::result(unsafe { libc::dup2(null.as_raw_fd(), libc::STDIN_FILENO) }).map_err(failed)?;
+    Errno::result(unsafe { libc::dup2(print.as_raw_fd(), libc::STDOUT_FILENO) }).map_err(failed)?;
+    Ok((input, output, printed))
+}
+
+/// Reads a message, which is a `Content-Length` header and a JSON body
+fn read_message(input: &mut impl BufRead) -> Result<Option<Value>> {
+    let invalid = |e: &dyn std::fmt::Display| Error::Remote(format!("Invalid DAP message: {e}"));
+    let mut len = None;
+    loop {
+        let mut header = String::new();
+        if input.read_line(&mut header).map_err(|e| invalid(&e))? == 0 {
+            return Ok(None);
+        }
+        let header = header.trim_end();
+        if header.is_empty() {
+            break;
+        }
+        if let Some((name, value)) = header.split_once(':') {
+            if name.eq_ignore_ascii_case("Content-Length") {
+                len = Some(value.trim().parse().map_err(|e| invalid(&e))?);
+            }
+        }
+    }
+
+    let len = len.ok_or_else(|| invalid(&"no Content-Length"))?;
+    if len > MAX_MESSAGE_LEN {
+        return Err(invalid(&format!("Content-Length {len} is too large")));
+    }
+    let mut body = vec![0; len];
+    input.read_exact(&mut body).map_err(|e| invalid(&e))?;
+    serde_json::from_slice(&body)
+        .map(Some)
+        .map_err(|e| invalid(&e))
+}
+
+/// Writes messages to the editor, numbering them. Shared with the thread
+/// forwarding output
+#[derive(Clone)]
+struct Sender(Arc<Mutex<(Box<dyn Write + Send>, u64)>>);
+
+impl Sender {
+    fn new(output: impl Write + Send + 'static) -> Sender {
+        Sender(Arc::new(Mutex::new((Box::new(output), 1))))
+    }
+
+    fn send(&self, mut message: Value) {
+        // Messages without a body leave it out
+        if let Some(message) = message.as_object_mut() {
+            message.retain(|key, value| key != "body" || !value.is_null());
+        }
+        let mut connection = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let (output, seq) = &mut *connection;
+        message["seq"] = json!(*seq);
+        *seq += 1;
+        let body = message.to_string();
+        // The editor is gone if this fails, which the next read finds
+        let _ = write!(output, "Content-Length: {}\r\n\r\n{body}", body.len());
+        let _ = output.flush();
+    }
+
+    fn event(&self, event: &str, body: Value) {
+        self.send(json!({ "type": "event", "event": event, "body": body }));
+    }
+}
+
+/// Requests the program is stopped for if they come while it runs. Others
+/// wait until it stops
+const INTERRUPTING: [&str; 4] = [
+    "pause",
+    "disconnect",
+    "setBreakpoints",
+    "setFunctionBreakpoints",
+];
+
+/// Requests read so far, or the error that ended reading, or `None` once the
+/// editor closed the connection
+type Requests = Receiver<Result<Option<Value>>>;
+
+/// Lets the thread reading requests stop the program for the ones that need
+/// it. Shared with that thread
+#[derive(Default)]
+struct Interrupter {
+    /// The process's id while it runs, and 0 otherwise
+    running: AtomicI32,
+    /// Requests read that stop the program, and haven't been handled yet
+    pending: AtomicUsize,
+}
+
+impl Interrupter {
+    fn interrupt(&self) {
+        let pid = self.running.load(Ordering::SeqCst);
+        if pid != 0 {
+            let _ = kill(Pid::from_raw(pid), Signal::SIGINT);
+        }
+    }
+}
+
+/// How a request lets the program run, which happens once it's answered
+#[derive(Clone, Copy, PartialEq)]
+enum Run {
+    Continue,
+    Next,
+    StepIn,
+    StepOut,
+}
+
+struct Session {
+    sender: Sender,
+    dbg: Debugger,
+    /// Whether to stop before the program's first instruction, rather than
+    /// running it once the editor has set its breakpoints
+    stop_on_entry: bool,
+    /// Breakpoints set by `setBreakpoints` for each source file, which the
+    /// next request for the file replaces
+    source_breakpoints: HashMap<String, Vec<u32>>,
+    /// Breakpoints set by `setFunctionBreakpoints`, which the next one replaces
+    function_breakpoints: Vec<u32>,
+    /// Events to send once the request is answered
+    events: Vec<(&'static str, Value)>,
+    /// How to run the program once the request is answered
+    run: Option<Run>,
+    /// The process started or attached to, which can be interrupted, unlike a
+    /// remote target
+    pid: Option<Pid>,
+    interrupter: Arc<Interrupter>,
+    done: bool,
+}
+
+impl Session {
+    fn new(sender: Sender) -> Session {
+        Session {
+            sender,
+            dbg: Debugger::new(NoTarget),
+            stop_on_entry: false,
+            source_breakpoints: HashMap::new(),
+            function_breakpoints: Vec::new(),
+            events: Vec::new(),
+            run: None,
+            pid: None,
+            interrupter: Arc::default(),
+            done: false,
+        }
+    }
+
+    fn serve(mut self, mut input: impl BufRead + Send + 'static) -> Result<()> {
+        // Requests are read on another thread, so ones such as `pause` are
+        // seen while the program runs
+        let (sender, requests) = mpsc::channel();
+        let interrupter = Arc::clone(&self.interrupter);
+        thread::spawn(move || loop {
+            let request = read_message(&mut input);
+            let interrupt = match &request {
+                Ok(Some(request)) => interrupting(request),
+                _ => true,
+            };
+            let last = !matches!(request, Ok(Some(_)));
+            // Sent first, so it's there once the program has stopped
+            if sender.send(request).is_err() {
+                break;
+            }
+            if interrupt {
+                interrupter.pending.fetch_add(1, Ordering::SeqCst);
+                interrupter.interrupt();
+            }
+            if last {
+                break;
+            }
+        });
+
+        while let Ok(request) = requests.recv() {
+            let Some(request) = request? else {
+                break;
+            };
+            self.respond(&request);
+            if let Some(run) = self.run.take() {
+                self.resume(run, &requests)?;
+            }
+            if self.done {
+                break;
+            }
+        }
+        Ok(())
+    }
+
+    /// Answers a request, then sends the events it led to
+    fn respond(&mut self, request: &Value) {
+        if interrupting(request) {
+            self.interrupter.pending.fetch_sub(1, Ordering::SeqCst);
+        }
+        let command = request["command"].as_str().unwrap_or_default();
+        let mut response = match self.handle(command, &request["arguments"]) {
+            Ok(body) => json!({ "success": true, "body": body }),
+            Err(e) => json!({
+                "success": false,
+                "message": e.to_string(),
+                "body": { "error": { "id": 1, "format": e.to_string() } },
+            }),
+        };
+        response["type"] = json!("response");
+        response["request_seq"] = request["seq"].clone();
+        response["command"] = json!(command);
+        self.sender.send(response);
+
+        for (event, body) in self.events.drain(..) {
+            self.sender.event(event, body);
+        }
+    }
+
+    /// Handles a request, giving the body of its response
+    fn handle(&mut self, command: &str, args: &Value) -> Result<Value> {
+        match command {
+            "initialize" => Ok(json!({
+                "supportsConfigurationDoneRequest": true,
+                "supportsFunctionBreakpoints": true,
+                "supportsConditionalBreakpoints": true,
+            })),
+            "launch" => {
+                let program = args["program"]
+                    .as_str()
+                    .ok_or_else(|| Error::Parse("launch needs a program".into()))?;
+                let argv: Vec<String> = args["args"]
+                    .as_array()
+                    .into_iter()
+                    .flatten()
+                    .filter_map(|arg| Some(arg.as_str()?.to_string()))
+                    .collect();
+                if let Some(cwd) = args["cwd"].as_str() {
+                    std::env::set_current_dir(cwd)
+                        .map_err(|e| Error::State(format!("Cannot change to {cwd}: {e}")))?;
+                }
+                let process = Process::launch(program, &argv)?;
+                self.pid = Some(process.pid());
+                self.dbg = Debugger::new(process);
+                self.started(args);
+                Ok(Value::Null)
+            }
+            "attach" => {
+                if let Some(pid) = args["pid"].as_i64() {
+                    let process = Process::attach(Pid::from_raw(pid as i32))?;
+                    self.pid = Some(process.pid());
+                    self.dbg = Debugger::new(process);
+                } else if let Some(addr) = args["target"].as_str() {
+                    self.pid = None;
+                    self.dbg = Debugger::new(NoTarget);
+                    self.dbg.connect(addr)?;
+                } else {
+                    return Err(Error::Parse(
+                        "attach needs a pid, or a target stub's address".into(),
+                    ));
+                }
+                self.started(args);
+                Ok(Value::Null)
+            }
+            "setBreakpoints" => {
+                let path = args["source"]["path"]
+                    .as_str()
+                    .ok_or_else(|| Error::Parse("setBreakpoints needs a source path".into()))?;
+                let old = self.source_breakpoints.remove(path).unwrap_or_default();
+                self.unset_breakpoints(old);
+
+                let mut nums = Vec::new();
+                let breakpoints = requested(args)
+                    .map(|bp| {
+                        let line = bp["line"].as_u64().unwrap_or_default();
+                        let loc = Location::FileLine(path.to_string(), line);
+                        let mut set = self.set_breakpoint(loc, condition(bp), &mut nums);
+                        set["line"] = json!(line);
+                        set
+                    })
+                    .collect::<Vec<_>>();
+                self.source_breakpoints.insert(path.to_string(), nums);
+                Ok(json!({ "breakpoints": breakpoints }))
+            }
+            "setFunctionBreakpoints" => {
+                let old = std::mem::take(&mut self.function_breakpoints);
+                self.unset_breakpoints(old);
+
+                let mut nums = Vec::new();
+                let breakpoints = requested(args)
+                    .map(|bp| {
+                        let name = bp["name"].as_str().unwrap_or_default();
+                        let loc = Location::Function(name.to_string());
+                        self.set_breakpoint(loc, condition(bp), &mut nums)
+                    })
+                    .collect::<Vec<_>>();
+                self.function_breakpoints = nums;
+                Ok(json!({ "breakpoints": breakpoints }))
+            }
+            "configurationDone" => {
+                match self.stop_on_entry {
+                    true => {
+                        let stopped = self.stopped("entry", None);
+                        self.events.push(("stopped", stopped));
+                    }
+                    false => self.run = Some(Run::Continue),
+                }
+                Ok(Value::Null)
+            }
+            "threads" => {
+                let threads = self.dbg.target().threads().unwrap_or_default();
+                let threads: Vec<Value> = threads
+                    .iter()
+                    .map(|thread| {
+                        let id = thread_id(thread.tid);
+                        json!({ "id": id, "name": format!("Thread {id}") })
+                    })
+                    .collect();
+                Ok(json!({ "threads": threads }))
+            }
+            "stackTrace" => {
+                // Only the thread the debugger follows can be unwound
+                if args["threadId"].as_i64() != Some(self.thread()) {
+                    return Ok(json!({ "stackFrames": [], "totalFrames": 0 }));
+                }
+                let frames = self.dbg.stack()?;
+                let total = frames.len();
+                let start = args["startFrame"].as_u64().unwrap_or(0) as usize;
+                let levels = match args["levels"].as_u64() {
+                    Some(levels @ 1..) => levels as usize,
+                    _ => total,
+                };
+                let frames: Vec<Value> = frames
+                    .into_iter()
+                    .enumerate()
+                    .skip(start)
+                    .take(levels)
+                    .map(|(id, frame)| {
+                        let name = match frame.function {
+                            Some((name, 0)) => name,
+                            Some((name, offset)) => format!("{name}+{offset:#x}"),
+                            None => format!("{:#x}", frame.pc),
+                        };
+                        let mut value = json!({
+                            "id": id,
+                            "name": name,
+                            "line": 0,
+                            "column": 0,
+                            "instructionPointerReference": format!("{:#x}", frame.pc),
+                        });
+                        if let Some((path, line)) = frame.source {
+                            let file = path.file_name().unwrap_or_default().to_string_lossy();
+                            value["source"] = json!({ "name": file, "path": path });
+                            value["line"] = json!(line);
+                            value["column"] = json!(1);
+                        }
+                        value
+                    })
+                    .collect();
+                Ok(json!({ "stackFrames": frames, "totalFrames": total }))
+            }
+            "scopes" => {
+                let frame = args["frameId"].as_u64().unwrap_or(0);
+                // Each frame's arguments and locals have their own reference,
+                // which is never 0
+                Ok(json!({ "scopes": [
+                    {
+                        "name": "Arguments",
+                        "presentationHint": "arguments",
+                        "variablesReference": frame * 2 + 1,
+                        "expensive": false,
+                    },
+                    {
+                        "name": "Locals",
+                        "presentationHint": "locals",
+                        "variablesReference": frame * 2 + 2,
+                        "expensive": false,
+                    },
+                ]}))
+            }
+            "variables" => {
+                let reference = args["variablesReference"].as_u64().unwrap_or(0);
+                let Some(scope) = reference.checked_sub(1) else {
+                    return Err(Error::NotFound("No variables with reference 0".into()));
+                };
+                let vars = self.dbg.variables(scope as usize / 2, scope % 2 == 0)?;
+                let vars: Vec<Value> = vars
+                    .into_iter()
+                    .map(|(name, value)| {
+                        json!({ "name": name, "value": value, "variablesReference": 0 })
+                    })
+                    .collect();
+                Ok(json!({ "variables": vars }))
+            }
+            // Only comes while the program is stopped once it's been
+            // interrupted, which `resume` reports
+            "pause" => Ok(Value::Null),
+            "continue" => {
+                self.run = Some(Run::Continue);
+                Ok(json!({ "allThreadsContinued": true }))
+            }
+            "next" | "stepIn" | "stepOut" => {
+                self.run = Some(match command {
+                    "next" => Run::Next,
+                    "stepIn" => Run::StepIn,
+                    _ => Run::StepOut,
+                });
+                Ok(Value::Null)
+            }
+            "evaluate" => {
+                let expr = args["expression"].as_str().unwrap_or_default();
+                // The debug console takes commands, whose output is printed
+                if args["context"] == "repl" {
+                    self.dbg.execute(expr)?;
+                    return Ok(json!({ "result": "", "variablesReference": 0 }));
+                }
+                let value = self.dbg.evaluate(expr)?;
+                Ok(json!({
+                    "result": format!("{} ({value:#x})", value as i64),
+                    "variablesReference": 0,
+                }))
+            }
+            "disconnect" => {
+                self.done = true;
+                self.pid = None;
+                self.dbg.quit()?;
+                Ok(Value::Null)
+            }
+            _ => Err(Error::Parse(format!("Unsupported request {command}"))),
+        }
+    }
+
+    /// Gets ready for breakpoints once the program is started or attached to
+    fn started(&mut self, args: &Value) {
+        self.stop_on_entry = args["stopOnEntry"].as_bool().unwrap_or(false);
+        self.source_breakpoints.clear();
+        self.function_breakpoints.clear();
+        self.events.push(("initialized", Value::Null));
+    }
+
+    /// Sets a breakpoint, giving how the editor should show it
+    fn set_breakpoint(
+        &mut self,
+        loc: Location,
+        condition: Option<String>,
+        nums: &mut Vec<u32>,
+    ) -> Value {
+        match self.dbg.set_breakpoint(loc, condition) {
+            Ok(bp) => {
+                nums.push(bp.num);
+                json!({ "id": bp.num, "verified": true })
+            }
+            Err(e) => json!({ "verified": false, "message": e.to_string() }),
+        }
+    }
+
+    fn unset_breakpoints(&mut self, nums: Vec<u32>) {
+        for num in nums {
+            // Already gone if the program has exited
+            let _ = self.dbg.unset_breakpoint(num);
+        }
+    }
+
+    /// The thread the debugger follows, which stops are reported for
+    fn thread(&self) -> i64 {
+        let threads = self.dbg.target().threads().unwrap_or_default();
+        thread_id(threads.first().map_or(0, |thread| thread.tid))
+    }
+
+    /// Runs the program, then reports how it stopped. Requests that come
+    /// while it runs interrupt it, and are answered before it carries on
+    /// continuing, while `pause` and interrupted steps report it stopped
+    fn resume(&mut self, mut run: Run, requests: &Requests) -> Result<()> {
+        loop {
+            let pid = self.pid.map_or(0, Pid::as_raw);
+            self.interrupter.running.store(pid, Ordering::SeqCst);
+            // Ones read before it was running didn't interrupt it
+            if self.interrupter.pending.load(Ordering::SeqCst) > 0 {
+                self.interrupter.interrupt();
+            }
+            let stop = match run {
+                Run::Continue => self.dbg.continue_(),
+                Run::Next => self.dbg.step_line(true),
+                Run::StepIn => self.dbg.step_line(false),
+                Run::StepOut => self.dbg.finish(),
+            };
+            self.interrupter.running.store(0, Ordering::SeqCst);
+            if !matches!(stop, Ok(StopEvent::Signal(Signal::SIGINT))) {
+                self.report(stop);
+                return Ok(());
+            }
+
+            let mut paused = run != Run::Continue;
+            while let Ok(request) = requests.try_recv() {
+                let Some(request) = request? else {
+                    self.done = true;
+                    return Ok(());
+                };
+                paused |= request["command"] == "pause";
+                self.respond(&request);
+                if self.done {
+                    return Ok(());
+                }
+            }
+            match self.run.take() {
+                Some(next) => run = next,
+                None if paused => {
+                    self.report(stop);
+                    return Ok(());
+                }
+                None => {}
+            }
+        }
+    }
+
+    /// Tells the editor how the program stopped
+    fn report(&mut self, stop: Result<StopEvent>) {
+        let stopped = match stop {
+            Ok(StopEvent::Breakpoint { num, .. }) => {
+                let mut stopped = self.stopped("breakpoint", None);
+                stopped["hitBreakpointIds"] = json!([num]);
+                stopped
+            }
+            Ok(StopEvent::Step { .. }) => self.stopped("step", None),
+            Ok(StopEvent::Signal(Signal::SIGINT)) => self.stopped("pause", None),
+            Ok(StopEvent::Signal(signal)) => {
+                self.stopped("exception", Some(format!("Program received {signal:?}")))
+            }
+            Ok(StopEvent::Exited(code)) => return self.exited(code),
+            // Like a shell, report a kill as 128 plus the signal's number
+            Ok(StopEvent::Killed(signal)) => return self.exited(128 + signal as i32),
+            // The program didn't run, such as when stepping out of the
+            // outermost frame, so the editor is told it stopped where it was
+            Err(e) => {
+                self.sender.event(
+                    "output",
+                    json!({ "category": "stderr", "output": format!("{e}\n") }),
+                );
+                self.stopped("step", None)
+            }
+        };
+        self.sender.event("stopped", stopped);
+    }
+
+    /// The body of a stopped event
+    fn stopped(&self, reason: &str, text: Option<String>) -> Value {
+        let mut body = json!({
+            "reason": reason,
+            "threadId": self.thread(),
+            "allThreadsStopped": true,
+        });
+        if let Some(text) = text {
+            body["description"] = json!(text);
+            body["text"] = json!(text);
+        }
+        body
+    }
+
+    fn exited(&mut self, code: i32) {
+        self.sender.event("exited", json!({ "exitCode": code }));
+        self.sender.event("terminated", Value::Null);
+    }
+}
+
+/// Whether a request stops the program if it comes while it runs
+fn interrupting(request: &Value) -> bool {
+    let command = request["command"].as_str().unwrap_or_default();
+    INTERRUPTING.contains(&command)
+}
+
+/// The breakpoints a `setBreakpoints` or `setFunctionBreakpoints` asks for
+fn requested(args: &Value) -> impl Iterator<Item = &Value> {
+    args["breakpoints"].as_array().into_iter().flatten()
+}
+
+fn condition(bp: &Value) -> Option<String> {
+    bp["condition"]
+        .as_str()
+        .filter(|condition| !condition.is_empty())
+        .map(str::to_string)
+}
+
+/// DAP's id for a thread. Targets that don't know their threads' ids give 0,
+/// which editors take as no thread
+fn thread_id(tid: i32) -> i64 {
+    match tid {
+        0 => 1,
+        tid => tid.into(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn messages_are_read_after_their_headers() {
+        let mut input: &[u8] = b"Content-Length: 13\r\nContent-Type: json\r\n\r\n{\"seq\": 1}   \
+Content-Length: 2\r\n\r\n{}";
+
+        assert_eq!(read_message(&mut input).unwrap(), Some(json!({ "seq": 1 })));
+        assert_eq!(read_message(&mut input).unwrap(), Some(json!({})));
+        assert_eq!(read_message(&mut input).unwrap(), None);
+        assert!(read_message(&mut &b"\r\n{}"[..]).is_err());
+        let huge = b"Content-Length: 18446744073709551615\r\n\r\n{}";
+        assert!(read_message(&mut &huge[..]).is_err());
+    }
+
+    #[test]
+    fn messages_are_numbered_and_framed() {
+        #[derive(Clone, Default)]
+        struct Shared(Arc<Mutex<Vec<u8>>>);
+        impl Write for Shared {
+            fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+                self.0.lock().unwrap().write(buf)
+            }
+            fn flush(&mut self) -> std::io::Result<()> {
+                Ok(())
+            }
+        }
+
+        let output = Shared::default();
+        let sender = Sender::new(output.clone());
+        sender.event("initialized", Value::Null);
+        sender.event("exited", json!({ "exitCode": 0 }));
+
+        let mut written = &output.0.lock().unwrap()[..];
+        let first = read_message(&mut written).unwrap().unwrap();
+        assert_eq!(
+            first,
+            json!({ "seq": 1, "type": "event", "event": "initialized" })
+        );
+        let second = read_message(&mut written).unwrap().unwrap();
+        assert_eq!(second["seq"], 2);
+        assert_eq!(second["body"]["exitCode"], 0);
+    }
+}
@@ -1,151 +1,1545 @@
-use std::collections::HashMap;
-
-use nix::sys::ptrace;
-use nix::sys::wait::waitpid;
-use nix::unistd::Pid;
-use nom::bytes::complete::take_until;
-use nom::character::complete::{digit1, space1};
-use nom::combinator::map_res;
+use std::collections::{HashMap, HashSet};
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use nix::sys::signal::Signal;
+use nom::bytes::complete::{take_until, take_while};
+use nom::character::complete::{char, digit1, one_of, space1};
+use nom::combinator::{all_consuming, map_res, opt};
 use nom::error::ErrorKind;
-use nom::sequence::pair;
+use nom::sequence::{pair, preceded};
 use nom::{Err, IResult};
-use rustyline::error::ReadlineError;
-use rustyline::DefaultEditor;
+use serde_json::{json, Map, Value};
 
 use crate::breakpoint::{Breakpoint, Location};
+use crate::corefile;
+use crate::disasm;
+use crate::dwarf::{Scope, Variable};
+use crate::error::{Error, Result};
+use crate::expr::{self, Context};
+use crate::memory;
+use crate::module::{self, Module};
+use crate::output::{hex, Output};
+use crate::panic;
 use crate::register::{Register, RegisterSelector};
+use crate::remote::Remote;
+use crate::source::{self, SourceLine};
+use crate::target::{NoTarget, StopEvent, Target};
+use crate::types::{Type, TypeKind};
+use crate::unwind::{self, Frame};
 
 pub struct Debugger {
-    pub pid: Pid,
-    pub breakpoints: HashMap<Location, Breakpoint>,
+    target: Box<dyn Target>,
+    /// Breakpoints by number
+    pub breakpoints: HashMap<u32, Breakpoint>,
+    modules: Vec<Module>,
+    /// Paths of mapped files that aren't ELF images or can't be read
+    unloadable: HashSet<PathBuf>,
+    /// The unwound call stack, computed when first needed after each stop
+    frames: Vec<Frame>,
+    /// Index of the frame commands operate in, 0 being the innermost
+    selected_frame: usize,
+    /// Address of the panic handler, when `catch panic` is set
+    panic_handler: Option<u64>,
+    /// Prefixes of source paths to rewrite, from `set substitute-path`
+    substitute_paths: Vec<(PathBuf, PathBuf)>,
+    /// The last source line printed, which `list` continues from
+    listed: Option<SourceLine>,
+    /// Whether to disassemble the next instruction whenever the program stops
+    disassemble_next_line: bool,
+    /// Values printed by `print`, referred to as `$1`, `$2`... in expressions.
+    /// Values that aren't integers, such as structs, are kept as `None`
+    history: Vec<Option<u64>>,
+    /// Variables set with `set $name = <expr>`
+    convenience: HashMap<String, u64>,
+    /// A breakpoint of the debugger's own, at the return address `next` and
+    /// `finish` run to
+    temporary: Option<Breakpoint>,
+    /// Where commands print to
+    out: Output,
+}
+
+/// A frame of the call stack, as `bt` shows it
+#[derive(Debug, Clone)]
+pub struct StackFrame {
+    pub pc: u64,
+    /// The function the frame is in, and the offset of the PC into it
+    pub function: Option<(String, u64)>,
+    /// The source file and line of the PC
+    pub source: Option<(PathBuf, u64)>,
 }
 
+/// Lines of context shown either side of the current line on a stop
+const CONTEXT_LINES: u64 = 2;
+
+/// Lines shown by each `list`
+const LIST_LINES: u64 = 10;
+
+/// Instructions shown by `disassemble` when not given a count or function
+const DISASSEMBLE_COUNT: usize = 10;
+
+/// Printed when a register command is malformed
+const REGISTER_USAGE: &str = "Usage: reg read <register> or reg write <register> <value>";
+
+/// Bytes of memory shown on each line by `x`
+const EXAMINE_LINE_LEN: usize = 16;
+
 enum Command {
     Continue,
     Break,
     Register,
+    Backtrace,
+    Frame,
+    Thread,
+    Up,
+    Down,
+    Info,
+    Print,
+    Catch,
+    List,
+    Set,
+    Disassemble,
+    StepInstruction,
+    Next,
+    Step,
+    Finish,
+    Examine,
+    Gcore,
+    Target,
     Unknown,
 }
 
 enum RegisterOp {
     Read { reg: Register },
-    Write { reg: Register, value: isize },
+    Write { reg: Register, value: String },
+    InvalidRegister(String),
+    Unknown,
+}
+
+enum InfoOp {
+    Locals,
+    Args,
+    Unknown,
+}
+
+enum CatchOp {
+    Panic,
+    Unknown,
+}
+
+enum TargetOp {
+    /// Connects to a gdbserver-compatible stub at an address
+    Remote(String),
+    Unknown,
+}
+
+enum ListOp {
+    /// Continue after the last lines listed, or around the selected frame
+    Continue,
+    Line(u64),
+    FileLine(String, u64),
+    Function(String),
+}
+
+enum SetOp {
+    SubstitutePath {
+        from: PathBuf,
+        to: PathBuf,
+    },
+    DisassembleNextLine(bool),
+    /// Sets a convenience variable to the value of an expression
+    Variable {
+        name: String,
+        value: String,
+    },
     Unknown,
 }
 
 enum BreakpointOp {
     List,
-    Set(Location),
-    Unset(u8),
+    /// Sets a breakpoint, which only stops when its condition is non-zero
+    Set(Location, Option<String>),
+    /// Removes a breakpoint by number, which may be too large to exist
+    Unset(u64),
     Unknown,
 }
 
 impl Debugger {
-    pub fn new(pid: Pid) -> Debugger {
+    pub fn new(target: impl Target + 'static) -> Debugger {
         Debugger {
-            pid,
+            target: Box::new(target),
             breakpoints: HashMap::new(),
+            modules: Vec::new(),
+            unloadable: HashSet::new(),
+            frames: Vec::new(),
+            selected_frame: 0,
+            panic_handler: None,
+            substitute_paths: Vec::new(),
+            listed: None,
+            disassemble_next_line: true,
+            history: Vec::new(),
+            convenience: HashMap::new(),
+            temporary: None,
+            out: Output::default(),
+        }
+    }
+
+    pub fn target(&self) -> &dyn Target {
+        self.target.as_ref()
+    }
+
+    /// Runs a command, such as `b set main`, printing its output
+    pub fn execute(&mut self, line: &str) -> Result<()> {
+        if line.trim().is_empty() {
+            return Ok(());
+        }
+        let (args, cmd) = parse_cmd(line).map_err(usage("Unknown command"))?;
+
+        match cmd {
+            Command::Continue => self.continue_execution()?,
+            Command::Break => {
+                let (_, op) = parse_bkpt_cmd(args).map_err(usage("Unknown breakpoint command"))?;
+                match op {
+                    BreakpointOp::List => self.list_breakpoints(),
+                    BreakpointOp::Set(loc, condition) => {
+                        let bp = self.set_breakpoint(loc, condition)?;
+                        let (text, value) = set_message(bp);
+                        self.out.line(text, "breakpoint", value);
+                    }
+                    BreakpointOp::Unset(num) => match u32::try_from(num) {
+                        Ok(num) => self.unset_breakpoint(num)?,
+                        Err(_) => {
+                            return Err(Error::NotFound(format!("No breakpoint number {num}")))
+                        }
+                    },
+                    BreakpointOp::Unknown => return Err(unknown("breakpoint command")),
+                }
+            }
+            Command::Register => {
+                let (_, op) = parse_reg_cmd(args).map_err(usage(REGISTER_USAGE))?;
+                match op {
+                    RegisterOp::Read { reg } => {
+                        let value = self.read_register(&reg)?;
+                        self.out.line(
+                            format!("{} {value:#x}", reg.name()),
+                            "register",
+                            json!({ "name": reg.name(), "value": format!("{value:#x}") }),
+                        );
+                    }
+                    RegisterOp::Write { reg, value } => {
+                        if self.selected_frame != 0 {
+                            return Err(Error::State(
+                                "Registers can only be written in the innermost frame".into(),
+                            ));
+                        }
+                        let value = self.evaluate(&value)?;
+                        self.frames.clear();
+                        reg.write(self.target.as_mut(), value as u128)?;
+                    }
+                    RegisterOp::InvalidRegister(name) => {
+                        return Err(Error::NotFound(format!("Invalid register {name}")))
+                    }
+                    RegisterOp::Unknown => return Err(Error::Parse(REGISTER_USAGE.into())),
+                }
+            }
+            Command::Backtrace => self.backtrace()?,
+            Command::Frame => {
+                let (_, num) = parse_frame_cmd(args).map_err(usage("Usage: frame [number]"))?;
+                self.select_frame(num.unwrap_or(self.selected_frame))?;
+            }
+            Command::Thread => {
+                let (_, tid) = parse_frame_cmd(args).map_err(usage("Usage: thread [id]"))?;
+                if let Some(tid) = tid {
+                    self.select_thread(tid)?;
+                }
+                let current = self.current_thread()?;
+                self.out.line(
+                    format!("Current thread is {current}"),
+                    "thread",
+                    json!(current),
+                );
+                if tid.is_some() {
+                    self.show_stop()?;
+                }
+            }
+            Command::Up => {
+                let (_, count) = parse_frame_cmd(args).map_err(usage("Usage: up [count]"))?;
+                let Some(num) = self.selected_frame.checked_add(count.unwrap_or(1)) else {
+                    return Err(Error::State("Already at the outermost frame".into()));
+                };
+                self.select_frame(num)?;
+            }
+            Command::Down => {
+                let (_, count) = parse_frame_cmd(args).map_err(usage("Usage: down [count]"))?;
+                let Some(num) = self.selected_frame.checked_sub(count.unwrap_or(1)) else {
+                    return Err(Error::State("Already at the innermost frame".into()));
+                };
+                self.select_frame(num)?;
+            }
+            Command::Info => {
+                let (_, op) = parse_info_cmd(args).map_err(usage("Unknown info command"))?;
+                match op {
+                    InfoOp::Locals => self.print_variables(false)?,
+                    InfoOp::Args => self.print_variables(true)?,
+                    InfoOp::Unknown => return Err(unknown("info command")),
+                }
+            }
+            Command::Print => self.print(args.trim())?,
+            Command::Catch => {
+                let (_, op) = parse_catch_cmd(args).map_err(usage("Unknown catch command"))?;
+                match op {
+                    CatchOp::Panic => self.catch_panic()?,
+                    CatchOp::Unknown => return Err(unknown("catch command")),
+                }
+            }
+            Command::List => {
+                let (_, op) =
+                    parse_list_cmd(args).map_err(usage("Usage: list [line|file:line|function]"))?;
+                self.list(op)?;
+            }
+            Command::Set => {
+                let (_, op) = parse_set_cmd(args).map_err(usage("Unknown setting"))?;
+                match op {
+                    SetOp::SubstitutePath { from, to } => self.substitute_paths.push((from, to)),
+                    SetOp::DisassembleNextLine(on) => self.disassemble_next_line = on,
+                    SetOp::Variable { name, value } => self.set_variable(name, &value)?,
+                    SetOp::Unknown => return Err(unknown("setting")),
+                }
+            }
+            Command::Disassemble => {
+                let (_, (loc, count)) = parse_disas_cmd(args)
+                    .map_err(usage("Usage: disassemble [addr|function] [count]"))?;
+                self.disassemble(loc, count)?;
+            }
+            Command::StepInstruction => self.step_instruction()?,
+            Command::Next => {
+                let stop = self.step_line(true)?;
+                self.report(stop)?;
+            }
+            Command::Step => {
+                let stop = self.step_line(false)?;
+                self.report(stop)?;
+            }
+            Command::Finish => {
+                let stop = self.finish()?;
+                self.report(stop)?;
+            }
+            Command::Examine => {
+                let (_, (count, size, addr)) = parse_examine_cmd(args)
+                    .map_err(usage("Usage: x[/<count><b|h|w|g>] <address>"))?;
+                self.examine(addr, count, size)?;
+            }
+            Command::Gcore => {
+                let path = self.save_core(Some(args.trim()).filter(|path| !path.is_empty()))?;
+                let text = format!("Saved corefile {}", path.display());
+                self.out.line(text, "core", json!(path));
+            }
+            Command::Target => {
+                let (_, op) =
+                    parse_target_cmd(args).map_err(usage("Usage: target remote <host:port>"))?;
+                match op {
+                    TargetOp::Remote(addr) => {
+                        self.connect(&addr)?;
+                        let text = format!("Remote debugging using {addr}");
+                        self.out.line(text, "remote", json!(addr));
+                        self.show_stop()?;
+                    }
+                    TargetOp::Unknown => return Err(unknown("target")),
+                }
+            }
+            Command::Unknown => return Err(unknown("command")),
         }
+        Ok(())
     }
 
-    pub fn run(&mut self) {
-        // wait for process to start. we get a signal because of the ptrace.
-        // once we get that, we can proceed
-        let _ = waitpid(self.pid, None);
+    /// Runs a command like `execute`, giving its output as one JSON object.
+    /// Its `type` is `result`, `stop` if the command ran the program, or
+    /// `error` with a `message`
+    pub fn execute_json(&mut self, line: &str) -> Value {
+        self.out.start_json();
+        let result = self.execute(line);
+        let mut object = Map::new();
+        object.insert("type".into(), json!("result"));
+        object.insert("command".into(), json!(line.trim()));
+        object.extend(self.out.finish_json());
+        if let Err(e) = result {
+            object.insert("type".into(), json!("error"));
+            object.insert("message".into(), json!(e.to_string()));
+        }
+        Value::Object(object)
+    }
 
-        let mut rl = DefaultEditor::new().unwrap();
+    /// Runs the program until it hits a breakpoint whose condition holds, or
+    /// otherwise stops
+    pub fn continue_(&mut self) -> Result<StopEvent> {
         loop {
-            let readline = rl.readline(">>> ");
-            match readline {
-                Ok(line) => self.handle_input(line),
-                Err(ReadlineError::Interrupted) => {
-                    println!("CTRL-C");
-                    break;
+            // Any previously unwound frames are stale once the process runs
+            self.frames.clear();
+            self.selected_frame = 0;
+
+            if let Some(stop @ (StopEvent::Exited(_) | StopEvent::Killed(_))) =
+                self.step_over_breakpoint()?
+            {
+                return Ok(stop);
+            }
+            self.target.resume()?;
+            let stop = self.target.wait()?;
+            let StopEvent::Signal(Signal::SIGTRAP) = stop else {
+                return Ok(stop);
+            };
+
+            // The trap fires after the breakpoint's int3 executes, so move
+            // back onto the breakpoint's address, unless the target has
+            let mut regs = self.target.regs()?;
+            let decr = self.target.decr_pc_after_break();
+            let pc = regs.rip - decr;
+            let temporary = self.temporary.as_ref().is_some_and(|bp| bp.addr == pc);
+            if !temporary && !self.breakpoints.values().any(|bp| bp.addr == pc) {
+                return Ok(stop);
+            }
+            if decr != 0 {
+                regs.rip = pc;
+                self.target.set_regs(regs)?;
+            }
+
+            if let Some(num) = self.hit_breakpoint(pc) {
+                return Ok(StopEvent::Breakpoint { num, addr: pc });
+            }
+            if temporary {
+                return Ok(StopEvent::Step { pc });
+            }
+        }
+    }
+
+    /// The number of the breakpoint at `pc`, if there is one and its
+    /// condition holds
+    fn hit_breakpoint(&mut self, pc: u64) -> Option<u32> {
+        let bp = self.breakpoints.values().find(|bp| bp.addr == pc)?;
+        let (num, condition) = (bp.num, bp.condition.clone());
+        if let Some(condition) = condition {
+            match self.evaluate(&condition) {
+                Ok(0) => return None,
+                Ok(_) => {}
+                Err(e) => self
+                    .out
+                    .note(format!("Error in condition of breakpoint {num}: {e}")),
+            }
+        }
+        Some(num)
+    }
+
+    /// Executes a single instruction
+    pub fn step(&mut self) -> Result<StopEvent> {
+        self.frames.clear();
+        self.selected_frame = 0;
+
+        match self.step_over_breakpoint()? {
+            Some(stop) => Ok(stop),
+            None => single_step(self.target.as_mut()),
+        }
+    }
+
+    /// Runs the program to the next source line, stepping into calls unless
+    /// `over` is set. Calls into code without line information, such as the
+    /// PLT or libc, are always stepped over
+    pub fn step_line(&mut self, over: bool) -> Result<StopEvent> {
+        self.unwind()?;
+        let mut line = self.line_at(0);
+        let mut cfa = self.frame_cfa()?;
+        if line.is_none() {
+            // Without a line to step from, run back to code that has one
+            return self.finish_frame(0);
+        }
+        // Instructions stepped in the rest of the line's code are still on it,
+        // so the stack is only unwound once the program leaves it
+        let mut range = self.line_range()?;
+
+        loop {
+            let stop = self.step()?;
+            let StopEvent::Step { pc } = stop else {
+                return Ok(stop);
+            };
+            // Like continuing, stepping onto a breakpoint stops there
+            if let Some(num) = self.hit_breakpoint(pc) {
+                return Ok(StopEvent::Breakpoint { num, addr: pc });
+            }
+            if range.contains(&pc) {
+                continue;
+            }
+            self.unwind()?;
+            let frame_cfa = self.frame_cfa()?;
+            let here = self.line_at(0);
+
+            if frame_cfa < cfa {
+                // Stepped into a call, which `next` and code without lines
+                // run to the end of
+                if !over && here.is_some() {
+                    return Ok(stop);
+                }
+                match self.finish_frame(0)? {
+                    StopEvent::Step { .. } => continue,
+                    stop => return Ok(stop),
                 }
-                Err(ReadlineError::Eof) => {
-                    println!("CTRL-D");
-                    break;
+            }
+            if frame_cfa > cfa {
+                // Returned into the middle of the caller's line, which is
+                // finished first
+                if here.is_none() {
+                    return Ok(stop);
                 }
-                Err(e) => println!("error: {:?}", e),
+                (line, cfa) = (here, frame_cfa);
+                range = self.line_range()?;
+                continue;
+            }
+            let moved = match (&here, &line) {
+                (Some(here), Some(line)) => here.line != line.line || here.path != line.path,
+                _ => false,
+            };
+            if moved {
+                return Ok(StopEvent::Step { pc });
             }
+            range = self.line_range()?;
         }
     }
 
-    pub fn handle_input(&mut self, line: String) {
-        let (args, cmd) = parse_cmd(&line).unwrap();
+    /// The addresses from the PC on that are still on its source line, which
+    /// are none if it has no line
+    fn line_range(&self) -> Result<Range<u64>> {
+        let pc = self.target.pc()?;
+        Ok(source::line_range_for(&self.modules, pc).map_or(pc..pc, |(_, range)| range))
+    }
 
-        match cmd {
-            Command::Continue => {
-                let _ = ptrace::cont(self.pid, None);
-                // wait until signaled
-                let _ = waitpid(self.pid, None);
+    /// Runs the program until the selected frame returns
+    pub fn finish(&mut self) -> Result<StopEvent> {
+        self.unwind()?;
+        self.finish_frame(self.selected_frame)
+    }
+
+    /// Runs until a frame returns to its caller, stopping early at a
+    /// breakpoint or signal
+    fn finish_frame(&mut self, num: usize) -> Result<StopEvent> {
+        let (Some(caller), Some(cfa)) = (
+            self.frames.get(num + 1),
+            self.frames.get(num).and_then(|frame| frame.cfa),
+        ) else {
+            return Err(Error::State(
+                "\"finish\" not meaningful in the outermost frame".into(),
+            ));
+        };
+        let addr = caller.pc;
+
+        // A breakpoint of the user's at the return address stops it anyway
+        if !self.breakpoints.values().any(|bp| bp.addr == addr) {
+            let mut bp = Breakpoint::new(0, Location::Address(addr as isize), addr);
+            bp.enable(self.target.as_mut())?;
+            self.temporary = Some(bp);
+        }
+        let stop = loop {
+            match self.continue_() {
+                // A recursive call returning to the same address, deeper in
+                // the stack than the frame
+                Ok(StopEvent::Step { .. }) if self.target.regs()?.rsp < cfa => continue,
+                stop => break stop,
             }
-            Command::Break => {
-                let (_, op) = parse_bkpt_cmd(args).unwrap();
-                match op {
-                    BreakpointOp::List => println!("List breakpoints"),
-                    BreakpointOp::Set(_) => println!("Set breakpoint"),
-                    BreakpointOp::Unset(num) => println!("Unset breakpoint: {num}"),
-                    BreakpointOp::Unknown => println!("Unknown breakpoint command"),
+        };
+        if let Some(mut bp) = self.temporary.take() {
+            if !matches!(stop, Ok(StopEvent::Exited(_) | StopEvent::Killed(_))) {
+                bp.disable(self.target.as_mut())?;
+            }
+        }
+        stop
+    }
+
+    /// The canonical frame address of the innermost frame, which is lower in
+    /// deeper frames
+    fn frame_cfa(&mut self) -> Result<u64> {
+        match self.frames.first().and_then(|frame| frame.cfa) {
+            Some(cfa) => Ok(cfa),
+            None => Ok(self.target.regs()?.rsp),
+        }
+    }
+
+    /// The source line of an unwound frame
+    fn line_at(&self, num: usize) -> Option<SourceLine> {
+        let frame = self.frames.get(num)?;
+        // Callers are looked up by their call instruction
+        let pc = if num == 0 { frame.pc } else { frame.pc - 1 };
+        source::line_for(&self.modules, pc)
+    }
+
+    fn continue_execution(&mut self) -> Result<()> {
+        let stop = self.continue_()?;
+        self.report(stop)
+    }
+
+    fn step_instruction(&mut self) -> Result<()> {
+        let stop = self.step()?;
+        self.report(stop)
+    }
+
+    /// Prints why the program stopped, and where if it's still running. In
+    /// JSON, the command's object is a stop event
+    fn report(&mut self, stop: StopEvent) -> Result<()> {
+        self.out.field("type", json!("stop"));
+        match stop {
+            StopEvent::Breakpoint { num, addr } => {
+                self.out.field("reason", json!("breakpoint"));
+                self.out.field("address", hex(addr));
+                if self.panic_handler == Some(addr) {
+                    self.print_panic()?;
+                } else {
+                    let text = format!("Hit breakpoint {num} at {addr:#x}");
+                    self.out.line(text, "breakpoint", json!(num));
                 }
-                // let loc = {
-                //     let a = args.next().unwrap().strip_prefix("0x").unwrap();
-                //     let addr = isize::from_str_radix(a, 16).unwrap();
-                //     Location::Address(addr)
-                // };
+            }
+            StopEvent::Step { .. } => self.out.field("reason", json!("step")),
+            StopEvent::Signal(signal) => {
+                self.out.field("reason", json!("signal"));
+                let text = format!("Program received {signal:?}");
+                self.out.line(text, "signal", json!(signal.as_str()));
+            }
+            StopEvent::Exited(code) => {
+                self.out.field("reason", json!("exited"));
+                let text = format!("Program exited with code {code}");
+                self.out.line(text, "code", json!(code));
+                return Ok(());
+            }
+            StopEvent::Killed(signal) => {
+                self.out.field("reason", json!("killed"));
+                let text = format!("Program terminated by {signal:?}");
+                self.out.line(text, "signal", json!(signal.as_str()));
+                return Ok(());
+            }
+        }
+        self.show_stop()
+    }
+
+    /// Shows where the program stopped: the source line, and the next
+    /// instruction if `disassemble-next-line` is on
+    fn show_stop(&mut self) -> Result<()> {
+        self.unwind()?;
+        if !self.frames.is_empty() {
+            let (_, frame) = self.frame_item(0);
+            self.out.field("frame", frame);
+        }
+        self.show_source();
+        if !self.disassemble_next_line {
+            return Ok(());
+        }
 
-                // self.set_breakpoint(loc);
+        let pc = self.target.regs()?.rip;
+        let code = self.read_memory(pc, disasm::MAX_INSTRUCTION_LEN);
+        let Some(insn) = disasm::decode(&code, pc, 1).pop() else {
+            return Err(Error::Eval(format!("Cannot read memory at {pc:#x}")));
+        };
+        let (text, value) = self.instruction(&insn, Some(pc));
+        self.out.line(text, "instruction", value);
+        Ok(())
+    }
+
+    /// Prints the source around the selected frame's line
+    fn show_source(&mut self) {
+        let Some(loc) = self.frame_line() else {
+            return;
+        };
+        let path = source::substitute(&loc.path, &self.substitute_paths);
+        let first = loc.line.saturating_sub(CONTEXT_LINES).max(1);
+        let last = loc.line + CONTEXT_LINES;
+        // `list` then continues from here
+        self.listed = self
+            .print_source(&path, first, last, Some(loc.line))
+            .map(|line| SourceLine {
+                path: loc.path,
+                line,
+            });
+    }
+
+    /// Prints lines `first` to `last` of a source file, marking the `current`
+    /// line. Returns the last line printed, or `None` if there were none
+    fn print_source(
+        &mut self,
+        path: &Path,
+        first: u64,
+        last: u64,
+        current: Option<u64>,
+    ) -> Option<u64> {
+        let lines = match source::read_lines(path, first, last) {
+            Ok(lines) => lines,
+            Err(e) => {
+                self.out.note(e);
+                return None;
             }
-            Command::Register => {
-                let (_, op) = parse_reg_cmd(args).unwrap();
-                match op {
-                    RegisterOp::Read { reg } => {
-                        let value = reg.read(self.pid);
-                        println!("{value:0x}");
-                    }
-                    RegisterOp::Write { reg, value } => reg.write(self.pid, value as u64),
-                    RegisterOp::Unknown => {
-                        println!("Unknown register command")
+        };
+        let printed = lines.last().map(|(num, _)| *num);
+        self.out.list(
+            "source",
+            lines.into_iter().map(|(num, text)| {
+                let marker = if Some(num) == current { "=>" } else { "  " };
+                (
+                    format!("{marker} {num:<4} {text}"),
+                    json!({ "line": num, "text": text, "current": Some(num) == current }),
+                )
+            }),
+        );
+        printed
+    }
+
+    fn list(&mut self, op: ListOp) -> Result<()> {
+        module::refresh(
+            self.target.as_ref(),
+            &mut self.modules,
+            &mut self.unloadable,
+        );
+
+        // The line to center the listing on, if not continuing from the last
+        let center = match op {
+            ListOp::Continue => match &self.listed {
+                Some(listed) => {
+                    let path = listed.path.clone();
+                    let first = listed.line + 1;
+                    self.print_lines(path, first);
+                    return Ok(());
+                }
+                None => self.frame_line(),
+            },
+            ListOp::Line(line) => match self.listed.clone().or_else(|| self.frame_line()) {
+                Some(listed) => Some(SourceLine {
+                    path: listed.path,
+                    line,
+                }),
+                None => return Err(Error::State("No source file selected".into())),
+            },
+            ListOp::FileLine(file, line) => match source::find_file(&self.modules, &file) {
+                Some(path) => Some(SourceLine { path, line }),
+                None => return Err(Error::NotFound(format!("No source file named {file}"))),
+            },
+            ListOp::Function(name) => {
+                let addr = self.resolve(&Location::Function(name.clone()))?;
+                match source::line_for(&self.modules, addr) {
+                    Some(line) => Some(line),
+                    None => {
+                        return Err(Error::NotFound(format!(
+                            "No source line for function {name}"
+                        )))
                     }
                 }
             }
-            Command::Unknown => println!("Unknown command"),
+        };
+
+        let Some(center) = center else {
+            return Err(Error::State("No source line for the selected frame".into()));
+        };
+        let first = center.line.saturating_sub(LIST_LINES / 2).max(1);
+        self.print_lines(center.path, first);
+        Ok(())
+    }
+
+    /// Lists lines starting from `first`, marking the selected frame's line
+    /// and remembering where the listing ended
+    fn print_lines(&mut self, path: PathBuf, first: u64) {
+        let last = first + LIST_LINES - 1;
+        let current = self
+            .frame_line()
+            .filter(|loc| loc.path == path)
+            .map(|loc| loc.line);
+        let substituted = source::substitute(&path, &self.substitute_paths);
+        if let Some(line) = self.print_source(&substituted, first, last, current) {
+            self.listed = Some(SourceLine { path, line });
+        }
+    }
+
+    /// The source line of the selected frame
+    fn frame_line(&mut self) -> Option<SourceLine> {
+        self.unwind().ok()?;
+        self.line_at(self.selected_frame)
+    }
+
+    /// Executes the original instruction under a breakpoint at the current
+    /// address, so continuing doesn't immediately trap again. Gives how the
+    /// step stopped, if there was a breakpoint to step over
+    fn step_over_breakpoint(&mut self) -> Result<Option<StopEvent>> {
+        let pc = self.target.pc()?;
+        let Some(bp) = self
+            .breakpoints
+            .values_mut()
+            .chain(&mut self.temporary)
+            .find(|bp| bp.addr == pc)
+        else {
+            return Ok(None);
+        };
+        if !bp.enabled {
+            return Ok(None);
+        }
+
+        bp.disable(self.target.as_mut())?;
+        let stop = single_step(self.target.as_mut())?;
+        if !matches!(stop, StopEvent::Exited(_) | StopEvent::Killed(_)) {
+            bp.enable(self.target.as_mut())?;
+        }
+        Ok(Some(stop))
+    }
+
+    fn backtrace(&mut self) -> Result<()> {
+        self.unwind()?;
+        let frames: Vec<(String, Value)> = (0..self.frames.len())
+            .map(|num| self.frame_item(num))
+            .collect();
+        self.out.list("frames", frames);
+        Ok(())
+    }
+
+    /// Follows another thread, whose innermost frame is then selected
+    fn select_thread(&mut self, tid: usize) -> Result<()> {
+        let tid = i32::try_from(tid).map_err(|_| Error::NotFound(format!("No thread {tid}")))?;
+        self.target.select_thread(tid)?;
+        self.frames.clear();
+        self.selected_frame = 0;
+        Ok(())
+    }
+
+    /// The id of the thread the debugger follows
+    fn current_thread(&self) -> Result<i32> {
+        let threads = self.target.threads()?;
+        let thread = threads
+            .first()
+            .ok_or_else(|| Error::State("The program has no threads".into()))?;
+        Ok(thread.tid)
+    }
+
+    fn select_frame(&mut self, num: usize) -> Result<()> {
+        self.unwind()?;
+        if num >= self.frames.len() {
+            return Err(Error::NotFound(format!("No frame at level {num}")));
+        }
+        self.selected_frame = num;
+        let (text, value) = self.frame_item(num);
+        self.out.line(text, "frame", value);
+        self.show_source();
+        Ok(())
+    }
+
+    /// The call stack, innermost frame first
+    pub fn stack(&mut self) -> Result<Vec<StackFrame>> {
+        self.unwind()?;
+        Ok((0..self.frames.len())
+            .map(|num| StackFrame {
+                pc: self.frames[num].pc,
+                function: self
+                    .frame_symbol(num)
+                    .map(|(name, offset)| (name.to_string(), offset)),
+                source: self.line_at(num).map(|loc| {
+                    let path = source::substitute(&loc.path, &self.substitute_paths);
+                    (path, loc.line)
+                }),
+            })
+            .collect())
+    }
+
+    /// The function a frame is in, and how far into it its PC is
+    fn frame_symbol(&self, num: usize) -> Option<(&str, u64)> {
+        let frame = &self.frames[num];
+        // Callers return past the call, which may be the end of a function
+        // that never returns, so look up the call instruction instead
+        let addr = if num == 0 { frame.pc } else { frame.pc - 1 };
+        module::find(&self.modules, addr)
+            .and_then(|module| module.symbol_for(addr))
+            .map(|(name, offset)| (name, offset + frame.pc - addr))
+    }
+
+    /// A frame as `bt` prints it, and as JSON with its source line
+    fn frame_item(&self, num: usize) -> (String, Value) {
+        let pc = self.frames[num].pc;
+        let function = match self.frame_symbol(num) {
+            Some((name, 0)) => Some(name.to_string()),
+            Some((name, offset)) => Some(format!("{name}+{offset:#x}")),
+            None => None,
+        };
+        let text = format!(
+            "#{num:<3} {pc:#018x} in {}",
+            function.as_deref().unwrap_or("??")
+        );
+
+        let mut value = json!({ "level": num, "pc": hex(pc), "function": function });
+        if let Some(loc) = self.line_at(num) {
+            let path = source::substitute(&loc.path, &self.substitute_paths);
+            value["file"] = json!(path);
+            value["line"] = json!(loc.line);
+        }
+        (text, value)
+    }
+
+    /// Unwinds the call stack, unless it has been since the process last ran
+    fn unwind(&mut self) -> Result<()> {
+        if !self.frames.is_empty() {
+            return Ok(());
+        }
+
+        // Shared libraries are mapped in after the process starts, so pick up
+        // any new ones before unwinding through them
+        module::refresh(
+            self.target.as_ref(),
+            &mut self.modules,
+            &mut self.unloadable,
+        );
+
+        let regs = self.target.regs()?;
+        self.frames = unwind::backtrace(self.target.as_ref(), &self.modules, regs);
+        Ok(())
+    }
+
+    /// Reads a register in the selected frame. Outer frames only have the
+    /// registers calls preserve, and ones their callees saved
+    pub fn read_register(&mut self, reg: &Register) -> Result<u128> {
+        if self.selected_frame == 0 {
+            return reg.read(self.target.as_ref());
+        }
+        self.unwind()?;
+        let frame = &self.frames[self.selected_frame];
+        match reg.read_from(&frame.regs) {
+            Some(value) if frame.has(reg) => Ok(value.into()),
+            _ => Err(Error::Eval(format!(
+                "Value of {} is not available in frame {}",
+                reg.name(),
+                self.selected_frame
+            ))),
+        }
+    }
+
+    /// The debug information for the selected frame
+    fn scope(&self) -> Result<Scope<'_>> {
+        Scope::new(
+            self.target.as_ref(),
+            &self.modules,
+            &self.frames,
+            self.selected_frame,
+        )
+        .ok_or_else(|| Error::State("No debug information for the selected frame".into()))
+    }
+
+    /// The arguments or the local variables of a frame, by name, formatted
+    /// using their types
+    pub fn variables(&mut self, frame: usize, args: bool) -> Result<Vec<(String, String)>> {
+        self.unwind()?;
+        if frame >= self.frames.len() {
+            return Err(Error::NotFound(format!("No frame at level {frame}")));
+        }
+        let scope = Scope::new(self.target.as_ref(), &self.modules, &self.frames, frame)
+            .ok_or_else(|| Error::State(format!("No debug information for frame {frame}")))?;
+
+        // Locals in the innermost scope come first
+        let vars: Vec<&Variable> = match args {
+            true => scope.variables.iter().filter(|var| var.is_arg).collect(),
+            false => scope
+                .variables
+                .iter()
+                .rev()
+                .filter(|var| !var.is_arg)
+                .collect(),
+        };
+        Ok(vars
+            .into_iter()
+            .map(|var| (var.name.clone(), format_variable(&scope, var)))
+            .collect())
+    }
+
+    /// Prints the arguments or the local variables of the selected frame
+    fn print_variables(&mut self, args: bool) -> Result<()> {
+        let vars = self.variables(self.selected_frame, args)?;
+        if vars.is_empty() {
+            self.out
+                .text(format!("No {}", if args { "arguments" } else { "locals" }));
+        }
+        self.out.list(
+            "variables",
+            vars.into_iter().map(|(name, value)| {
+                let text = format!("{name} = {value}");
+                (text, json!({ "name": name, "value": value }))
+            }),
+        );
+        Ok(())
+    }
+
+    /// Prints a variable formatted using its type, or else the value of an
+    /// expression, and records it in the value history
+    fn print(&mut self, expr: &str) -> Result<()> {
+        self.unwind()?;
+        let scope = self.scope().ok();
+
+        // Each leading `*` follows a pointer
+        let name = expr.trim_start_matches('*');
+        let derefs = expr.len() - name.len();
+        let Some((scope, var)) = scope
+            .as_ref()
+            .and_then(|scope| Some((scope, scope.find(name)?)))
+        else {
+            let value = self.evaluate(expr)?;
+            self.history.push(Some(value));
+            let num = self.history.len();
+            let text = format!("${num} = {} ({value:#x})", value as i64);
+            self.out
+                .line(text, "value", json!((value as i64).to_string()));
+            self.out.field("history", json!(num));
+            return Ok(());
+        };
+        let (Some(mut ty), Some(mut bytes)) = (scope.type_of(var), scope.read(var)) else {
+            self.out
+                .line("<optimized out>", "optimized_out", json!(true));
+            return Ok(());
+        };
+
+        let types = scope.types();
+        for _ in 0..derefs {
+            let Some((pointee, value)) = types.deref(&ty, &bytes) else {
+                return Err(Error::Eval(format!(
+                    "Cannot dereference a value of type {}",
+                    ty.name
+                )));
+            };
+            (ty, bytes) = (pointee, value);
+        }
+        let formatted = types.format(&ty, &bytes);
+        self.history.push(integer(scope, &ty, &bytes));
+        let num = self.history.len();
+        self.out
+            .line(format!("${num} = {formatted}"), "value", json!(formatted));
+        self.out.field("history", json!(num));
+        Ok(())
+    }
+
+    /// Sets a convenience variable, which can't share a register's name
+    fn set_variable(&mut self, name: String, expr: &str) -> Result<()> {
+        if Register::from_selector(RegisterSelector::Name(&name)).is_some() {
+            return Err(Error::Parse(format!(
+                "${name} is a register, use reg write to change it"
+            )));
+        }
+        let value = self.evaluate(expr)?;
+        self.convenience.insert(name, value);
+        Ok(())
+    }
+
+    /// Stops when a Rust panic starts, before the stack is unwound
+    fn catch_panic(&mut self) -> Result<()> {
+        module::refresh(
+            self.target.as_ref(),
+            &mut self.modules,
+            &mut self.unloadable,
+        );
+        let Some((name, addr)) = panic::find_handler(&self.modules) else {
+            return Err(Error::NotFound(
+                "No Rust panic handler found in the program".into(),
+            ));
+        };
+        if self.panic_handler != Some(addr) {
+            let bp = self.set_breakpoint(Location::Function(name.to_string()), None)?;
+            let (text, value) = set_message(bp);
+            self.out.line(text, "breakpoint", value);
+            self.panic_handler = Some(addr);
+        }
+        Ok(())
+    }
+
+    fn print_panic(&mut self) -> Result<()> {
+        let regs = self.target.regs()?;
+        match panic::describe(self.target.as_ref(), &self.modules, &regs) {
+            Some(panic) => self
+                .out
+                .line(format!("Caught panic: {panic}"), "panic", json!(panic)),
+            None => self.out.line("Caught panic", "panic", Value::Null),
+        }
+        Ok(())
+    }
+
+    /// Sets a breakpoint, which only stops the program when its condition, if
+    /// given, is non-zero
+    pub fn set_breakpoint(
+        &mut self,
+        loc: Location,
+        condition: Option<String>,
+    ) -> Result<&Breakpoint> {
+        let addr = self.resolve(&loc)?;
+        if let Some(condition) = &condition {
+            expr::parse(condition)?;
+        }
+        // An expression such as `$rip` can give a different address later,
+        // and a bare line a different file once another one is listed
+        let loc = match loc {
+            Location::Expression(_) => Location::Address(addr as isize),
+            Location::Line(line) => match self.listed.clone().or_else(|| self.frame_line()) {
+                Some(listed) => Location::FileLine(listed.path.display().to_string(), line),
+                None => Location::Address(addr as isize),
+            },
+            loc => loc,
+        };
+        // A second breakpoint would save the first one's int3 as the
+        // original instruction
+        if let Some(bp) = self.breakpoints.values().find(|bp| bp.addr == addr) {
+            return Err(Error::State(format!(
+                "Breakpoint {} is already set at {addr:#x}",
+                bp.num
+            )));
+        }
+
+        let num = self
+            .breakpoints
+            .values()
+            .map(|bp| bp.num)
+            .max()
+            .unwrap_or(0)
+            .checked_add(1)
+            .ok_or_else(|| Error::State("No breakpoint numbers left".into()))?;
+        let mut bp = Breakpoint::new(num, loc, addr);
+        bp.condition = condition;
+        bp.enable(self.target.as_mut())?;
+        Ok(self.breakpoints.entry(num).or_insert(bp))
+    }
+
+    /// Removes a breakpoint, restoring the instruction it replaced
+    pub fn unset_breakpoint(&mut self, num: u32) -> Result<()> {
+        match self.breakpoints.remove(&num) {
+            Some(mut bp) => bp.disable(self.target.as_mut()),
+            None => Err(Error::NotFound(format!("No breakpoint number {num}"))),
+        }
+    }
+
+    /// Removes every breakpoint, including the one `finish` sets, and stops
+    /// debugging the program, leaving it running
+    pub fn detach(&mut self) -> Result<()> {
+        for bp in self.breakpoints.values_mut().chain(&mut self.temporary) {
+            if bp.enabled {
+                bp.disable(self.target.as_mut())?;
+            }
+        }
+        self.temporary = None;
+        self.target.detach()?;
+        self.forget();
+        Ok(())
+    }
+
+    /// Stops debugging, detaching from a program that was attached to and
+    /// killing one the debugger started
+    pub fn quit(&mut self) -> Result<()> {
+        if self.target.attached() {
+            return self.detach();
+        }
+        self.forget();
+        Ok(())
+    }
+
+    /// Drops the target, and the breakpoints and symbols of its program
+    fn forget(&mut self) {
+        self.target = Box::new(NoTarget);
+        self.breakpoints.clear();
+        self.modules.clear();
+        self.unloadable.clear();
+        self.frames.clear();
+        self.selected_frame = 0;
+    }
+
+    /// Debugs the program a gdbserver-compatible stub at `addr` is serving,
+    /// instead of the current target
+    pub fn connect(&mut self, addr: &str) -> Result<()> {
+        let remote = Remote::connect(addr)?;
+        // Breakpoints and symbols belong to the old program
+        self.breakpoints.clear();
+        self.modules.clear();
+        self.unloadable.clear();
+        self.frames.clear();
+        self.selected_frame = 0;
+        self.panic_handler = None;
+        self.listed = None;
+        self.target = Box::new(remote);
+        Ok(())
+    }
+
+    /// Writes a core file of the program, to `core.<pid>` if not given a path
+    pub fn save_core(&mut self, path: Option<&str>) -> Result<PathBuf> {
+        let path = match path {
+            Some(path) => PathBuf::from(path),
+            None => PathBuf::from(format!("core.{}", self.current_thread()?)),
+        };
+
+        // The core should have the program's own code, not breakpoints
+        let enabled: Vec<u32> = self
+            .breakpoints
+            .values()
+            .filter(|bp| bp.enabled)
+            .map(|bp| bp.num)
+            .collect();
+        for num in &enabled {
+            self.breakpoints
+                .get_mut(num)
+                .unwrap()
+                .disable(self.target.as_mut())?;
+        }
+        let written = corefile::write(self.target.as_ref(), &path);
+        for num in &enabled {
+            self.breakpoints
+                .get_mut(num)
+                .unwrap()
+                .enable(self.target.as_mut())?;
+        }
+        written.map(|_| path)
+    }
+
+    fn list_breakpoints(&mut self) {
+        let mut bps: Vec<&Breakpoint> = self.breakpoints.values().collect();
+        bps.sort_by_key(|bp| bp.num);
+        let mut items = Vec::new();
+        for bp in bps {
+            let state = if bp.enabled { "enabled" } else { "disabled" };
+            let location = match self.symbolize(bp.addr) {
+                Some(symbol) => format!("in {symbol}"),
+                None => format!("{:?}", bp.location),
+            };
+            let condition = match &bp.condition {
+                Some(condition) => format!(" if {condition}"),
+                None => String::new(),
+            };
+            let text = format!(
+                "{:<3} {:#018x} {state} {location}{condition}",
+                bp.num, bp.addr
+            );
+            let value = json!({
+                "number": bp.num,
+                "address": hex(bp.addr),
+                "enabled": bp.enabled,
+                "location": location.trim_start_matches("in "),
+                "condition": bp.condition,
+            });
+            items.push((text, value));
+        }
+        self.out.list("breakpoints", items);
+    }
+
+    /// Disassembles `count` instructions from a location, all of a function
+    /// when given one without a count, or from the selected frame's PC
+    fn disassemble(&mut self, loc: Option<Location>, count: Option<usize>) -> Result<()> {
+        self.unwind()?;
+        let pc = self.frames.get(self.selected_frame).map(|frame| frame.pc);
+
+        let start = match (&loc, pc) {
+            (Some(loc), _) => self.resolve(loc)?,
+            (None, Some(pc)) => pc,
+            (None, None) => return Err(Error::State("No frame selected".into())),
+        };
+        let function_end = match (&loc, count) {
+            (Some(Location::Function(_)), None) => {
+                module::find(&self.modules, start).and_then(|module| module.function_end(start))
+            }
+            _ => None,
+        };
+        let (len, count) = match function_end {
+            Some(end) => ((end - start) as usize, usize::MAX),
+            None => {
+                let count = count.unwrap_or(DISASSEMBLE_COUNT);
+                let len = count
+                    .checked_mul(disasm::MAX_INSTRUCTION_LEN)
+                    .filter(|&len| len <= memory::MAX_READ_LEN)
+                    .ok_or_else(|| Error::Parse(format!("Too many instructions: {count}")))?;
+                (len, count)
+            }
+        };
+
+        let code = self.read_memory(start, len);
+        let instructions = disasm::decode(&code, start, count);
+        if instructions.is_empty() {
+            return Err(Error::Eval(format!("Cannot read memory at {start:#x}")));
+        }
+        let items: Vec<(String, Value)> = instructions
+            .iter()
+            .map(|insn| self.instruction(insn, pc))
+            .collect();
+        self.out.list("instructions", items);
+        Ok(())
+    }
+
+    /// Prints `count` values of `size` bytes from the address an expression
+    /// gives, in hex
+    fn examine(&mut self, expr: &str, count: usize, size: usize) -> Result<()> {
+        let addr = self.evaluate(expr)?;
+
+        let len = count
+            .checked_mul(size)
+            .filter(|&len| len <= memory::MAX_READ_LEN)
+            .ok_or_else(|| Error::Parse(format!("Too many values: {count}")))?;
+        let bytes = self.read_memory(addr, len);
+        let width = 2 + 2 * size;
+        let mut items = Vec::new();
+        for (line, chunk) in (0..).zip(bytes.chunks(EXAMINE_LINE_LEN)) {
+            let line_addr = addr + line * EXAMINE_LINE_LEN as u64;
+            let symbol = match self.symbolize(line_addr) {
+                Some(symbol) => format!(" <{symbol}>"),
+                None => String::new(),
+            };
+            let values: Vec<String> = chunk
+                .chunks_exact(size)
+                .map(|value| {
+                    let mut word = [0; 8];
+                    word[..size].copy_from_slice(value);
+                    format!("{:#0width$x}", u64::from_le_bytes(word))
+                })
+                .collect();
+            let text = format!("{line_addr:#018x}{symbol}:  {}", values.join("  "));
+            items.push((text, json!({ "address": hex(line_addr), "values": values })));
+        }
+        self.out.list("memory", items);
+        if bytes.len() < len {
+            let addr = addr + bytes.len() as u64;
+            return Err(Error::Eval(format!("Cannot access memory at {addr:#x}")));
         }
+        Ok(())
+    }
+
+    /// Parses and evaluates an expression in the selected frame
+    pub fn evaluate(&mut self, expr: &str) -> Result<u64> {
+        expr::parse(expr)?.evaluate(self)
+    }
+
+    /// Reads up to `len` bytes of memory from the tracee, showing the
+    /// program's own instructions rather than the breakpoints' int3s
+    pub fn read_memory(&self, start: u64, len: usize) -> Vec<u8> {
+        let mut code = memory::read_up_to(self.target.as_ref(), start, len);
+        for bp in self.breakpoints.values() {
+            bp.mask(start, &mut code);
+        }
+        code
+    }
+
+    /// An instruction as `disassemble` prints it, marked if it's at `pc` or a
+    /// breakpoint, and as JSON
+    fn instruction(&self, insn: &disasm::Instruction, pc: Option<u64>) -> (String, Value) {
+        let marker = if Some(insn.addr) == pc { "=>" } else { "  " };
+        let bp = match self.breakpoints.values().any(|bp| bp.addr == insn.addr) {
+            true => "*",
+            false => " ",
+        };
+        let symbol = match self.symbolize(insn.addr) {
+            Some(symbol) => format!(" <{symbol}>"),
+            None => String::new(),
+        };
+        let target = match insn.target.and_then(|target| self.symbolize(target)) {
+            Some(symbol) => format!(" <{symbol}>"),
+            None => String::new(),
+        };
+        let text = format!(
+            "{marker}{bp} {:#018x}{symbol}:\t{}{target}",
+            insn.addr, insn.text
+        );
+        let value = json!({
+            "address": hex(insn.addr),
+            "symbol": self.symbolize(insn.addr),
+            "text": insn.text,
+            "current": Some(insn.addr) == pc,
+        });
+        (text, value)
+    }
+
+    /// Names the function containing an address, as `name+offset`
+    fn symbolize(&self, addr: u64) -> Option<String> {
+        let module = module::find(&self.modules, addr)?;
+        match module.symbol_for(addr)? {
+            (name, 0) => Some(name.to_string()),
+            (name, offset) => Some(format!("{name}+{offset:#x}")),
+        }
+    }
+
+    /// Resolves a location to a runtime address in the tracee
+    fn resolve(&mut self, loc: &Location) -> Result<u64> {
+        let not_found = || Error::NotFound(format!("Could not resolve location {loc:?}"));
+        match loc {
+            Location::Address(addr) => Ok(*addr as u64),
+            Location::Function(name) => {
+                module::refresh(
+                    self.target.as_ref(),
+                    &mut self.modules,
+                    &mut self.unloadable,
+                );
+                self.modules
+                    .iter()
+                    .find_map(|module| module.address_of(name))
+                    .ok_or_else(not_found)
+            }
+            Location::Expression(expr) => self.evaluate(expr),
+            Location::Line(line) => {
+                let Some(listed) = self.listed.clone().or_else(|| self.frame_line()) else {
+                    return Err(Error::State("No source file selected".into()));
+                };
+                source::address_for(&self.modules, &listed.path, *line).ok_or_else(not_found)
+            }
+            Location::FileLine(file, line) => {
+                module::refresh(
+                    self.target.as_ref(),
+                    &mut self.modules,
+                    &mut self.unloadable,
+                );
+                source::address_for(&self.modules, Path::new(file), *line).ok_or_else(not_found)
+            }
+        }
+    }
+}
+
+/// An attached program is left running without its breakpoints, which would
+/// otherwise kill it with a SIGTRAP
+impl Drop for Debugger {
+    fn drop(&mut self) {
+        let _ = self.quit();
+    }
+}
+
+impl Context for Debugger {
+    /// Looks up a history value, a register in the selected frame or a
+    /// convenience variable
+    fn variable(&mut self, name: &str) -> Result<u64> {
+        if let Ok(num) = name.parse::<usize>() {
+            return match self.history.get(num.wrapping_sub(1)) {
+                Some(Some(value)) => Ok(*value),
+                Some(None) => Err(Error::Eval(format!("${num} is not an integer"))),
+                None => Err(Error::NotFound(format!("History has no value ${num}"))),
+            };
+        }
+        if let Some(reg) = Register::from_selector(RegisterSelector::Name(name)) {
+            // Only the low 64 bits of the SSE registers are used
+            return Ok(self.read_register(&reg)? as u64);
+        }
+        self.convenience
+            .get(name)
+            .copied()
+            .ok_or_else(|| Error::NotFound(format!("No register or convenience variable ${name}")))
+    }
+
+    /// Reads an integer or pointer variable in the selected frame, or else
+    /// gives the address of a function
+    fn symbol(&mut self, name: &str) -> Option<u64> {
+        self.unwind().ok()?;
+        if let Ok(scope) = self.scope() {
+            if let Some(var) = scope.find(name) {
+                let (ty, bytes) = (scope.type_of(var)?, scope.read(var)?);
+                return integer(&scope, &ty, &bytes);
+            }
+        }
+        self.modules
+            .iter()
+            .find_map(|module| module.address_of(name))
+    }
+
+    fn read(&mut self, addr: u64, len: usize) -> Option<Vec<u8>> {
+        Some(self.read_memory(addr, len))
     }
+}
+
+/// What `b set` prints about a new breakpoint
+fn set_message(bp: &Breakpoint) -> (String, Value) {
+    let text = format!("Breakpoint {} set at {:#x}", bp.num, bp.addr);
+    (text, json!({ "number": bp.num, "address": hex(bp.addr) }))
+}
+
+/// Executes a single instruction, ignoring any breakpoint at it
+fn single_step(target: &mut dyn Target) -> Result<StopEvent> {
+    target.step()?;
+    match target.wait()? {
+        StopEvent::Signal(Signal::SIGTRAP) => Ok(StopEvent::Step { pc: target.pc()? }),
+        stop => Ok(stop),
+    }
+}
+
+/// The value of an integer or pointer as 64 bits, sign extending signed types
+fn integer(scope: &Scope, ty: &Type, bytes: &[u8]) -> Option<u64> {
+    let value = scope.types().uint(ty, bytes)?;
+    let signed = matches!(
+        ty.kind,
+        TypeKind::Base(gimli::DW_ATE_signed | gimli::DW_ATE_signed_char)
+    );
+    let unused = 64 - 8 * ty.size.min(8) as u32;
+    match signed && unused < 64 {
+        true => Some(((value << unused) as i64 >> unused) as u64),
+        false => Some(value),
+    }
+}
 
-    fn set_breakpoint(&mut self, addr: Location) {
-        let mut bp = Breakpoint::new(self.pid, addr.clone());
-        bp.enable();
-        self.breakpoints.insert(addr.clone(), bp);
-        println!("Breakpoint set at {:#?}", addr);
+fn format_variable(scope: &Scope, var: &Variable) -> String {
+    match (scope.type_of(var), scope.read(var)) {
+        (Some(ty), Some(bytes)) => scope.types().format(&ty, &bytes),
+        _ => "<optimized out>".into(),
     }
 }
 
 // ===== RegisterOp =====
 
 impl RegisterOp {
-    fn new(op: &str, reg: &str, write: Option<isize>) -> RegisterOp {
-        let reg = Register::from_selector(RegisterSelector::Name(reg));
+    fn new(op: &str, reg: &str, value: &str) -> RegisterOp {
+        // Registers are given by name, or by DWARF number as `%16`
+        let selector = match reg.strip_prefix('%').map(str::parse) {
+            Some(Ok(num)) => Some(RegisterSelector::Dwarf(num)),
+            Some(Err(_)) => None,
+            None => Some(RegisterSelector::Name(reg)),
+        };
+        let Some(reg) = selector.and_then(Register::from_selector) else {
+            return RegisterOp::InvalidRegister(reg.to_string());
+        };
         match op {
-            "r" | "read" => RegisterOp::Read { reg },
-            "w" | "write" => RegisterOp::Write {
+            "r" | "read" if value.is_empty() => RegisterOp::Read { reg },
+            "w" | "write" if !value.is_empty() => RegisterOp::Write {
                 reg,
-                // TODO: Fix unwrap
-                value: write.unwrap(),
+                value: value.to_string(),
             },
             _ => RegisterOp::Unknown,
         }
     }
 }
 
+// ===== InfoOp =====
+
+impl From<&str> for InfoOp {
+    fn from(op: &str) -> Self {
+        match op {
+            "locals" => InfoOp::Locals,
+            "args" => InfoOp::Args,
+            _ => InfoOp::Unknown,
+        }
+    }
+}
+
+// ===== CatchOp =====
+
+impl From<&str> for CatchOp {
+    fn from(op: &str) -> Self {
+        match op {
+            "panic" => CatchOp::Panic,
+            _ => CatchOp::Unknown,
+        }
+    }
+}
+
+// ===== SetOp =====
+
+impl SetOp {
+    fn new(name: &str, args: Vec<&str>) -> Self {
+        match (name, args.as_slice()) {
+            ("substitute-path", [from, to]) => SetOp::SubstitutePath {
+                from: PathBuf::from(from),
+                to: PathBuf::from(to),
+            },
+            ("disassemble-next-line", ["on"]) => SetOp::DisassembleNextLine(true),
+            ("disassemble-next-line", ["off"]) => SetOp::DisassembleNextLine(false),
+            _ => SetOp::Unknown,
+        }
+    }
+}
+
 // ===== BreakpointOp =====
 
 impl BreakpointOp {
-    fn new(op: &str, bkpt_num: Option<u8>, addr: Option<Location>) -> Self {
-        match op {
-            "ls" | "list" => BreakpointOp::List,
-            "set" => BreakpointOp::Set(addr.unwrap()),
-            "unset" => BreakpointOp::Unset(bkpt_num.unwrap()),
+    fn new(
+        op: &str,
+        bkpt_num: Option<u64>,
+        addr: Option<Location>,
+        condition: Option<String>,
+    ) -> Self {
+        match (op, addr, bkpt_num) {
+            ("ls" | "list", _, _) => BreakpointOp::List,
+            ("set", Some(addr), _) => BreakpointOp::Set(addr, condition),
+            ("unset", _, Some(num)) => BreakpointOp::Unset(num),
             _ => BreakpointOp::Unknown,
         }
     }
@@ -159,13 +1553,41 @@ impl From<&str> for Command {
             "c" | "cont" | "continue" => Command::Continue,
             "b" | "br" | "break" | "bkpt" => Command::Break,
             "r" | "reg" | "register" => Command::Register,
+            "bt" | "backtrace" => Command::Backtrace,
+            "f" | "frame" => Command::Frame,
+            "thread" => Command::Thread,
+            "up" => Command::Up,
+            "down" => Command::Down,
+            "i" | "info" => Command::Info,
+            "p" | "print" => Command::Print,
+            "catch" => Command::Catch,
+            "l" | "list" => Command::List,
+            "set" => Command::Set,
+            "disas" | "disassemble" => Command::Disassemble,
+            "si" | "stepi" => Command::StepInstruction,
+            "n" | "next" => Command::Next,
+            "s" | "step" => Command::Step,
+            "finish" => Command::Finish,
+            "x" => Command::Examine,
+            "gcore" => Command::Gcore,
+            "target" => Command::Target,
             _ => Command::Unknown,
         }
     }
 }
 
+/// Reports a command that didn't parse with a fixed message, such as its usage
+fn usage<E>(msg: &str) -> impl FnOnce(E) -> Error + '_ {
+    move |_| Error::Parse(msg.to_string())
+}
+
+fn unknown(what: &str) -> Error {
+    Error::Parse(format!("Unknown {what}"))
+}
+
 fn parse_cmd(input: &str) -> IResult<&str, Command> {
-    let (rem, cmd) = until_space_or_eof(input)?;
+    // Formats such as `x/4g` follow the name directly
+    let (rem, cmd) = take_while(|c: char| c.is_ascii_alphanumeric())(input)?;
     let cmd = Command::from(cmd);
 
     Ok((rem, cmd))
@@ -175,30 +1597,160 @@ fn parse_reg_cmd(input: &str) -> IResult<&str, RegisterOp> {
     let (rem, op) = take_space_then_until_space_or_eof(input)?;
 
     // now we have to parse the register. it can be in the format of the register name or a dwarf no.
-    // TODO: Implement parsing a dwarf no
     let (rem, reg) = take_space_then_until_space_or_eof(rem)?;
 
-    // If we have a write command, rem will have a value
-    let mut value = None;
+    // If we have a write command, the rest is an expression for the value
+    let op = RegisterOp::new(op, reg, rem.trim());
+    Ok(("", op))
+}
+
+fn parse_bkpt_cmd(input: &str) -> IResult<&str, BreakpointOp> {
+    let (rem, op) = take_space_then_until_space_or_eof(input)?;
+
+    // `set` takes a location and an optional `if <condition>`, and `unset`
+    // takes a breakpoint number
+    let mut addr = None;
+    let mut num = None;
+    let mut condition = None;
     if !rem.is_empty() {
-        let (_, (_, v)) = pair(space1, parse_number)(rem)?;
-        value = Some(v);
+        let (_, _) = space1(rem)?;
+        let (arg, cond) = match rem.split_once(" if ") {
+            Some((arg, cond)) => (arg.trim(), Some(cond.trim().to_string())),
+            None => (rem.trim(), None),
+        };
+        addr = Some(parse_location(arg));
+        num = arg.parse().ok();
+        condition = cond;
     }
 
-    let op = RegisterOp::new(op, reg, value);
+    let op = BreakpointOp::new(op, num, addr, condition);
+
     Ok(("", op))
 }
 
-fn parse_bkpt_cmd(input: &str) -> IResult<&str, BreakpointOp> {
+fn parse_info_cmd(input: &str) -> IResult<&str, InfoOp> {
     let (rem, op) = take_space_then_until_space_or_eof(input)?;
-    let (_, addr) = take_space_then_until_space_or_eof(rem)?;
+    Ok((rem, InfoOp::from(op)))
+}
 
-    let (_, num) = take_space_then_until_space_or_eof(rem)?;
-    let (_, num) = parse_number(num)?;
+fn parse_catch_cmd(input: &str) -> IResult<&str, CatchOp> {
+    let (rem, op) = take_space_then_until_space_or_eof(input)?;
+    Ok((rem, CatchOp::from(op)))
+}
 
-    let op = BreakpointOp::new(op);
+/// Parses `target remote <host:port>`
+fn parse_target_cmd(input: &str) -> IResult<&str, TargetOp> {
+    let (rem, op) = take_space_then_until_space_or_eof(input)?;
+    match op {
+        "remote" => {
+            let (rem, addr) = all_consuming(take_space_then_until_space_or_eof)(rem)?;
+            Ok((rem, TargetOp::Remote(addr.to_string())))
+        }
+        _ => Ok((rem, TargetOp::Unknown)),
+    }
+}
 
-    Ok(("", op))
+/// Parses `list`'s argument: nothing, `line`, `file:line` or `function`
+fn parse_list_cmd(input: &str) -> IResult<&str, ListOp> {
+    let arg = input.trim();
+    if arg.is_empty() {
+        return Ok(("", ListOp::Continue));
+    }
+    if let Ok((_, line)) = all_consuming(parse_number)(arg) {
+        return Ok(("", ListOp::Line(line as u64)));
+    }
+    if let Some((file, line)) = arg.rsplit_once(':') {
+        if let Ok((_, line)) = all_consuming(parse_number)(line) {
+            return Ok(("", ListOp::FileLine(file.to_string(), line as u64)));
+        }
+    }
+    Ok(("", ListOp::Function(arg.to_string())))
+}
+
+/// Parses `disassemble`'s optional location and count. A location is given
+/// in hex or by name, so a single decimal number is a count
+fn parse_disas_cmd(input: &str) -> IResult<&str, (Option<Location>, Option<usize>)> {
+    let mut args = input.split_whitespace();
+    let (loc, count) = match (args.next(), args.next()) {
+        (None, _) => (None, None),
+        (Some(arg), None) if arg.bytes().all(|b| b.is_ascii_digit()) => (None, Some(arg)),
+        (Some(arg), count) => (Some(parse_location(arg)), count),
+    };
+    let count = match count {
+        Some(count) => Some(all_consuming(parse_number)(count)?.1 as usize),
+        None => None,
+    };
+    Ok(("", (loc, count)))
+}
+
+/// Parses `x`'s optional `/<count><size>` format, where the size is one of
+/// `b`, `h`, `w` or `g` for 1, 2, 4 or 8 bytes, and its address expression
+fn parse_examine_cmd(input: &str) -> IResult<&str, (usize, usize, &str)> {
+    let (rem, format) = opt(preceded(
+        char('/'),
+        pair(opt(parse_number), opt(one_of("bhwg"))),
+    ))(input)?;
+    let (addr, _) = space1(rem)?;
+
+    let (count, size) = format.unwrap_or((None, None));
+    let size = match size {
+        Some('b') => 1,
+        Some('h') => 2,
+        Some('g') => 8,
+        _ => 4,
+    };
+    Ok(("", (count.unwrap_or(1) as usize, size, addr.trim())))
+}
+
+fn parse_set_cmd(input: &str) -> IResult<&str, SetOp> {
+    // Convenience variables are set with `set $name = <expr>`
+    if let Some((name, value)) = input.trim().split_once('=') {
+        if let Some(name) = name.trim().strip_prefix('$') {
+            if !name.is_empty()
+                && name.chars().all(|c| c.is_ascii_alphanumeric() || c == '_')
+                && !name.starts_with(|c: char| c.is_ascii_digit())
+            {
+                let (name, value) = (name.to_string(), value.trim().to_string());
+                return Ok(("", SetOp::Variable { name, value }));
+            }
+        }
+    }
+
+    let (rem, name) = take_space_then_until_space_or_eof(input)?;
+    let args = rem.split_whitespace().collect();
+    Ok(("", SetOp::new(name, args)))
+}
+
+/// Parses the optional frame number or count taken by `frame`, `up` and `down`
+fn parse_frame_cmd(input: &str) -> IResult<&str, Option<usize>> {
+    if input.trim().is_empty() {
+        return Ok(("", None));
+    }
+    let (rem, num) = take_space_then_until_space_or_eof(input)?;
+    let (_, num) = parse_number(num)?;
+    Ok((rem, Some(num as usize)))
+}
+
+fn parse_location(input: &str) -> Location {
+    if let Some(addr) = input.strip_prefix("0x") {
+        if let Ok(addr) = isize::from_str_radix(addr, 16) {
+            return Location::Address(addr);
+        }
+    }
+    if let Ok(line) = input.parse::<u64>() {
+        return Location::Line(line);
+    }
+    if let Some((file, line)) = input.rsplit_once(':') {
+        if let Ok(line) = line.parse::<u64>() {
+            return Location::FileLine(file.to_string(), line);
+        }
+    }
+    // Names that aren't expressions, such as `<T as Trait>::f`, are still
+    // looked up as functions
+    match expr::parse(input) {
+        Ok(expr::Expr::Symbol(_)) | Err(_) => Location::Function(input.to_string()),
+        Ok(_) => Location::Expression(input.to_string()),
+    }
 }
 
 fn take_space_then_until_space_or_eof(input: &str) -> IResult<&str, &str> {
@@ -221,3 +1773,359 @@ fn until_space(input: &str) -> IResult<&str, &str> {
 fn parse_number(input: &str) -> IResult<&str, isize> {
     map_res(digit1, |s: &str| s.parse::<isize>())(input)
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::{MockTarget, Scripted};
+    use crate::target::Mapping;
+
+    /// A run of single byte `nop`s
+    const CODE: [u8; 16] = [0x90; 16];
+
+    /// A program starting at 0x1000 that runs through each of its
+    /// instructions once, then exits
+    fn debugger(rax: u64) -> Debugger {
+        let mut target = MockTarget::new()
+            .map(0x1000, &CODE)
+            .at(0x1000)
+            .script((0x1001..0x1010).map(Scripted::Execute));
+        target.regs.rax = rax;
+        Debugger::new(target)
+    }
+
+    fn set(dbg: &mut Debugger, addr: u64, condition: Option<&str>) {
+        let loc = Location::Address(addr as isize);
+        dbg.set_breakpoint(loc, condition.map(String::from))
+            .unwrap();
+    }
+
+    #[test]
+    fn disassembly_shows_the_instructions_under_breakpoints() {
+        // `call 0x1010` then `nop`s, with the callee at 0x1010
+        let mut code = [0x90; 0x20];
+        code[..5].copy_from_slice(&[0xe8, 0x0b, 0x00, 0x00, 0x00]);
+        let mut target = MockTarget::new().map(0x1000, &code).at(0x1000);
+        target.mappings.push(Mapping {
+            path: PathBuf::from("/bin/test"),
+            range: 0x1000..0x1020,
+            executable: true,
+            offset: 0,
+        });
+        let mut dbg = Debugger::new(target);
+        dbg.modules.push(Module::with_symbols(
+            0x1000..0x1020,
+            &[
+                ("main", 0x1000, 0x10),
+                ("_ZN4test6callee17h0123456789abcdefE", 0x1010, 0x10),
+            ],
+        ));
+        set(&mut dbg, 0x1000, None);
+        assert_eq!(dbg.target().read_memory(0x1000, 1).unwrap(), [0xcc]);
+
+        let output = dbg.execute_json("disassemble 0x1000 2");
+
+        let instructions = &output["instructions"];
+        assert_eq!(instructions[0]["text"], "call 0x1010", "{output}");
+        assert_eq!(instructions[0]["symbol"], "main", "{output}");
+        assert_eq!(instructions[1]["text"], "nop", "{output}");
+        assert_eq!(instructions[1]["symbol"], "main+0x5", "{output}");
+
+        let code = dbg.read_memory(0x1000, disasm::MAX_INSTRUCTION_LEN);
+        let call = &disasm::decode(&code, 0x1000, 1)[0];
+        let (text, _) = dbg.instruction(call, Some(0x1000));
+        assert_eq!(
+            text,
+            "=>* 0x0000000000001000 <main>:\tcall 0x1010 <test::callee>"
+        );
+    }
+
+    #[test]
+    fn continue_stops_at_breakpoint() {
+        let mut dbg = debugger(0);
+        set(&mut dbg, 0x1004, None);
+
+        let stop = dbg.continue_().unwrap();
+
+        assert_eq!(
+            stop,
+            StopEvent::Breakpoint {
+                num: 1,
+                addr: 0x1004
+            }
+        );
+        // The PC is moved back from after the int3
+        assert_eq!(dbg.target().pc().unwrap(), 0x1004);
+    }
+
+    #[test]
+    fn continue_steps_over_breakpoint() {
+        let mut dbg = debugger(0);
+        set(&mut dbg, 0x1004, None);
+        set(&mut dbg, 0x1008, None);
+
+        dbg.continue_().unwrap();
+        let stop = dbg.continue_().unwrap();
+
+        assert_eq!(
+            stop,
+            StopEvent::Breakpoint {
+                num: 2,
+                addr: 0x1008
+            }
+        );
+        // The first breakpoint is put back after stepping over it
+        assert_eq!(dbg.target().read_memory(0x1004, 1).unwrap(), [0xcc]);
+    }
+
+    #[test]
+    fn continue_runs_to_exit() {
+        let mut dbg = debugger(0);
+        set(&mut dbg, 0x1004, None);
+
+        dbg.continue_().unwrap();
+
+        assert_eq!(dbg.continue_().unwrap(), StopEvent::Exited(0));
+    }
+
+    #[test]
+    fn continue_reports_signals_and_exit_codes() {
+        let target = MockTarget::new().map(0x1000, &CODE).at(0x1000).script([
+            Scripted::Execute(0x1001),
+            Scripted::Signal(Signal::SIGSEGV),
+            Scripted::Exit(3),
+        ]);
+        let mut dbg = Debugger::new(target);
+
+        assert_eq!(dbg.continue_().unwrap(), StopEvent::Signal(Signal::SIGSEGV));
+        assert_eq!(dbg.continue_().unwrap(), StopEvent::Exited(3));
+    }
+
+    #[test]
+    fn continue_skips_breakpoint_when_condition_is_zero() {
+        let mut dbg = debugger(1);
+        set(&mut dbg, 0x1004, Some("$rax == 2"));
+
+        assert_eq!(dbg.continue_().unwrap(), StopEvent::Exited(0));
+    }
+
+    #[test]
+    fn continue_stops_when_condition_is_non_zero() {
+        let mut dbg = debugger(2);
+        set(&mut dbg, 0x1004, Some("$rax == 2"));
+
+        assert!(matches!(
+            dbg.continue_().unwrap(),
+            StopEvent::Breakpoint { num: 1, .. }
+        ));
+    }
+
+    #[test]
+    fn step_executes_one_instruction() {
+        let mut dbg = debugger(0);
+
+        assert_eq!(dbg.step().unwrap(), StopEvent::Step { pc: 0x1001 });
+    }
+
+    #[test]
+    fn step_executes_original_instruction_at_breakpoint() {
+        let mut dbg = debugger(0);
+        set(&mut dbg, 0x1000, None);
+
+        assert_eq!(dbg.step().unwrap(), StopEvent::Step { pc: 0x1001 });
+        assert_eq!(dbg.target().read_memory(0x1000, 1).unwrap(), [0xcc]);
+    }
+
+    #[test]
+    fn breakpoints_at_the_same_address_are_rejected() {
+        let mut dbg = debugger(0);
+        set(&mut dbg, 0x1004, None);
+
+        let loc = Location::Address(0x1004);
+        assert!(dbg.set_breakpoint(loc, None).is_err());
+    }
+
+    #[test]
+    fn unset_breakpoint_restores_code() {
+        let mut dbg = debugger(0);
+        set(&mut dbg, 0x1004, None);
+
+        dbg.unset_breakpoint(1).unwrap();
+
+        assert_eq!(dbg.target().read_memory(0x1000, 16).unwrap(), CODE);
+        assert!(matches!(dbg.unset_breakpoint(1), Err(Error::NotFound(_))));
+    }
+
+    #[test]
+    fn unset_breakpoint_number_too_large_is_not_found() {
+        let mut dbg = debugger(0);
+        set(&mut dbg, 0x1004, None);
+
+        // 257 would be breakpoint 1 if truncated to a byte
+        let e = dbg.execute("b unset 257").unwrap_err();
+        assert_eq!(e.to_string(), "No breakpoint number 257");
+        let e = dbg.execute("b unset 4294967297").unwrap_err();
+        assert_eq!(e.to_string(), "No breakpoint number 4294967297");
+        assert_eq!(dbg.breakpoints.len(), 1);
+    }
+
+    #[test]
+    fn up_past_the_largest_frame_number_is_an_error() {
+        let mut dbg = debugger(0);
+        dbg.selected_frame = usize::MAX;
+
+        let e = dbg.execute("up").unwrap_err();
+        assert_eq!(e.to_string(), "Already at the outermost frame");
+    }
+
+    #[test]
+    fn read_memory_hides_breakpoints() {
+        let mut dbg = debugger(0);
+        set(&mut dbg, 0x1004, None);
+
+        assert_eq!(dbg.read_memory(0x1000, 16), CODE);
+    }
+
+    #[test]
+    fn reads_too_large_to_make_are_refused() {
+        let mut dbg = debugger(0);
+
+        let len = memory::MAX_READ_LEN + 1;
+        assert!(memory::read(dbg.target(), 0x1000, len).is_none());
+        let e = dbg.execute("x/4294967295g 0x1000").unwrap_err();
+        assert_eq!(e.to_string(), "Too many values: 4294967295");
+        let e = dbg.execute("disassemble 0x1000 4294967295").unwrap_err();
+        assert_eq!(e.to_string(), "Too many instructions: 4294967295");
+    }
+
+    #[test]
+    fn evaluate_reads_registers_and_memory() {
+        let mut dbg = debugger(0x1000);
+
+        assert_eq!(dbg.evaluate("$rax + 4").unwrap(), 0x1004);
+        assert_eq!(dbg.evaluate("*(u8 *)$rax").unwrap(), 0x90);
+        assert!(dbg.evaluate("*0x2000").is_err());
+    }
+
+    #[test]
+    fn execute_reports_errors() {
+        let mut dbg = debugger(0);
+
+        assert!(matches!(dbg.execute("frobnicate"), Err(Error::Parse(_))));
+        assert!(matches!(dbg.execute("b set"), Err(Error::Parse(_))));
+        assert!(matches!(dbg.execute("b unset 3"), Err(Error::NotFound(_))));
+        assert!(dbg.execute("").is_ok());
+    }
+
+    #[test]
+    fn parses_locations() {
+        assert_eq!(parse_location("0x1004"), Location::Address(0x1004));
+        assert_eq!(parse_location("12"), Location::Line(12));
+        assert_eq!(
+            parse_location("main.c:12"),
+            Location::FileLine("main.c".into(), 12)
+        );
+        assert_eq!(
+            parse_location("main"),
+            Location::Function("main".to_string())
+        );
+        assert_eq!(
+            parse_location("<T as Trait>::f"),
+            Location::Function("<T as Trait>::f".to_string())
+        );
+        assert_eq!(
+            parse_location("main+8"),
+            Location::Expression("main+8".to_string())
+        );
+    }
+
+    #[test]
+    fn parses_breakpoint_commands() {
+        let (_, op) = parse_bkpt_cmd(" set f if $rdi == 1").unwrap();
+        assert!(matches!(
+            op,
+            BreakpointOp::Set(Location::Function(name), Some(condition))
+                if name == "f" && condition == "$rdi == 1"
+        ));
+
+        let (_, op) = parse_bkpt_cmd(" unset 2").unwrap();
+        assert!(matches!(op, BreakpointOp::Unset(2)));
+
+        let (_, op) = parse_bkpt_cmd(" ls").unwrap();
+        assert!(matches!(op, BreakpointOp::List));
+
+        let (_, op) = parse_bkpt_cmd(" unset").unwrap();
+        assert!(matches!(op, BreakpointOp::Unknown));
+    }
+
+    #[test]
+    fn parses_register_commands() {
+        let (_, op) = parse_reg_cmd(" read %16").unwrap();
+        assert!(matches!(op, RegisterOp::Read { reg } if reg.name() == "rip"));
+
+        let (_, op) = parse_reg_cmd(" write rax $rbx + 1").unwrap();
+        assert!(matches!(
+            op,
+            RegisterOp::Write { reg, value } if reg.name() == "rax" && value == "$rbx + 1"
+        ));
+
+        let (_, op) = parse_reg_cmd(" read %99").unwrap();
+        assert!(matches!(op, RegisterOp::InvalidRegister(name) if name == "%99"));
+
+        let (_, op) = parse_reg_cmd(" write rax").unwrap();
+        assert!(matches!(op, RegisterOp::Unknown));
+    }
+
+    #[test]
+    fn parses_examine_commands() {
+        let (args, cmd) = parse_cmd("x/4g $rsp").unwrap();
+        assert!(matches!(cmd, Command::Examine));
+        assert_eq!(parse_examine_cmd(args).unwrap().1, (4, 8, "$rsp"));
+
+        assert_eq!(parse_examine_cmd(" $rsp").unwrap().1, (1, 4, "$rsp"));
+        assert_eq!(parse_examine_cmd("/b f").unwrap().1, (1, 1, "f"));
+    }
+
+    #[test]
+    fn parses_set_commands() {
+        let (_, op) = parse_set_cmd(" $want = $rdi + 1").unwrap();
+        assert!(matches!(
+            op,
+            SetOp::Variable { name, value } if name == "want" && value == "$rdi + 1"
+        ));
+
+        let (_, op) = parse_set_cmd(" disassemble-next-line off").unwrap();
+        assert!(matches!(op, SetOp::DisassembleNextLine(false)));
+    }
+
+    #[test]
+    fn parses_list_commands() {
+        assert!(matches!(parse_list_cmd("").unwrap().1, ListOp::Continue));
+        assert!(matches!(parse_list_cmd(" 12").unwrap().1, ListOp::Line(12)));
+        assert!(matches!(
+            parse_list_cmd(" main.rs:3").unwrap().1,
+            ListOp::FileLine(file, 3) if file == "main.rs"
+        ));
+        assert!(matches!(
+            parse_list_cmd(" main").unwrap().1,
+            ListOp::Function(name) if name == "main"
+        ));
+    }
+
+    #[test]
+    fn parses_target_commands() {
+        let (args, cmd) = parse_cmd("target remote localhost:1234").unwrap();
+        assert!(matches!(cmd, Command::Target));
+        assert!(matches!(
+            parse_target_cmd(args).unwrap().1,
+            TargetOp::Remote(addr) if addr == "localhost:1234"
+        ));
+
+        assert!(parse_target_cmd(" remote").is_err());
+        assert!(parse_target_cmd(" remote :1 :2").is_err());
+        assert!(matches!(
+            parse_target_cmd(" core").unwrap().1,
+            TargetOp::Unknown
+        ));
+    }
+}
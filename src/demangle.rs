@@ -0,0 +1,106 @@
+use cpp_demangle::DemangleOptions;
+
+/// A symbol's name as shown to users, and its path without hashes or
+/// parameters, such as `my_crate::module::func`, used to look it up
+pub struct Demangled {
+    pub name: String,
+    pub path: String,
+}
+
+/// Demangles Rust (legacy and v0) and Itanium C++ symbol names. Other names,
+/// such as C functions, are left as they are
+pub fn demangle(mangled: &str) -> Demangled {
+    if let Ok(symbol) = rustc_demangle::try_demangle(mangled) {
+        // The alternate format leaves out the hash
+        let name = format!("{symbol:#}");
+        return Demangled {
+            path: name.clone(),
+            name,
+        };
+    }
+
+    if mangled.starts_with("_Z") {
+        if let Ok(symbol) = cpp_demangle::Symbol::new(mangled) {
+            let name = symbol.demangle(&DemangleOptions::new());
+            let path = symbol.demangle(&DemangleOptions::new().no_params().no_return_type());
+            if let (Ok(name), Ok(path)) = (name, path) {
+                return Demangled { name, path };
+            }
+        }
+    }
+
+    Demangled {
+        name: mangled.to_string(),
+        path: mangled.to_string(),
+    }
+}
+
+/// Whether `query` names the symbol: its mangled name, its demangled name or
+/// path, or the end of its path such as `module::func`
+pub fn matches(query: &str, mangled: &str, demangled: &Demangled) -> bool {
+    query == mangled
+        || query == demangled.name
+        || query == demangled.path
+        || demangled
+            .path
+            .strip_suffix(query)
+            .is_some_and(|prefix| prefix.ends_with("::"))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn symbols_are_demangled() {
+        let cases = [
+            // Legacy Rust, whose hash is left out
+            (
+                "_ZN8my_crate6module4func17h0123456789abcdefE",
+                "my_crate::module::func",
+                "my_crate::module::func",
+            ),
+            // Rust v0
+            (
+                "_RNvNtCs1234_8my_crate6module4func",
+                "my_crate::module::func",
+                "my_crate::module::func",
+            ),
+            // Itanium C++, whose path has no parameters
+            ("_ZN3foo3barEi", "foo::bar(int)", "foo::bar"),
+            (
+                "_ZNK3foo3Baz3getEv",
+                "foo::Baz::get() const",
+                "foo::Baz::get",
+            ),
+            // C, and names that only look mangled
+            ("main", "main", "main"),
+            ("_Z", "_Z", "_Z"),
+        ];
+
+        for (mangled, name, path) in cases {
+            let demangled = demangle(mangled);
+            assert_eq!(demangled.name, name, "{mangled}");
+            assert_eq!(demangled.path, path, "{mangled}");
+        }
+    }
+
+    #[test]
+    fn paths_match_on_whole_components() {
+        let mangled = "_ZN8my_crate6module4func17h0123456789abcdefE";
+        let demangled = demangle(mangled);
+        let cases = [
+            (mangled, true),
+            ("my_crate::module::func", true),
+            ("module::func", true),
+            ("func", true),
+            ("unc", false),
+            ("dule::func", false),
+            ("other::func", false),
+        ];
+
+        for (query, expected) in cases {
+            assert_eq!(matches(query, mangled, &demangled), expected, "{query}");
+        }
+    }
+}
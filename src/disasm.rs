@@ -0,0 +1,44 @@
+use iced_x86::{Decoder, DecoderError, DecoderOptions, Formatter, GasFormatter};
+
+/// The longest an x86-64 instruction can be, in bytes
+pub const MAX_INSTRUCTION_LEN: usize = 15;
+
+/// A decoded instruction, formatted in AT&T syntax like `objdump -d`
+pub struct Instruction {
+    pub addr: u64,
+    pub text: String,
+    /// Target of a direct jump or call
+    pub target: Option<u64>,
+}
+
+/// Decodes up to `count` instructions from code that starts at `addr`
+pub fn decode(code: &[u8], addr: u64, count: usize) -> Vec<Instruction> {
+    let mut decoder = Decoder::with_ip(64, code, addr, DecoderOptions::NONE);
+    let mut formatter = GasFormatter::new();
+    formatter.options_mut().set_uppercase_hex(false);
+    formatter.options_mut().set_branch_leading_zeros(false);
+    let mut decoded = iced_x86::Instruction::default();
+
+    let mut instructions = Vec::new();
+    while decoder.can_decode() && instructions.len() < count {
+        decoder.decode_out(&mut decoded);
+        // The code can end part way through an instruction
+        if decoder.last_error() == DecoderError::NoMoreBytes {
+            break;
+        }
+
+        let mut text = String::new();
+        if decoded.is_invalid() {
+            text.push_str("(bad)");
+        } else {
+            formatter.format(&decoded, &mut text);
+        }
+        let target = decoded.near_branch_target();
+        instructions.push(Instruction {
+            addr: decoded.ip(),
+            text,
+            target: (target != 0).then_some(target),
+        });
+    }
+    instructions
+}
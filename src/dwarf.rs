@@ -0,0 +1,488 @@
+use gimli::Reader as _;
+use gimli::{
+    constants, AttributeValue, DebuggingInformationEntry, DwAt, DwTag, Dwarf, EntriesTreeNode,
+    EvaluationResult, Expression, Location, Piece, Unit, UnitOffset, Value, ValueType,
+};
+
+use crate::error::{Error, Result};
+use crate::memory;
+use crate::module::{self, Module, Reader};
+use crate::register::{Register, RegisterSelector};
+use crate::target::Target;
+use crate::types::{Type, Types};
+use crate::unwind::Frame;
+
+/// A variable or parameter in scope at a frame's PC
+pub struct Variable {
+    pub name: String,
+    pub is_arg: bool,
+    location: Option<AttributeValue<Reader>>,
+    const_value: Option<AttributeValue<Reader>>,
+    type_offset: Option<UnitOffset>,
+}
+
+/// The debug information for a frame: the unit and function its PC is in, and
+/// the variables visible there
+pub struct Scope<'a> {
+    target: &'a dyn Target,
+    modules: &'a [Module],
+    frames: &'a [Frame],
+    /// Index of the frame in `frames`
+    index: usize,
+    module: &'a Module,
+    dwarf: &'a Dwarf<Reader>,
+    unit: &'a Unit<Reader>,
+    /// Link-time PC used for scope and location list lookups
+    pc: u64,
+    frame_base: Option<AttributeValue<Reader>>,
+    /// Variables in scope, from the outermost to the innermost
+    pub variables: Vec<Variable>,
+}
+
+impl<'a> Scope<'a> {
+    /// Finds the scope of the frame at `index`, if its module has debug information
+    pub fn new(
+        target: &'a dyn Target,
+        modules: &'a [Module],
+        frames: &'a [Frame],
+        index: usize,
+    ) -> Option<Self> {
+        let frame = frames.get(index)?;
+        // Caller frames are looked up by the call instruction rather than the
+        // return address, which could be in a different scope
+        let pc = if index == 0 { frame.pc } else { frame.pc - 1 };
+        let module = module::find(modules, pc)?;
+        let dwarf = module.dwarf.as_ref()?;
+        let pc = pc - module.bias;
+
+        for unit in &module.units {
+            if !contains(dwarf.unit_ranges(unit), pc) {
+                continue;
+            }
+
+            let mut builder = ScopeBuilder::default();
+            let mut tree = unit.entries_tree(None).ok()?;
+            builder
+                .collect(dwarf, unit, tree.root().ok()?, pc, false)
+                .ok()?;
+            if !builder.found {
+                continue;
+            }
+
+            return Some(Scope {
+                target,
+                modules,
+                frames,
+                index,
+                module,
+                dwarf,
+                unit,
+                pc,
+                frame_base: builder.frame_base,
+                variables: builder.variables,
+            });
+        }
+        None
+    }
+
+    /// Finds a variable by name, preferring the innermost one if it is shadowed
+    pub fn find(&self, name: &str) -> Option<&Variable> {
+        self.variables.iter().rev().find(|var| var.name == name)
+    }
+
+    pub fn types(&self) -> Types<'a> {
+        Types::new(self.target, self.dwarf, self.unit)
+    }
+
+    pub fn type_of(&self, var: &Variable) -> Option<Type> {
+        self.types().decode(var.type_offset?)
+    }
+
+    /// Reads the bytes of a variable's value, or `None` if it has been optimized out
+    pub fn read(&self, var: &Variable) -> Option<Vec<u8>> {
+        let size = self.type_of(var)?.size as usize;
+
+        if let Some(value) = &var.const_value {
+            return match value {
+                AttributeValue::Block(bytes) => Some(bytes.to_slice().ok()?.to_vec()),
+                AttributeValue::Sdata(value) => Some(value.to_le_bytes()[..size.min(8)].to_vec()),
+                value => Some(value.udata_value()?.to_le_bytes()[..size.min(8)].to_vec()),
+            };
+        }
+
+        let expr = self.expression(var.location.clone()?)?;
+        let pieces = self.evaluate(expr)?;
+        self.assemble(&pieces, size)
+    }
+
+    /// Selects the expression that applies at the PC, for locations that
+    /// are given by a location list
+    fn expression(&self, value: AttributeValue<Reader>) -> Option<Expression<Reader>> {
+        if let AttributeValue::Exprloc(expr) = value {
+            return Some(expr);
+        }
+
+        let mut locations = self.dwarf.attr_locations(self.unit, value).ok()??;
+        while let Ok(Some(entry)) = locations.next() {
+            if entry.range.begin <= self.pc && self.pc < entry.range.end {
+                return Some(entry.data);
+            }
+        }
+        None
+    }
+
+    /// Evaluates a DWARF expression against the frame's registers and the
+    /// tracee's memory
+    fn evaluate(&self, expr: Expression<Reader>) -> Option<Vec<Piece<Reader>>> {
+        let frame = &self.frames[self.index];
+        let mut eval = expr.evaluation(self.unit.encoding());
+
+        let mut result = eval.evaluate().ok()?;
+        loop {
+            result = match result {
+                EvaluationResult::Complete => break,
+                EvaluationResult::RequiresMemory { address, size, .. } => {
+                    let value = deref(self.target, address, size).ok()?;
+                    eval.resume_with_memory(Value::Generic(value)).ok()?
+                }
+                EvaluationResult::RequiresRegister { register, .. } => {
+                    let value = read_register(frame, register.0)?;
+                    eval.resume_with_register(Value::Generic(value)).ok()?
+                }
+                EvaluationResult::RequiresFrameBase => {
+                    let frame_base = self.frame_base()?;
+                    eval.resume_with_frame_base(frame_base).ok()?
+                }
+                EvaluationResult::RequiresCallFrameCfa => {
+                    eval.resume_with_call_frame_cfa(frame.cfa?).ok()?
+                }
+                EvaluationResult::RequiresRelocatedAddress(addr) => {
+                    let addr = addr.wrapping_add(self.module.bias);
+                    eval.resume_with_relocated_address(addr).ok()?
+                }
+                EvaluationResult::RequiresIndexedAddress { index, relocate } => {
+                    let mut addr = self.dwarf.address(self.unit, index).ok()?;
+                    if relocate {
+                        addr = addr.wrapping_add(self.module.bias);
+                    }
+                    eval.resume_with_indexed_address(addr).ok()?
+                }
+                EvaluationResult::RequiresBaseType(offset) => {
+                    let entry = self.unit.entry(offset).ok()?;
+                    let value_type = ValueType::from_entry(&entry).ok()??;
+                    eval.resume_with_base_type(value_type).ok()?
+                }
+                EvaluationResult::RequiresEntryValue(expr) => {
+                    let value = self.entry_value(&expr)?;
+                    eval.resume_with_entry_value(Value::Generic(value)).ok()?
+                }
+                _ => return None,
+            };
+        }
+
+        Some(eval.result())
+    }
+
+    fn frame_base(&self) -> Option<u64> {
+        let expr = self.expression(self.frame_base.clone()?)?;
+        let pieces = self.evaluate(expr)?;
+        match pieces.first()?.location {
+            Location::Register { register } => read_register(&self.frames[self.index], register.0),
+            Location::Address { address } => Some(address),
+            _ => None,
+        }
+    }
+
+    /// Recovers the value a register had on entry to the function, using the
+    /// call site parameters the caller describes for the call
+    fn entry_value(&self, expr: &Expression<Reader>) -> Option<u64> {
+        let caller = Scope::new(self.target, self.modules, self.frames, self.index + 1)?;
+        let return_pc = self.frames.get(self.index + 1)?.pc - caller.module.bias;
+        let param = caller.call_site_parameter(return_pc, expr)?;
+
+        let pieces = caller.evaluate(param)?;
+        match pieces.first()?.location {
+            Location::Register { register } => {
+                read_register(&caller.frames[caller.index], register.0)
+            }
+            Location::Address { address } => Some(address),
+            Location::Value { value } => value.to_u64(!0).ok(),
+            _ => None,
+        }
+    }
+
+    /// Finds the expression for the value the caller passed in the register
+    /// described by `location` at the call returning to `return_pc`
+    fn call_site_parameter(
+        &self,
+        return_pc: u64,
+        location: &Expression<Reader>,
+    ) -> Option<Expression<Reader>> {
+        let mut entries = self.unit.entries();
+        while let Ok(Some((_, entry))) = entries.next_dfs() {
+            let pc = match entry.tag() {
+                constants::DW_TAG_call_site => entry.attr_value(constants::DW_AT_call_return_pc),
+                constants::DW_TAG_GNU_call_site => entry.attr_value(constants::DW_AT_low_pc),
+                _ => continue,
+            };
+            let Ok(Some(pc)) = pc else {
+                continue;
+            };
+            if self.dwarf.attr_address(self.unit, pc).ok()? != Some(return_pc) {
+                continue;
+            }
+
+            let mut tree = self.unit.entries_tree(Some(entry.offset())).ok()?;
+            let root = tree.root().ok()?;
+            let mut children = root.children();
+            while let Ok(Some(child)) = children.next() {
+                let param = child.entry();
+                if !matches!(
+                    param.tag(),
+                    constants::DW_TAG_call_site_parameter
+                        | constants::DW_TAG_GNU_call_site_parameter
+                ) {
+                    continue;
+                }
+                let Ok(Some(AttributeValue::Exprloc(loc))) =
+                    param.attr_value(constants::DW_AT_location)
+                else {
+                    continue;
+                };
+                if loc.0.to_slice().ok()? != location.0.to_slice().ok()? {
+                    continue;
+                }
+                let value = param
+                    .attr_value(constants::DW_AT_call_value)
+                    .ok()
+                    .flatten()
+                    .or_else(|| {
+                        param
+                            .attr_value(constants::DW_AT_GNU_call_site_value)
+                            .ok()
+                            .flatten()
+                    });
+                if let Some(AttributeValue::Exprloc(expr)) = value {
+                    return Some(expr);
+                }
+            }
+            return None;
+        }
+        None
+    }
+
+    /// Joins the pieces a variable is split into into its bytes
+    fn assemble(&self, pieces: &[Piece<Reader>], size: usize) -> Option<Vec<u8>> {
+        let frame = &self.frames[self.index];
+        let mut bytes = Vec::with_capacity(size);
+
+        for piece in pieces {
+            let len = match piece.size_in_bits {
+                Some(bits) => bits.div_ceil(8) as usize,
+                None => size,
+            };
+            match &piece.location {
+                Location::Empty => return None,
+                Location::Register { register } => {
+                    let value = read_register(frame, register.0)?;
+                    bytes.extend(value.to_le_bytes().iter().take(len));
+                }
+                Location::Address { address } => {
+                    bytes.extend(memory::read(self.target, *address, len)?);
+                }
+                Location::Value { value } => {
+                    let value = value.to_u64(!0).ok()?;
+                    bytes.extend(value.to_le_bytes().iter().take(len));
+                }
+                Location::Bytes { value } => bytes.extend(value.to_slice().ok()?.iter()),
+                Location::ImplicitPointer { .. } => return None,
+            }
+        }
+
+        bytes.resize(size, 0);
+        Some(bytes)
+    }
+}
+
+#[derive(Default)]
+struct ScopeBuilder {
+    /// Whether a function containing the PC was found
+    found: bool,
+    frame_base: Option<AttributeValue<Reader>>,
+    variables: Vec<Variable>,
+}
+
+impl ScopeBuilder {
+    /// Walks the DIE tree down to the innermost scope containing the PC,
+    /// collecting the variables of each scope on the way
+    fn collect(
+        &mut self,
+        dwarf: &Dwarf<Reader>,
+        unit: &Unit<Reader>,
+        node: EntriesTreeNode<Reader>,
+        pc: u64,
+        in_function: bool,
+    ) -> gimli::Result<()> {
+        let mut children = node.children();
+        while let Some(child) = children.next()? {
+            let entry = child.entry();
+            match entry.tag() {
+                constants::DW_TAG_subprogram
+                | constants::DW_TAG_inlined_subroutine
+                | constants::DW_TAG_lexical_block => {
+                    if !contains(dwarf.die_ranges(unit, entry), pc) {
+                        continue;
+                    }
+
+                    // Variables of the functions an inlined function was
+                    // inlined into aren't in its scope
+                    if entry.tag() != constants::DW_TAG_lexical_block {
+                        self.found = true;
+                        self.variables.clear();
+                    }
+                    if entry.tag() == constants::DW_TAG_subprogram {
+                        self.frame_base = entry.attr_value(constants::DW_AT_frame_base)?;
+                    }
+                    self.collect(dwarf, unit, child, pc, true)?;
+                }
+                constants::DW_TAG_variable | constants::DW_TAG_formal_parameter if in_function => {
+                    if let Some(var) = variable(dwarf, unit, entry) {
+                        self.variables.push(var);
+                    }
+                }
+                // Rust nests functions in namespaces, and sometimes types
+                _ if entry.has_children() && !in_function => {
+                    self.collect(dwarf, unit, child, pc, false)?;
+                }
+                _ => {}
+            }
+        }
+        Ok(())
+    }
+}
+
+fn variable(
+    dwarf: &Dwarf<Reader>,
+    unit: &Unit<Reader>,
+    entry: &DebuggingInformationEntry<Reader>,
+) -> Option<Variable> {
+    let type_offset = match attr(unit, entry, constants::DW_AT_type) {
+        Some(AttributeValue::UnitRef(offset)) => Some(offset),
+        _ => None,
+    };
+
+    Some(Variable {
+        name: attr_string(dwarf, unit, entry, constants::DW_AT_name)?,
+        is_arg: entry.tag() == constants::DW_TAG_formal_parameter,
+        location: entry.attr_value(constants::DW_AT_location).ok()?,
+        const_value: attr(unit, entry, constants::DW_AT_const_value),
+        type_offset,
+    })
+}
+
+/// Reads an attribute, following `DW_AT_abstract_origin` and
+/// `DW_AT_specification` to the DIE that declares it
+pub fn attr(
+    unit: &Unit<Reader>,
+    entry: &DebuggingInformationEntry<Reader>,
+    name: DwAt,
+) -> Option<AttributeValue<Reader>> {
+    if let Ok(Some(value)) = entry.attr_value(name) {
+        return Some(value);
+    }
+
+    for origin in [
+        constants::DW_AT_abstract_origin,
+        constants::DW_AT_specification,
+    ] {
+        if let Ok(Some(AttributeValue::UnitRef(offset))) = entry.attr_value(origin) {
+            let origin = unit.entry(offset).ok()?;
+            return attr(unit, &origin, name);
+        }
+    }
+    None
+}
+
+/// Reads a string attribute such as `DW_AT_name`
+pub fn attr_string(
+    dwarf: &Dwarf<Reader>,
+    unit: &Unit<Reader>,
+    entry: &DebuggingInformationEntry<Reader>,
+    name: DwAt,
+) -> Option<String> {
+    let value = attr(unit, entry, name)?;
+    let value = dwarf.attr_string(unit, value).ok()?;
+    Some(value.to_string_lossy().ok()?.into_owned())
+}
+
+/// Whether the tag is one of the qualifiers that don't change a type's layout
+pub fn is_qualifier(tag: DwTag) -> bool {
+    matches!(
+        tag,
+        constants::DW_TAG_const_type
+            | constants::DW_TAG_volatile_type
+            | constants::DW_TAG_restrict_type
+            | constants::DW_TAG_atomic_type
+    )
+}
+
+/// Whether any of the ranges of a unit or DIE contain the link-time PC
+pub fn contains(ranges: gimli::Result<gimli::RangeIter<Reader>>, pc: u64) -> bool {
+    let Ok(mut ranges) = ranges else {
+        return false;
+    };
+    while let Ok(Some(range)) = ranges.next() {
+        if range.begin <= pc && pc < range.end {
+            return true;
+        }
+    }
+    false
+}
+
+/// Reads the `size` byte value a `DW_OP_deref_size` or similar points to,
+/// which is at most a word
+fn deref(target: &dyn Target, addr: u64, size: u8) -> Result<u64> {
+    if size > 8 {
+        return Err(Error::Eval(format!("Cannot dereference {size} bytes")));
+    }
+    let bytes = memory::read(target, addr, size as usize)
+        .ok_or_else(|| Error::Eval(format!("Cannot access memory at {addr:#x}")))?;
+    let mut word = [0; 8];
+    word[..bytes.len()].copy_from_slice(&bytes);
+    Ok(u64::from_le_bytes(word))
+}
+
+/// Only the general purpose registers and rip (DWARF registers 0 to 16) are
+/// unwound, and outer frames only have the ones calls preserve
+fn read_register(frame: &Frame, dwarf_no: u16) -> Option<u64> {
+    if dwarf_no > 16 {
+        return None;
+    }
+    let reg = Register::from_selector(RegisterSelector::Dwarf(dwarf_no as i64))?;
+    if !frame.has(&reg) {
+        return None;
+    }
+    reg.read_from(&frame.regs)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockTarget;
+
+    #[test]
+    fn deref_reads_up_to_a_word() {
+        let target = MockTarget::new().map(0x1000, &[1, 2, 3, 4, 5, 6, 7, 8, 9]);
+
+        assert_eq!(deref(&target, 0x1000, 2).unwrap(), 0x0201);
+        assert_eq!(deref(&target, 0x1000, 8).unwrap(), 0x0807060504030201);
+    }
+
+    #[test]
+    fn deref_larger_than_a_word_is_an_error() {
+        let target = MockTarget::new().map(0x1000, &[0; 16]);
+
+        let e = deref(&target, 0x1000, 16).unwrap_err();
+        assert_eq!(e.to_string(), "Cannot dereference 16 bytes");
+    }
+}
@@ -0,0 +1,66 @@
+use std::fmt;
+
+use nix::errno::Errno;
+
+/// Everything that can go wrong while debugging. Errors are reported at the
+/// prompt, and the session carries on
+#[derive(Debug)]
+pub enum Error {
+    /// A command, location or expression that couldn't be parsed
+    Parse(String),
+    /// A breakpoint, frame, register, source file or symbol that doesn't exist
+    NotFound(String),
+    /// An expression that couldn't be evaluated, such as one reading memory
+    /// that isn't mapped
+    Eval(String),
+    /// The program isn't in a state the command can be used in, such as
+    /// there being no debug information where it stopped
+    State(String),
+    /// The program has exited, or isn't being traced
+    NoProcess,
+    /// A ptrace or wait call failed
+    Ptrace(Errno),
+    /// An executable or shared library that couldn't be read or parsed
+    Elf(String),
+    /// Debug information that couldn't be parsed
+    Dwarf(gimli::Error),
+    /// A connection to or from a remote debugger or an editor that failed, or
+    /// a message that didn't make sense
+    Remote(String),
+}
+
+pub type Result<T> = std::result::Result<T, Error>;
+
+impl fmt::Display for Error {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Parse(msg)
+            | Error::NotFound(msg)
+            | Error::Eval(msg)
+            | Error::State(msg)
+            | Error::Elf(msg)
+            | Error::Remote(msg) => write!(f, "{msg}"),
+            Error::NoProcess => write!(f, "The program is not being run"),
+            Error::Ptrace(errno) => write!(f, "ptrace failed: {}", errno.desc()),
+            Error::Dwarf(e) => write!(f, "Invalid debug information: {e}"),
+        }
+    }
+}
+
+impl std::error::Error for Error {}
+
+impl From<Errno> for Error {
+    fn from(errno: Errno) -> Self {
+        match errno {
+            // The process the call was made on, or waited for, is gone
+            Errno::ESRCH | Errno::ECHILD => Error::NoProcess,
+            errno => Error::Ptrace(errno),
+        }
+    }
+}
+
+impl From<gimli::Error> for Error {
+    fn from(e: gimli::Error) -> Self {
+        Error::Dwarf(e)
+    }
+}
@@ -0,0 +1,436 @@
+use nom::branch::alt;
+use nom::bytes::complete::{is_a, tag, tag_no_case, take_while1};
+use nom::character::complete::{char, digit1, hex_digit1, multispace0};
+use nom::combinator::{all_consuming, map, map_opt, map_res, opt, recognize};
+use nom::error::ErrorKind;
+use nom::multi::separated_list1;
+use nom::sequence::{delimited, pair, preceded, terminated, tuple};
+use nom::{Err, IResult};
+
+use crate::error::{Error, Result};
+
+/// An expression over the tracee's registers, memory and symbols, such as
+/// `*(u32 *)($rsp + 8) & 0xff`. Values are 64-bit integers
+#[derive(Clone)]
+pub enum Expr {
+    Number(u64),
+    /// A register, history value such as `$1` or convenience variable,
+    /// written `$name`
+    Variable(String),
+    /// A variable in the selected frame, or the address of a function
+    Symbol(String),
+    /// The value in memory at an address
+    Deref(Size, Box<Expr>),
+    Unary(UnaryOp, Box<Expr>),
+    Binary(BinaryOp, Box<Expr>, Box<Expr>),
+}
+
+/// How many bytes a dereference reads, and whether they are sign extended
+#[derive(Clone, Copy)]
+pub struct Size {
+    pub bytes: usize,
+    pub signed: bool,
+}
+
+#[derive(Clone, Copy)]
+pub enum UnaryOp {
+    /// `-`
+    Neg,
+    /// `~`
+    BitNot,
+    /// `!`
+    Not,
+}
+
+#[derive(Clone, Copy)]
+pub enum BinaryOp {
+    Mul,
+    Div,
+    Rem,
+    Add,
+    Sub,
+    Shl,
+    Shr,
+    Lt,
+    Le,
+    Gt,
+    Ge,
+    Eq,
+    Ne,
+    BitAnd,
+    BitXor,
+    BitOr,
+    And,
+    Or,
+}
+
+/// What an expression's variables, symbols and memory refer to
+pub trait Context {
+    /// Looks up a `$name`, or gives the reason there's no value for it
+    fn variable(&mut self, name: &str) -> Result<u64>;
+    fn symbol(&mut self, name: &str) -> Option<u64>;
+    fn read(&mut self, addr: u64, len: usize) -> Option<Vec<u8>>;
+}
+
+/// Binary operators, with the longer ones first so `<<` isn't read as `<`
+const OPERATORS: [(&str, BinaryOp); 18] = [
+    ("||", BinaryOp::Or),
+    ("&&", BinaryOp::And),
+    ("<<", BinaryOp::Shl),
+    (">>", BinaryOp::Shr),
+    ("<=", BinaryOp::Le),
+    (">=", BinaryOp::Ge),
+    ("==", BinaryOp::Eq),
+    ("!=", BinaryOp::Ne),
+    ("|", BinaryOp::BitOr),
+    ("^", BinaryOp::BitXor),
+    ("&", BinaryOp::BitAnd),
+    ("<", BinaryOp::Lt),
+    (">", BinaryOp::Gt),
+    ("+", BinaryOp::Add),
+    ("-", BinaryOp::Sub),
+    ("*", BinaryOp::Mul),
+    ("/", BinaryOp::Div),
+    ("%", BinaryOp::Rem),
+];
+
+impl Expr {
+    /// Computes the expression's value. Arithmetic wraps, and comparisons and
+    /// division treat values as signed
+    pub fn evaluate(&self, ctx: &mut impl Context) -> Result<u64> {
+        match self {
+            Expr::Number(value) => Ok(*value),
+            Expr::Variable(name) => ctx.variable(name),
+            Expr::Symbol(name) => ctx
+                .symbol(name)
+                .ok_or_else(|| Error::NotFound(format!("No symbol {name:?} in the current scope"))),
+            Expr::Deref(size, addr) => {
+                let addr = addr.evaluate(ctx)?;
+                match ctx.read(addr, size.bytes) {
+                    Some(bytes) if bytes.len() == size.bytes => Ok(size.extend(&bytes)),
+                    _ => Err(Error::Eval(format!("Cannot access memory at {addr:#x}"))),
+                }
+            }
+            Expr::Unary(op, expr) => {
+                let value = expr.evaluate(ctx)?;
+                Ok(match op {
+                    UnaryOp::Neg => value.wrapping_neg(),
+                    UnaryOp::BitNot => !value,
+                    UnaryOp::Not => (value == 0) as u64,
+                })
+            }
+            Expr::Binary(op, lhs, rhs) => {
+                let lhs = lhs.evaluate(ctx)?;
+                // `&&` and `||` stop early, so `p && *p` doesn't read through null
+                match op {
+                    BinaryOp::And if lhs == 0 => return Ok(0),
+                    BinaryOp::Or if lhs != 0 => return Ok(1),
+                    _ => {}
+                }
+                let rhs = rhs.evaluate(ctx)?;
+                op.apply(lhs, rhs)
+            }
+        }
+    }
+}
+
+impl Size {
+    const WORD: Size = Size {
+        bytes: 8,
+        signed: false,
+    };
+
+    /// The size of an integer type such as `u32` or `i8`
+    fn from_name(name: &str) -> Option<Size> {
+        let (signed, bits) = match name.split_at_checked(1)? {
+            ("u", bits) => (false, bits),
+            ("i", bits) => (true, bits),
+            _ => return None,
+        };
+        let bytes = match bits {
+            "8" => 1,
+            "16" => 2,
+            "32" => 4,
+            "64" => 8,
+            _ => return None,
+        };
+        Some(Size { bytes, signed })
+    }
+
+    /// Widens little endian bytes of this size to 64 bits
+    fn extend(&self, bytes: &[u8]) -> u64 {
+        let mut word = [0; 8];
+        word[..bytes.len()].copy_from_slice(bytes);
+        let value = u64::from_le_bytes(word);
+
+        let unused = 64 - 8 * bytes.len() as u32;
+        match self.signed {
+            true => ((value << unused) as i64 >> unused) as u64,
+            false => value,
+        }
+    }
+}
+
+impl BinaryOp {
+    /// How tightly the operator binds, following C
+    fn precedence(&self) -> u8 {
+        match self {
+            BinaryOp::Or => 1,
+            BinaryOp::And => 2,
+            BinaryOp::BitOr => 3,
+            BinaryOp::BitXor => 4,
+            BinaryOp::BitAnd => 5,
+            BinaryOp::Eq | BinaryOp::Ne => 6,
+            BinaryOp::Lt | BinaryOp::Le | BinaryOp::Gt | BinaryOp::Ge => 7,
+            BinaryOp::Shl | BinaryOp::Shr => 8,
+            BinaryOp::Add | BinaryOp::Sub => 9,
+            BinaryOp::Mul | BinaryOp::Div | BinaryOp::Rem => 10,
+        }
+    }
+
+    fn apply(&self, lhs: u64, rhs: u64) -> Result<u64> {
+        let (signed_lhs, signed_rhs) = (lhs as i64, rhs as i64);
+        // Shifting by the width or more shifts every bit out
+        let shift = |shift: fn(u64, u32) -> Option<u64>| {
+            u32::try_from(rhs)
+                .ok()
+                .and_then(|rhs| shift(lhs, rhs))
+                .unwrap_or(0)
+        };
+
+        Ok(match self {
+            BinaryOp::Mul => lhs.wrapping_mul(rhs),
+            BinaryOp::Div | BinaryOp::Rem if rhs == 0 => {
+                return Err(Error::Eval("Division by zero".into()))
+            }
+            BinaryOp::Div => signed_lhs.wrapping_div(signed_rhs) as u64,
+            BinaryOp::Rem => signed_lhs.wrapping_rem(signed_rhs) as u64,
+            BinaryOp::Add => lhs.wrapping_add(rhs),
+            BinaryOp::Sub => lhs.wrapping_sub(rhs),
+            BinaryOp::Shl => shift(u64::checked_shl),
+            BinaryOp::Shr => shift(u64::checked_shr),
+            BinaryOp::Lt => (signed_lhs < signed_rhs) as u64,
+            BinaryOp::Le => (signed_lhs <= signed_rhs) as u64,
+            BinaryOp::Gt => (signed_lhs > signed_rhs) as u64,
+            BinaryOp::Ge => (signed_lhs >= signed_rhs) as u64,
+            BinaryOp::Eq => (lhs == rhs) as u64,
+            BinaryOp::Ne => (lhs != rhs) as u64,
+            BinaryOp::BitAnd => lhs & rhs,
+            BinaryOp::BitXor => lhs ^ rhs,
+            BinaryOp::BitOr => lhs | rhs,
+            BinaryOp::And => (lhs != 0 && rhs != 0) as u64,
+            BinaryOp::Or => (lhs != 0 || rhs != 0) as u64,
+        })
+    }
+}
+
+/// Parses a whole expression
+pub fn parse(input: &str) -> Result<Expr> {
+    match all_consuming(terminated(expression, multispace0))(input) {
+        Ok((_, expr)) => Ok(expr),
+        Err(_) => Err(Error::Parse(format!(
+            "Invalid expression {:?}",
+            input.trim()
+        ))),
+    }
+}
+
+fn expression(input: &str) -> IResult<&str, Expr> {
+    binary(input, 0)
+}
+
+/// Parses operators that bind at least as tightly as `min`, each taking the
+/// operators that bind more tightly than it as its operands
+fn binary(input: &str, min: u8) -> IResult<&str, Expr> {
+    let (mut input, mut lhs) = unary(input)?;
+    while let Ok((rest, op)) = operator(input) {
+        if op.precedence() < min {
+            break;
+        }
+        let (rest, rhs) = binary(rest, op.precedence() + 1)?;
+        lhs = Expr::Binary(op, Box::new(lhs), Box::new(rhs));
+        input = rest;
+    }
+    Ok((input, lhs))
+}
+
+fn operator(input: &str) -> IResult<&str, BinaryOp> {
+    let (input, _) = multispace0(input)?;
+    OPERATORS
+        .iter()
+        .find_map(|(token, op)| Some((input.strip_prefix(token)?, *op)))
+        .ok_or(Err::Error(nom::error::Error::new(input, ErrorKind::Tag)))
+}
+
+fn unary(input: &str) -> IResult<&str, Expr> {
+    let op = |c, op| {
+        map(preceded(char(c), unary), move |expr| {
+            Expr::Unary(op, Box::new(expr))
+        })
+    };
+
+    let (input, _) = multispace0(input)?;
+    alt((
+        op('-', UnaryOp::Neg),
+        op('~', UnaryOp::BitNot),
+        op('!', UnaryOp::Not),
+        map(
+            pair(preceded(char('*'), opt(cast)), unary),
+            |(size, expr)| Expr::Deref(size.unwrap_or(Size::WORD), Box::new(expr)),
+        ),
+        primary,
+    ))(input)
+}
+
+/// Parses a pointer cast such as `(u32 *)`, giving the size it reads
+fn cast(input: &str) -> IResult<&str, Size> {
+    delimited(
+        pair(char('('), multispace0),
+        size,
+        tuple((multispace0, char('*'), multispace0, char(')'))),
+    )(input)
+}
+
+fn primary(input: &str) -> IResult<&str, Expr> {
+    let (input, _) = multispace0(input)?;
+    alt((
+        map(number, Expr::Number),
+        map(preceded(char('$'), identifier), |name| {
+            Expr::Variable(name.to_string())
+        }),
+        map(pair(opt(size), brackets), |(size, expr)| {
+            Expr::Deref(size.unwrap_or(Size::WORD), Box::new(expr))
+        }),
+        delimited(char('('), expression, preceded(multispace0, char(')'))),
+        map(symbol, |name| Expr::Symbol(name.to_string())),
+    ))(input)
+}
+
+/// Parses `[expr]`, which dereferences the address inside
+fn brackets(input: &str) -> IResult<&str, Expr> {
+    delimited(char('['), expression, preceded(multispace0, char(']')))(input)
+}
+
+fn size(input: &str) -> IResult<&str, Size> {
+    map_opt(identifier, Size::from_name)(input)
+}
+
+/// Parses a hex (`0x`), binary (`0b`) or decimal number
+fn number(input: &str) -> IResult<&str, u64> {
+    alt((
+        map_res(preceded(tag_no_case("0x"), hex_digit1), |digits| {
+            u64::from_str_radix(digits, 16)
+        }),
+        map_res(preceded(tag_no_case("0b"), is_a("01")), |digits| {
+            u64::from_str_radix(digits, 2)
+        }),
+        map_res(digit1, str::parse),
+    ))(input)
+}
+
+/// Parses a name, which may be a path such as `module::func`
+fn symbol(input: &str) -> IResult<&str, &str> {
+    recognize(separated_list1(tag("::"), identifier))(input)
+}
+
+fn identifier(input: &str) -> IResult<&str, &str> {
+    take_while1(|c: char| c.is_ascii_alphanumeric() || c == '_')(input)
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::HashMap;
+
+    use super::*;
+
+    /// Variables, symbols and memory starting at 0x1000
+    struct TestContext {
+        variables: HashMap<&'static str, u64>,
+        memory: Vec<u8>,
+    }
+
+    impl Context for TestContext {
+        fn variable(&mut self, name: &str) -> Result<u64> {
+            self.variables
+                .get(name)
+                .copied()
+                .ok_or_else(|| Error::NotFound(format!("No variable ${name}")))
+        }
+
+        fn symbol(&mut self, name: &str) -> Option<u64> {
+            (name == "main").then_some(0x1000)
+        }
+
+        fn read(&mut self, addr: u64, len: usize) -> Option<Vec<u8>> {
+            let offset = addr.checked_sub(0x1000)? as usize;
+            self.memory.get(offset..offset + len).map(<[u8]>::to_vec)
+        }
+    }
+
+    fn eval(input: &str) -> Result<u64> {
+        let mut ctx = TestContext {
+            variables: HashMap::from([("rsp", 0x1000), ("one", 1)]),
+            memory: vec![0xff, 0xff, 0xff, 0xff, 0x01, 0x02, 0x03, 0x04],
+        };
+        parse(input)?.evaluate(&mut ctx)
+    }
+
+    #[test]
+    fn arithmetic_follows_c_precedence() {
+        assert_eq!(eval("1 + 2 * 3").unwrap(), 7);
+        assert_eq!(eval("(1 + 2) * 3").unwrap(), 9);
+        assert_eq!(eval("1 << 4 | 1").unwrap(), 17);
+        assert_eq!(eval("10 - 4 - 3").unwrap(), 3);
+        assert_eq!(eval("1 + 2 == 3 && 0b11 > 2").unwrap(), 1);
+    }
+
+    #[test]
+    fn values_are_signed_for_comparison_and_division() {
+        assert_eq!(eval("-1 < 0").unwrap(), 1);
+        assert_eq!(eval("-7 / 2").unwrap() as i64, -3);
+        assert_eq!(eval("-7 % 2").unwrap() as i64, -1);
+        assert_eq!(eval("~0").unwrap(), u64::MAX);
+    }
+
+    #[test]
+    fn division_by_zero_is_an_error() {
+        assert!(matches!(eval("1 / 0"), Err(Error::Eval(_))));
+        assert!(matches!(eval("1 % 0"), Err(Error::Eval(_))));
+    }
+
+    #[test]
+    fn wide_shifts_give_zero() {
+        assert_eq!(eval("1 << 64").unwrap(), 0);
+        assert_eq!(eval("1 >> -1").unwrap(), 0);
+    }
+
+    #[test]
+    fn dereferences_read_sized_values() {
+        assert_eq!(eval("*$rsp").unwrap(), 0x04030201ffffffff);
+        // Only 4 of the 8 bytes are mapped
+        assert!(matches!(eval("[$rsp + 4]"), Err(Error::Eval(_))));
+        assert_eq!(eval("*(u32 *)($rsp + 4)").unwrap(), 0x04030201);
+        assert_eq!(eval("u8[main]").unwrap(), 0xff);
+        assert_eq!(eval("*(i16 *)$rsp").unwrap() as i64, -1);
+        assert!(matches!(eval("*0x2000"), Err(Error::Eval(_))));
+    }
+
+    #[test]
+    fn logical_operators_short_circuit() {
+        assert_eq!(eval("0 && *0").unwrap(), 0);
+        assert_eq!(eval("$one || *0").unwrap(), 1);
+    }
+
+    #[test]
+    fn unknown_names_are_errors() {
+        assert!(matches!(eval("$nope"), Err(Error::NotFound(_))));
+        assert!(matches!(eval("nope + 1"), Err(Error::NotFound(_))));
+    }
+
+    #[test]
+    fn malformed_expressions_are_errors() {
+        for input in ["", "1 +", "(1", "1 2", "*(u7 *)0", "$"] {
+            assert!(matches!(eval(input), Err(Error::Parse(_))), "{input}");
+        }
+    }
+}
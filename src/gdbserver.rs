@@ -0,0 +1,533 @@
+//! A gdbserver, which lets gdb or lldb debug a process through bkpt by
+//! speaking gdb's Remote Serial Protocol over TCP
+
+use std::collections::HashMap;
+use std::fs::File;
+use std::io::Write;
+use std::net::{TcpListener, TcpStream};
+use std::os::unix::fs::FileExt;
+use std::sync::mpsc::{self, Receiver};
+use std::thread;
+
+use nix::sys::signal::{kill, Signal};
+
+use crate::breakpoint::{Breakpoint, Location};
+use crate::error::{Error, Result};
+use crate::process::Process;
+use crate::rsp::{self, Event};
+use crate::target::{StopEvent, Target};
+
+/// Most bytes in a packet, which clients size their `m` and `M` packets by
+const PACKET_SIZE: usize = 0x4000;
+
+/// Sent back for a request that failed, such as reading unmapped memory
+const ERROR_REPLY: &str = "E01";
+
+/// Listens on `addr`, such as `:1234`, and serves the process to the first
+/// client that connects, until it kills or detaches from the process or
+/// disconnects
+pub fn serve(process: Process, addr: &str) -> Result<()> {
+    // Leaving out the host only listens on this machine
+    let addr = match addr.strip_prefix(':') {
+        Some(port) => format!("127.0.0.1:{port}"),
+        None => addr.to_string(),
+    };
+    let remote = |e: std::io::Error| Error::Remote(format!("Cannot listen on {addr}: {e}"));
+    let listener = TcpListener::bind(&addr).map_err(remote)?;
+    println!(
+        "Listening on port {}",
+        listener.local_addr().map_err(remote)?.port()
+    );
+
+    let (stream, peer) = listener.accept().map_err(remote)?;
+    println!("Remote debugging from host {}", peer.ip());
+    Server::new(process, stream)?.run()
+}
+
+struct Server {
+    process: Process,
+    stream: TcpStream,
+    /// Packets from the client, read on another thread so interrupts can be
+    /// handled while waiting for the process
+    events: Receiver<Event>,
+    /// Breakpoints inserted with `Z0`, by address
+    breakpoints: HashMap<u64, Breakpoint>,
+    /// The number the next breakpoint inserted gets, so none is reused
+    next_breakpoint: u32,
+    /// Files opened with `vFile:open`, by descriptor
+    files: HashMap<u64, File>,
+    /// Why the process last stopped, which `?` reports
+    stop: StopEvent,
+    /// Whether packets are acknowledged, which `QStartNoAckMode` turns off
+    ack: bool,
+    /// Whether the client understands the `swbreak` stop reason
+    swbreak: bool,
+    /// Set once the process is killed, exits or is detached from
+    done: bool,
+    /// Whether to leave the process running once the client is done
+    detached: bool,
+}
+
+impl Server {
+    fn new(process: Process, stream: TcpStream) -> Result<Server> {
+        // Packets are small and each waits for a reply, so send them at once
+        let _ = stream.set_nodelay(true);
+        let reader = stream
+            .try_clone()
+            .map_err(|e| Error::Remote(format!("Cannot read from the connection: {e}")))?;
+        let (sender, events) = mpsc::channel();
+        let pid = process.pid();
+        thread::spawn(move || {
+            let mut reader = rsp::Reader::new(reader);
+            while let Ok(Some(event)) = reader.next() {
+                // The client interrupts while the process runs, so this stops
+                // it without waiting for the server
+                if event == Event::Interrupt {
+                    let _ = kill(pid, Signal::SIGINT);
+                } else if sender.send(event).is_err() {
+                    break;
+                }
+            }
+        });
+
+        Ok(Server {
+            process,
+            stream,
+            events,
+            breakpoints: HashMap::new(),
+            next_breakpoint: 1,
+            files: HashMap::new(),
+            // A launched process starts stopped by the exec's SIGTRAP
+            stop: StopEvent::Signal(Signal::SIGTRAP),
+            ack: true,
+            swbreak: false,
+            done: false,
+            detached: false,
+        })
+    }
+
+    fn run(mut self) -> Result<()> {
+        let disconnected = |e| Error::Remote(format!("Lost the connection: {e}"));
+        while let Ok(event) = self.events.recv() {
+            let packet = match event {
+                Event::Packet(packet) => packet,
+                Event::Corrupt => {
+                    self.stream.write_all(b"-").map_err(disconnected)?;
+                    continue;
+                }
+                _ => continue,
+            };
+            if self.ack {
+                self.stream.write_all(b"+").map_err(disconnected)?;
+            }
+
+            let packet = String::from_utf8_lossy(&packet);
+            let reply = match self.handle(&packet) {
+                Ok(reply) => reply,
+                // The client asks again or gives up, so the process isn't lost
+                Err(_) => Some(ERROR_REPLY.into()),
+            };
+            if let Some(reply) = reply {
+                rsp::write_packet(&mut self.stream, &reply).map_err(disconnected)?;
+            }
+            if self.done {
+                break;
+            }
+        }
+        if self.detached {
+            self.process.detach()?;
+        }
+        Ok(())
+    }
+
+    /// Handles a packet, giving the reply to send, if any
+    fn handle(&mut self, packet: &str) -> Result<Option<Vec<u8>>> {
+        let mut chars = packet.chars();
+        let Some(kind) = chars.next() else {
+            return Ok(Some(Vec::new()));
+        };
+        let args = chars.as_str();
+        let reply = match kind {
+            '?' => self.stop_reply(),
+            'g' => {
+                let (regs, fpregs) = (self.process.regs()?, self.process.fpregs()?);
+                rsp::to_hex(&rsp::encode_registers(&rsp::layout(), &regs, &fpregs))
+            }
+            'G' => {
+                let bytes = rsp::from_hex(args).ok_or_else(invalid)?;
+                let (mut regs, mut fpregs) = (self.process.regs()?, self.process.fpregs()?);
+                rsp::decode_registers(&rsp::layout(), &bytes, &mut regs, &mut fpregs);
+                self.process.set_regs(regs)?;
+                self.process.set_fpregs(fpregs)?;
+                "OK".into()
+            }
+            'p' => {
+                let spec = register(args)?;
+                let (regs, fpregs) = (self.process.regs()?, self.process.fpregs()?);
+                let mut value = rsp::read_register(&spec.name, &regs, &fpregs).unwrap_or_default();
+                value.resize(spec.bits / 8, 0);
+                rsp::to_hex(&value)
+            }
+            'P' => {
+                let (num, value) = args.split_once('=').ok_or_else(invalid)?;
+                let spec = register(num)?;
+                let value = rsp::from_hex(value).ok_or_else(invalid)?;
+                let (mut regs, mut fpregs) = (self.process.regs()?, self.process.fpregs()?);
+                rsp::write_register(&spec.name, &value, &mut regs, &mut fpregs)
+                    .ok_or_else(invalid)?;
+                self.process.set_regs(regs)?;
+                self.process.set_fpregs(fpregs)?;
+                "OK".into()
+            }
+            'm' => {
+                let (addr, len) = parse_range(args)?;
+                let bytes = self.read_memory(addr, len.min(PACKET_SIZE / 2))?;
+                rsp::to_hex(&bytes)
+            }
+            'M' => {
+                let (range, data) = args.split_once(':').ok_or_else(invalid)?;
+                let (addr, _) = parse_range(range)?;
+                let bytes = rsp::from_hex(data).ok_or_else(invalid)?;
+                self.write_memory(addr, &bytes)?;
+                "OK".into()
+            }
+            'Z' | 'z' => {
+                let mut fields = args.split(',');
+                let (Some("0"), Some(addr)) = (fields.next(), fields.next()) else {
+                    // Only software breakpoints are supported
+                    return Ok(Some(Vec::new()));
+                };
+                let addr = parse_hex(addr)?;
+                if kind == 'Z' {
+                    self.insert_breakpoint(addr)?;
+                } else if let Some(mut bp) = self.breakpoints.remove(&addr) {
+                    bp.disable(&mut self.process)?;
+                }
+                "OK".into()
+            }
+            'c' | 's' => {
+                if !args.is_empty() {
+                    self.set_pc(parse_hex(args)?)?;
+                }
+                self.resume(kind == 's', None)?
+            }
+            'C' | 'S' => {
+                let (signal, addr) = args.split_once(';').unwrap_or((args, ""));
+                if !addr.is_empty() {
+                    self.set_pc(parse_hex(addr)?)?;
+                }
+                self.resume(kind == 'S', parse_signal(signal)?)?
+            }
+            'v' => return self.handle_v(args),
+            'q' => return self.handle_query(args),
+            'Q' if args == "StartNoAckMode" => {
+                self.ack = false;
+                "OK".into()
+            }
+            // Only the thread that last stopped can be selected or asked about
+            'H' | 'T' => "OK".into(),
+            'k' => {
+                self.done = true;
+                return Ok(None);
+            }
+            'D' => {
+                self.detach()?;
+                "OK".into()
+            }
+            _ => String::new(),
+        };
+        Ok(Some(reply.into_bytes()))
+    }
+
+    /// Handles the `v` packets, which are named rather than a single letter
+    fn handle_v(&mut self, args: &str) -> Result<Option<Vec<u8>>> {
+        let reply = if args == "Cont?" {
+            "vCont;c;C;s;S".into()
+        } else if let Some(actions) = args.strip_prefix("Cont;") {
+            // The first action for the thread that last stopped applies, as
+            // the others only run along with it
+            let tid = self.process.tid().as_raw();
+            let action = actions
+                .split(';')
+                .map(|action| action.split_once(':').unwrap_or((action, "-1")))
+                .find(|(_, thread)| {
+                    *thread == "-1" || i32::from_str_radix(thread, 16).ok() == Some(tid)
+                })
+                .map(|(action, _)| action)
+                .ok_or_else(invalid)?;
+            match action.split_at_checked(1).ok_or_else(invalid)? {
+                ("c", "") => self.resume(false, None)?,
+                ("s", "") => self.resume(true, None)?,
+                ("C", signal) => self.resume(false, parse_signal(signal)?)?,
+                ("S", signal) => self.resume(true, parse_signal(signal)?)?,
+                _ => return Err(invalid()),
+            }
+        } else if args.starts_with("Kill") {
+            self.done = true;
+            "OK".into()
+        } else if let Some(request) = args.strip_prefix("File:") {
+            return Ok(Some(self.host_io(request)));
+        } else {
+            String::new()
+        };
+        Ok(Some(reply.into_bytes()))
+    }
+
+    /// Handles the `vFile` packets, which let the client read files on this
+    /// machine, such as the process's memory map. Files can only be read
+    fn host_io(&mut self, request: &str) -> Vec<u8> {
+        let failed = |e: std::io::Error| format!("F-1,{:x}", e.raw_os_error().unwrap_or(5));
+        let reply = match request.split_once(':').unwrap_or((request, "")) {
+            ("open", args) => {
+                let path = args.split(',').next().and_then(rsp::from_hex);
+                let path = String::from_utf8_lossy(&path.unwrap_or_default()).into_owned();
+                match File::open(path) {
+                    Ok(file) => {
+                        let fd = (0..).find(|fd| !self.files.contains_key(fd)).unwrap();
+                        self.files.insert(fd, file);
+                        format!("F{fd:x}")
+                    }
+                    Err(e) => failed(e),
+                }
+            }
+            ("pread", args) => {
+                let args: Vec<_> = args.split(',').filter_map(|a| parse_hex(a).ok()).collect();
+                let [fd, len, offset] = args[..] else {
+                    return b"F-1,16".to_vec();
+                };
+                let Some(file) = self.files.get(&fd) else {
+                    // EBADF
+                    return b"F-1,9".to_vec();
+                };
+                let mut data = vec![0; (len as usize).min(PACKET_SIZE / 2)];
+                match file.read_at(&mut data, offset) {
+                    Ok(read) => {
+                        let header = format!("F{read:x};");
+                        return [header.as_bytes(), &data[..read]].concat();
+                    }
+                    Err(e) => failed(e),
+                }
+            }
+            ("close", fd) => match parse_hex(fd).ok().and_then(|fd| self.files.remove(&fd)) {
+                Some(_) => "F0".into(),
+                None => "F-1,9".into(),
+            },
+            _ => String::new(),
+        };
+        reply.into_bytes()
+    }
+
+    /// Handles the `q` packets, which ask about the server and the process
+    fn handle_query(&mut self, args: &str) -> Result<Option<Vec<u8>>> {
+        let tid = self.process.tid().as_raw();
+        let reply = if let Some(features) = args.strip_prefix("Supported") {
+            self.swbreak = features.split([':', ';']).any(|f| f == "swbreak+");
+            format!(
+                "PacketSize={PACKET_SIZE:x};qXfer:features:read+;qXfer:auxv:read+;\
+                 QStartNoAckMode+;swbreak+"
+            )
+        } else if let Some(range) = args.strip_prefix("Xfer:features:read:target.xml:") {
+            return Ok(Some(xfer(rsp::target_xml().as_bytes(), range)?));
+        } else if let Some(range) = args.strip_prefix("Xfer:auxv:read::") {
+            return Ok(Some(xfer(&self.process.auxv()?, range)?));
+        } else if args == "C" {
+            format!("QC{tid:x}")
+        } else if args == "fThreadInfo" {
+            format!("m{tid:x}")
+        } else if args == "sThreadInfo" {
+            "l".into()
+        } else if args.starts_with("Attached") {
+            // The server always starts the process
+            "0".into()
+        } else if args.starts_with("Symbol") {
+            "OK".into()
+        } else {
+            String::new()
+        };
+        Ok(Some(reply.into_bytes()))
+    }
+
+    /// The stop reply packet for why the process last stopped
+    fn stop_reply(&self) -> String {
+        let thread = format!("thread:{:x};", self.process.tid().as_raw());
+        match self.stop {
+            StopEvent::Breakpoint { .. } if self.swbreak => format!("T05swbreak:;{thread}"),
+            StopEvent::Breakpoint { .. } | StopEvent::Step { .. } => format!("T05{thread}"),
+            StopEvent::Signal(signal) => {
+                format!("T{:02x}{thread}", rsp::signal_to_gdb(signal))
+            }
+            StopEvent::Exited(code) => format!("W{:02x}", code as u8),
+            StopEvent::Killed(signal) => format!("X{:02x}", rsp::signal_to_gdb(signal)),
+        }
+    }
+
+    /// Runs or steps the process, giving the stop reply for where it stops
+    fn resume(&mut self, step: bool, mut signal: Option<Signal>) -> Result<String> {
+        self.stop = self.run_until_stop(step, &mut signal)?;
+        if let StopEvent::Exited(_) | StopEvent::Killed(_) = self.stop {
+            self.done = true;
+        }
+        Ok(self.stop_reply())
+    }
+
+    fn run_until_stop(&mut self, step: bool, signal: &mut Option<Signal>) -> Result<StopEvent> {
+        // Step off a breakpoint at the PC with its instruction put back, in
+        // case the client left it inserted
+        let pc = self.process.pc()?;
+        if let Some(bp) = self.breakpoints.get_mut(&pc) {
+            bp.disable(&mut self.process)?;
+            self.process.step_with(signal.take())?;
+            let stop = self.process.wait()?;
+            if let StopEvent::Exited(_) | StopEvent::Killed(_) = stop {
+                return Ok(stop);
+            }
+            bp.enable(&mut self.process)?;
+            if step || stop != StopEvent::Signal(Signal::SIGTRAP) {
+                return Ok(stop);
+            }
+        }
+
+        if step {
+            self.process.step_with(signal.take())?;
+            return self.process.wait();
+        }
+        self.process.resume_with(signal.take())?;
+        let stop = self.process.wait()?;
+
+        // A breakpoint's trap leaves the PC after its int3, which gdbserver
+        // moves back onto the breakpoint
+        if stop == StopEvent::Signal(Signal::SIGTRAP) {
+            let mut regs = self.process.regs()?;
+            if let Some(bp) = self.breakpoints.get(&(regs.rip - 1)) {
+                regs.rip = bp.addr;
+                self.process.set_regs(regs)?;
+                return Ok(StopEvent::Breakpoint {
+                    num: bp.num,
+                    addr: bp.addr,
+                });
+            }
+        }
+        Ok(stop)
+    }
+
+    fn insert_breakpoint(&mut self, addr: u64) -> Result<()> {
+        if self.breakpoints.contains_key(&addr) {
+            return Ok(());
+        }
+        let num = self.next_breakpoint;
+        let next = num
+            .checked_add(1)
+            .ok_or_else(|| Error::State("No breakpoint numbers left".into()))?;
+        let mut bp = Breakpoint::new(num, Location::Address(addr as isize), addr);
+        bp.enable(&mut self.process)?;
+        self.breakpoints.insert(addr, bp);
+        self.next_breakpoint = next;
+        Ok(())
+    }
+
+    /// Reads as much of the memory as is mapped, which the client expects
+    /// rather than an error when a read runs off the end of a mapping
+    fn read_memory(&self, addr: u64, len: usize) -> Result<Vec<u8>> {
+        let end = addr.checked_add(len as u64).ok_or_else(invalid)?;
+        let mut bytes = match self.process.read_memory(addr, len) {
+            Ok(bytes) => bytes,
+            Err(_) => (addr..end)
+                .map_while(|addr| self.process.read_memory(addr, 1).ok())
+                .flatten()
+                .collect(),
+        };
+        if bytes.is_empty() && len > 0 {
+            return Err(Error::Eval(format!("Cannot access memory at {addr:#x}")));
+        }
+        // Breakpoints are hidden from the client, as gdbserver does
+        for bp in self.breakpoints.values() {
+            bp.mask(addr, &mut bytes);
+        }
+        Ok(bytes)
+    }
+
+    /// Writes memory, keeping any breakpoints in it inserted
+    fn write_memory(&mut self, addr: u64, bytes: &[u8]) -> Result<()> {
+        let end = addr.checked_add(bytes.len() as u64).ok_or_else(invalid)?;
+        let range = addr..end;
+        let covered: Vec<u64> = self
+            .breakpoints
+            .keys()
+            .copied()
+            .filter(|addr| range.contains(addr))
+            .collect();
+        for addr in &covered {
+            self.breakpoints
+                .get_mut(addr)
+                .unwrap()
+                .disable(&mut self.process)?;
+        }
+        self.process.write_memory(addr, bytes)?;
+        for addr in &covered {
+            self.breakpoints
+                .get_mut(addr)
+                .unwrap()
+                .enable(&mut self.process)?;
+        }
+        Ok(())
+    }
+
+    fn set_pc(&mut self, pc: u64) -> Result<()> {
+        let mut regs = self.process.regs()?;
+        regs.rip = pc;
+        self.process.set_regs(regs)
+    }
+
+    /// Removes the breakpoints and lets the process carry on without the
+    /// debugger
+    fn detach(&mut self) -> Result<()> {
+        for bp in self.breakpoints.values_mut() {
+            bp.disable(&mut self.process)?;
+        }
+        self.breakpoints.clear();
+        self.done = true;
+        self.detached = true;
+        Ok(())
+    }
+}
+
+/// Replies to a `qXfer` read of `offset,length` of a document, which starts
+/// with `l` if it's the last part, or `m` if there's more
+fn xfer(document: &[u8], range: &str) -> Result<Vec<u8>> {
+    let (offset, len) = parse_range(range)?;
+    let offset = offset.min(document.len() as u64) as usize;
+    let end = document
+        .len()
+        .min(offset.checked_add(len).ok_or_else(invalid)?);
+    let more = if end < document.len() { b'm' } else { b'l' };
+    Ok([&[more], &document[offset..end]].concat())
+}
+
+fn invalid() -> Error {
+    Error::Remote("Invalid packet".into())
+}
+
+fn parse_hex(hex: &str) -> Result<u64> {
+    u64::from_str_radix(hex, 16).map_err(|_| invalid())
+}
+
+/// Parses `addr,length`, both in hex
+fn parse_range(range: &str) -> Result<(u64, usize)> {
+    let (addr, len) = range.split_once(',').ok_or_else(invalid)?;
+    Ok((parse_hex(addr)?, parse_hex(len)? as usize))
+}
+
+fn parse_signal(num: &str) -> Result<Option<Signal>> {
+    let num = u8::from_str_radix(num, 16).map_err(|_| invalid())?;
+    // 0 means no signal
+    Ok(rsp::signal_from_gdb(num))
+}
+
+/// The register a `p` or `P` packet's number refers to
+fn register(num: &str) -> Result<rsp::RegisterSpec> {
+    let num = parse_hex(num)? as usize;
+    rsp::layout()
+        .into_iter()
+        .nth(num)
+        .ok_or_else(|| Error::NotFound(format!("Invalid register {num}")))
+}
@@ -0,0 +1,133 @@
+//! `--interpreter=json`, for scripts and tools. Each command read from stdin
+//! gives one JSON object on its own line of stdout, which is a result, an
+//! error or a stop event, as `Debugger::execute_json` describes. Anything else
+//! printed, by bkpt or the program, comes as `output` objects, before the
+//! object of the command that was running when it was printed
+
+use std::fs::File;
+use std::io::{BufRead, BufReader, ErrorKind, Read, Write};
+use std::os::fd::AsRawFd;
+use std::sync::{Arc, Mutex};
+use std::thread;
+
+use nix::errno::Errno;
+use nix::libc;
+use serde_json::{json, Value};
+
+use crate::dap;
+use crate::debugger::Debugger;
+use crate::error::{Error, Result};
+
+/// Bytes of output given in each output object
+const OUTPUT_CHUNK: usize = 0x1000;
+
+/// Runs commands from stdin until it's closed, on the debugger `start` gives.
+/// The program reads nothing from stdin, so `start` should launch it only
+/// once stdio has been redirected
+pub fn serve_stdio(start: impl FnOnce() -> Result<Debugger>) -> Result<()> {
+    let (input, output, printed) = dap::redirect_stdio()?;
+    // Reads from the pipe don't block, so the last output can be drained
+    // before each command's object
+    Errno::result(unsafe { libc::fcntl(printed.as_raw_fd(), libc::F_SETFL, libc::O_NONBLOCK) })
+        .map_err(|e| Error::State(format!("Cannot redirect stdio: {}", e.desc())))?;
+    let stdout = Stdout(Arc::new(Mutex::new((
+        File::from(output),
+        File::from(printed),
+    ))));
+
+    let forwarder = stdout.clone();
+    thread::spawn(move || forwarder.forward());
+
+    let mut dbg = match start() {
+        Ok(dbg) => dbg,
+        Err(e) => {
+            stdout.send(json!({ "type": "error", "message": e.to_string() }));
+            return Ok(());
+        }
+    };
+    let pid = dbg
+        .target()
+        .threads()
+        .ok()
+        .and_then(|threads| Some(threads.first()?.tid));
+    stdout.send(json!({ "type": "started", "pid": pid }));
+
+    for line in BufReader::new(File::from(input)).lines() {
+        let Ok(line) = line else {
+            break;
+        };
+        if line.trim().is_empty() {
+            continue;
+        }
+        let object = dbg.execute_json(&line);
+        stdout.send(object);
+    }
+    Ok(())
+}
+
+/// The tool's end of stdout, and the pipe everything else printed goes to.
+/// Shared with the thread forwarding output
+#[derive(Clone)]
+struct Stdout(Arc<Mutex<(File, File)>>);
+
+impl Stdout {
+    /// Writes an object on its own line, after any output printed before it
+    fn send(&self, object: Value) {
+        // bkpt's own `print!`s are buffered until a newline
+        let _ = std::io::stdout().flush();
+        let mut stdout = self.0.lock().unwrap_or_else(|e| e.into_inner());
+        let (output, printed) = &mut *stdout;
+        drain(printed, output);
+        // The tool is gone if this fails, which the next read finds
+        let _ = writeln!(output, "{object}");
+        let _ = output.flush();
+    }
+
+    /// Forwards output as it's printed, such as while the program runs
+    fn forward(&self) {
+        loop {
+            let fd = self
+                .0
+                .lock()
+                .unwrap_or_else(|e| e.into_inner())
+                .1
+                .as_raw_fd();
+            let mut poll = libc::pollfd {
+                fd,
+                events: libc::POLLIN,
+                revents: 0,
+            };
+            match Errno::result(unsafe { libc::poll(&mut poll, 1, -1) }) {
+                Ok(_) | Err(Errno::EINTR) => {}
+                Err(_) => return,
+            }
+
+            let mut stdout = self.0.lock().unwrap_or_else(|e| e.into_inner());
+            let (output, printed) = &mut *stdout;
+            if !drain(printed, output) {
+                return;
+            }
+        }
+    }
+}
+
+/// Writes whatever has been printed as output objects. Returns false once
+/// nothing more can be printed
+fn drain(printed: &mut File, output: &mut File) -> bool {
+    let mut buf = [0; OUTPUT_CHUNK];
+    loop {
+        match printed.read(&mut buf) {
+            Ok(0) => return false,
+            Ok(len) => {
+                let object = json!({
+                    "type": "output",
+                    "output": String::from_utf8_lossy(&buf[..len]),
+                });
+                let _ = writeln!(output, "{object}");
+            }
+            Err(e) if e.kind() == ErrorKind::WouldBlock => return true,
+            Err(e) if e.kind() == ErrorKind::Interrupted => {}
+            Err(_) => return false,
+        }
+    }
+}
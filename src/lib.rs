@@ -0,0 +1,49 @@
+//! A debugger for x86-64 Linux programs, which can be driven from Rust as well
+//! as from the `bkpt` command line
+//!
+//! ```no_run
+//! use bkpt::{Debugger, Location, Process, StopEvent};
+//!
+//! let process = Process::launch("./hello", &[]).unwrap();
+//! let mut dbg = Debugger::new(process);
+//! dbg.set_breakpoint(Location::Function("main".into()), None)
+//!     .unwrap();
+//! assert!(matches!(dbg.continue_(), Ok(StopEvent::Breakpoint { num: 1, .. })));
+//! ```
+
+pub mod breakpoint;
+pub mod corefile;
+pub mod dap;
+pub mod debugger;
+pub mod error;
+pub mod gdbserver;
+pub mod interpreter;
+pub mod process;
+pub mod register;
+pub mod remote;
+pub mod target;
+
+mod demangle;
+mod disasm;
+mod dwarf;
+mod expr;
+mod memory;
+#[cfg(test)]
+mod mock;
+mod module;
+mod output;
+mod panic;
+mod pretty;
+mod rsp;
+mod source;
+mod types;
+mod unwind;
+
+pub use breakpoint::{Breakpoint, Location};
+pub use corefile::CoreFile;
+pub use debugger::{Debugger, StackFrame};
+pub use error::{Error, Result};
+pub use process::Process;
+pub use register::{Register, RegisterSelector};
+pub use remote::Remote;
+pub use target::{Mapping, NoTarget, Region, StopEvent, Target, Thread};
@@ -1,61 +1,144 @@
-use std::ffi::CString;
+use std::path::Path;
 
-use clap::Parser;
-use nix::sys::personality;
-use nix::sys::ptrace;
-use nix::unistd::{execvp, fork, ForkResult};
-
-mod debugger;
-use debugger::Debugger;
-
-mod breakpoint;
-
-mod register;
+use bkpt::{dap, gdbserver, interpreter, CoreFile, Debugger, NoTarget, Process};
+use clap::{Parser, ValueEnum};
+use rustyline::error::ReadlineError;
+use rustyline::DefaultEditor;
 
 #[derive(Debug, Parser)]
 struct Args {
-    /// Path to the exectuable to debug
-    command: String,
+    /// Path to the exectuable to debug. Without one, a program can be
+    /// debugged through a stub with `target remote`
+    command: Option<String>,
     /// Arguments to the executable
     argv: Option<Vec<String>>,
+    /// Inspect a core dump of the executable instead of running it
+    #[arg(long, requires = "command")]
+    core: Option<String>,
+    /// Let gdb or lldb debug the executable through bkpt, listening on an
+    /// address such as `:1234`
+    #[arg(long, value_name = "ADDRESS", requires = "command")]
+    gdbserver: Option<String>,
+    /// Let an editor debug programs through bkpt, speaking the Debug Adapter
+    /// Protocol on stdin and stdout
+    #[arg(long, conflicts_with = "command")]
+    dap: bool,
+    /// How commands are read and their output given: `console` for people,
+    /// or `json` for one JSON object per command on stdout
+    #[arg(
+        long,
+        value_enum,
+        default_value = "console",
+        conflicts_with_all = ["gdbserver", "dap"]
+    )]
+    interpreter: Interpreter,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, ValueEnum)]
+enum Interpreter {
+    Console,
+    Json,
 }
 
 fn main() {
     let args = Args::parse();
-    let cmd = args.command;
-    let argv = args.argv;
-
-    match unsafe { fork() } {
-        Err(_) => return,
-        Ok(ForkResult::Child) => {
-            // set this process to be traced
-            if let Err(e) = ptrace::traceme() {
-                println!("traceme call failed. error {e}");
-            }
 
-            let mut cmd = vec![CString::new(cmd).expect("CString::new failed")];
-            if let Some(argv) = argv {
-                let mut argv: Vec<CString> = argv
-                    .into_iter()
-                    .map(|arg| CString::new(arg).unwrap())
-                    .collect();
+    if args.dap {
+        if let Err(e) = dap::serve_stdio() {
+            eprintln!("{e}");
+        }
+        return;
+    }
 
-                cmd.append(&mut argv);
-            }
+    if args.interpreter == Interpreter::Json {
+        let argv = args.argv.unwrap_or_default();
+        let served = interpreter::serve_stdio(|| match (&args.command, &args.core) {
+            (None, _) => Ok(Debugger::new(NoTarget)),
+            (Some(command), None) => Ok(Debugger::new(Process::launch(command, &argv)?)),
+            (Some(command), Some(core)) => Ok(Debugger::new(CoreFile::open(
+                Path::new(core),
+                Path::new(command),
+            )?)),
+        });
+        if let Err(e) = served {
+            eprintln!("{e}");
+        }
+        return;
+    }
 
-            // Switch off address space layout randomization
-            let pers = personality::get().unwrap();
-            personality::set(pers | personality::Persona::ADDR_NO_RANDOMIZE).unwrap();
+    let Some(command) = &args.command else {
+        run(Debugger::new(NoTarget));
+        return;
+    };
 
-            if let Err(e) = execvp(&cmd[0], &cmd) {
-                println!("failed to call program. error: {e}");
-                return;
-            }
+    if let Some(addr) = &args.gdbserver {
+        let argv = args.argv.unwrap_or_default();
+        let served = Process::launch(command, &argv).and_then(|process| {
+            println!("Process {command} created; pid = {}", process.pid());
+            gdbserver::serve(process, addr)
+        });
+        if let Err(e) = served {
+            eprintln!("{e}");
         }
-        Ok(ForkResult::Parent { child }) => {
-            println!("start debugging proces for pid {child}");
-            let mut dbg = Debugger::new(child);
-            dbg.run();
+        return;
+    }
+
+    let dbg = match &args.core {
+        Some(core) => open_core(Path::new(core), Path::new(command)),
+        None => launch(command, &args.argv.unwrap_or_default()),
+    };
+    match dbg {
+        Ok(dbg) => run(dbg),
+        Err(e) => eprintln!("{e}"),
+    }
+}
+
+fn launch(command: &str, argv: &[String]) -> bkpt::Result<Debugger> {
+    let process = Process::launch(command, argv)?;
+    println!("start debugging proces for pid {}", process.pid());
+    Ok(Debugger::new(process))
+}
+
+/// Opens a core dump, showing where the program was when it was saved
+fn open_core(core: &Path, executable: &Path) -> bkpt::Result<Debugger> {
+    let core = CoreFile::open(core, executable)?;
+    if let Some(signal) = core.signal {
+        println!("Program terminated with {signal:?}");
+    }
+    let mut dbg = Debugger::new(core);
+    dbg.execute("frame")?;
+    Ok(dbg)
+}
+
+/// Reads commands until the user quits
+fn run(mut dbg: Debugger) {
+    let mut rl = match DefaultEditor::new() {
+        Ok(rl) => rl,
+        Err(e) => {
+            eprintln!("Cannot read commands: {e}");
+            return;
+        }
+    };
+    loop {
+        let readline = rl.readline(">>> ");
+        match readline {
+            Ok(line) => {
+                if let Err(e) = dbg.execute(&line) {
+                    eprintln!("{e}");
+                }
+            }
+            Err(ReadlineError::Interrupted) => {
+                println!("CTRL-C");
+                break;
+            }
+            Err(ReadlineError::Eof) => {
+                println!("CTRL-D");
+                break;
+            }
+            Err(e) => eprintln!("error: {:?}", e),
         }
     }
+    if let Err(e) = dbg.quit() {
+        eprintln!("{e}");
+    }
 }
@@ -0,0 +1,40 @@
+use crate::target::Target;
+
+/// The most memory a command reads at once, so a mistyped count is an error
+/// rather than an allocation the size of the address space
+pub const MAX_READ_LEN: usize = 4 << 20;
+
+/// Bytes set aside up front by `read_up_to`
+const PAGE_SIZE: usize = 4096;
+
+/// Reads a single 8 byte word from the target's memory
+pub fn read_word(target: &dyn Target, addr: u64) -> Option<u64> {
+    let bytes = target.read_memory(addr, 8).ok()?;
+    Some(u64::from_le_bytes(bytes.try_into().ok()?))
+}
+
+/// Reads `len` bytes from the target's memory. Lengths over `MAX_READ_LEN`,
+/// such as from an uninitialized collection, aren't read
+pub fn read(target: &dyn Target, addr: u64, len: usize) -> Option<Vec<u8>> {
+    if len > MAX_READ_LEN {
+        return None;
+    }
+    target.read_memory(addr, len).ok()
+}
+
+/// Reads up to `len` bytes from the target's memory, stopping early at the
+/// first word that can't be read, such as at the end of a mapping
+pub fn read_up_to(target: &dyn Target, addr: u64, len: usize) -> Vec<u8> {
+    // Grown as words are read, as a large read can stop early
+    let mut bytes = Vec::with_capacity(len.min(PAGE_SIZE));
+    let mut word_addr = Some(addr);
+    while bytes.len() < len {
+        let Some(word) = word_addr.and_then(|addr| read_word(target, addr)) else {
+            break;
+        };
+        bytes.extend_from_slice(&word.to_le_bytes());
+        word_addr = word_addr.and_then(|addr| addr.checked_add(8));
+    }
+    bytes.truncate(len);
+    bytes
+}
@@ -0,0 +1,167 @@
+use std::collections::VecDeque;
+
+use nix::libc::{user_fpregs_struct, user_regs_struct};
+use nix::sys::signal::Signal;
+
+use crate::breakpoint::Breakpoint;
+use crate::error::{Error, Result};
+use crate::target::{Mapping, StopEvent, Target};
+
+/// A target whose memory, registers and stops are set up by a test, so the
+/// debugger can be tested without running a process
+pub struct MockTarget {
+    /// Mapped memory, as the start address and contents of each region
+    memory: Vec<(u64, Vec<u8>)>,
+    pub regs: user_regs_struct,
+    pub fpregs: user_fpregs_struct,
+    /// Files mapped into memory
+    pub mappings: Vec<Mapping>,
+    /// What the program does after the instruction at the PC, in order
+    script: VecDeque<Scripted>,
+    /// The stop the next `wait` reports
+    stop: Option<StopEvent>,
+}
+
+/// An event in the program's run
+pub enum Scripted {
+    /// Executes the instruction at this address, trapping if it's an `int3`
+    Execute(u64),
+    Signal(Signal),
+    Exit(i32),
+}
+
+impl MockTarget {
+    pub fn new() -> MockTarget {
+        MockTarget {
+            memory: Vec::new(),
+            // Both are plain C structs, for which all zeroes is valid
+            regs: unsafe { std::mem::zeroed() },
+            fpregs: unsafe { std::mem::zeroed() },
+            mappings: Vec::new(),
+            script: VecDeque::new(),
+            stop: None,
+        }
+    }
+
+    /// Maps `bytes` into memory at `addr`
+    pub fn map(mut self, addr: u64, bytes: &[u8]) -> MockTarget {
+        self.memory.push((addr, bytes.to_vec()));
+        self
+    }
+
+    /// Sets where the program starts
+    pub fn at(mut self, pc: u64) -> MockTarget {
+        self.regs.rip = pc;
+        self
+    }
+
+    /// Adds events to the end of the program's run
+    pub fn script(mut self, events: impl IntoIterator<Item = Scripted>) -> MockTarget {
+        self.script.extend(events);
+        self
+    }
+
+    /// The region containing `len` bytes at `addr`, and the offset into it
+    fn region(&mut self, addr: u64, len: usize) -> Result<(&mut Vec<u8>, usize)> {
+        self.memory
+            .iter_mut()
+            .find_map(|(start, bytes)| {
+                let offset = addr.checked_sub(*start)? as usize;
+                (offset + len <= bytes.len()).then_some((bytes, offset))
+            })
+            .ok_or_else(|| Error::Eval(format!("Cannot access memory at {addr:#x}")))
+    }
+
+    /// Executes the instruction at the PC, giving whether it was an `int3`,
+    /// which leaves the PC after it like the CPU does
+    fn execute(&mut self) -> bool {
+        let pc = self.regs.rip;
+        let trapped = self.read_memory(pc, 1).ok() == Some(vec![Breakpoint::BKPT_OPCODE]);
+        if trapped {
+            self.regs.rip = pc + 1;
+            self.stop = Some(StopEvent::Signal(Signal::SIGTRAP));
+        }
+        trapped
+    }
+
+    /// Moves on to the next event in the script, giving the stop it causes
+    fn advance(&mut self) -> Option<StopEvent> {
+        match self.script.pop_front() {
+            Some(Scripted::Execute(addr)) => {
+                self.regs.rip = addr;
+                None
+            }
+            Some(Scripted::Signal(signal)) => Some(StopEvent::Signal(signal)),
+            Some(Scripted::Exit(code)) => Some(StopEvent::Exited(code)),
+            None => Some(StopEvent::Exited(0)),
+        }
+    }
+}
+
+impl Target for MockTarget {
+    fn read_memory(&self, addr: u64, len: usize) -> Result<Vec<u8>> {
+        self.memory
+            .iter()
+            .find_map(|(start, bytes)| {
+                let offset = addr.checked_sub(*start)? as usize;
+                bytes.get(offset..offset + len).map(<[u8]>::to_vec)
+            })
+            .ok_or_else(|| Error::Eval(format!("Cannot access memory at {addr:#x}")))
+    }
+
+    fn write_memory(&mut self, addr: u64, bytes: &[u8]) -> Result<()> {
+        let (region, offset) = self.region(addr, bytes.len())?;
+        region[offset..offset + bytes.len()].copy_from_slice(bytes);
+        Ok(())
+    }
+
+    fn regs(&self) -> Result<user_regs_struct> {
+        Ok(self.regs)
+    }
+
+    fn set_regs(&mut self, regs: user_regs_struct) -> Result<()> {
+        self.regs = regs;
+        Ok(())
+    }
+
+    fn fpregs(&self) -> Result<user_fpregs_struct> {
+        Ok(self.fpregs)
+    }
+
+    fn set_fpregs(&mut self, regs: user_fpregs_struct) -> Result<()> {
+        self.fpregs = regs;
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<()> {
+        if self.execute() {
+            return Ok(());
+        }
+        loop {
+            if let Some(stop) = self.advance() {
+                self.stop = Some(stop);
+                return Ok(());
+            }
+            if self.execute() {
+                return Ok(());
+            }
+        }
+    }
+
+    fn step(&mut self) -> Result<()> {
+        if self.execute() {
+            return Ok(());
+        }
+        let stop = self.advance();
+        self.stop = Some(stop.unwrap_or(StopEvent::Signal(Signal::SIGTRAP)));
+        Ok(())
+    }
+
+    fn wait(&mut self) -> Result<StopEvent> {
+        self.stop.take().ok_or(Error::NoProcess)
+    }
+
+    fn mappings(&self) -> Vec<Mapping> {
+        self.mappings.clone()
+    }
+}
@@ -0,0 +1,324 @@
+use std::collections::HashSet;
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+use std::rc::Rc;
+
+use gimli::{BaseAddresses, DebugFrame, Dwarf, EhFrame, EndianRcSlice, RunTimeEndian, Unit};
+use object::{Object, ObjectSection, ObjectSegment, ObjectSymbol, SymbolKind};
+
+use crate::demangle::{self, Demangled};
+use crate::error::{Error, Result};
+use crate::target::Target;
+
+pub type Reader = EndianRcSlice<RunTimeEndian>;
+
+/// An ELF image (the executable or a shared library) mapped into the tracee
+pub struct Module {
+    pub path: PathBuf,
+    /// Difference between the runtime and link-time addresses of the module
+    pub bias: u64,
+    /// Runtime address ranges the module's code is mapped at
+    ranges: Vec<Range<u64>>,
+    /// Function symbols, sorted by address, including every name an address
+    /// has, such as both `puts` and `_IO_puts`
+    symbols: Vec<Symbol>,
+    /// Indices into `symbols` of the one symbol used to name each address
+    named: Vec<usize>,
+    pub eh_frame: Option<EhFrame<Reader>>,
+    pub debug_frame: Option<DebugFrame<Reader>>,
+    /// Link-time section addresses used to decode pointers in `.eh_frame`
+    pub bases: BaseAddresses,
+    /// Debug information, if the module was built with it
+    pub dwarf: Option<Dwarf<Reader>>,
+    /// Compilation units in `.debug_info`
+    pub units: Vec<Unit<Reader>>,
+}
+
+struct Symbol {
+    /// The name as it appears in the symbol table
+    name: String,
+    demangled: Demangled,
+    /// Link-time address of the symbol
+    addr: u64,
+    size: u64,
+}
+
+impl Module {
+    /// Loads the module at `path`, whose first byte is mapped at `base`
+    pub fn load(path: &Path, base: u64, ranges: Vec<Range<u64>>) -> Result<Module> {
+        let data = fs::read(path)
+            .map_err(|e| Error::Elf(format!("Cannot read {}: {e}", path.display())))?;
+        let file = object::File::parse(&*data)
+            .map_err(|e| Error::Elf(format!("Cannot parse {}: {e}", path.display())))?;
+
+        let endian = if file.is_little_endian() {
+            RunTimeEndian::Little
+        } else {
+            RunTimeEndian::Big
+        };
+        let section = |name: &str| {
+            let section = file.section_by_name(name)?;
+            let data = section.uncompressed_data().ok()?;
+            Some(Reader::new(Rc::from(&*data), endian))
+        };
+        let address = |name: &str| file.section_by_name(name).map(|s| s.address());
+
+        // The segment at file offset 0 is the one mapped at the base address
+        let first = file
+            .segments()
+            .find(|segment| segment.file_range().0 == 0)
+            .map(|segment| segment.address())
+            .unwrap_or(0);
+
+        let mut bases = BaseAddresses::default();
+        if let Some(addr) = address(".eh_frame_hdr") {
+            bases = bases.set_eh_frame_hdr(addr);
+        }
+        if let Some(addr) = address(".eh_frame") {
+            bases = bases.set_eh_frame(addr);
+        }
+        if let Some(addr) = address(".text") {
+            bases = bases.set_text(addr);
+        }
+        if let Some(addr) = address(".got") {
+            bases = bases.set_got(addr);
+        }
+
+        let mut symbols: Vec<Symbol> = file
+            .symbols()
+            .chain(file.dynamic_symbols())
+            .filter(|sym| sym.kind() == SymbolKind::Text && sym.address() != 0)
+            .filter_map(|sym| {
+                let name = sym.name().ok()?;
+                Some(Symbol {
+                    name: name.to_string(),
+                    demangled: demangle::demangle(name),
+                    addr: sym.address(),
+                    size: sym.size(),
+                })
+            })
+            .collect();
+        symbols.sort_by_key(|sym| sym.addr);
+        let mut named: Vec<usize> = (0..symbols.len()).collect();
+        named.dedup_by_key(|idx| symbols[*idx].addr);
+
+        let dwarf = match file.section_by_name(".debug_info") {
+            Some(_) => Some(Dwarf::load(|id| {
+                let empty = || Reader::new(Rc::from(&[][..]), endian);
+                Ok::<_, gimli::Error>(section(id.name()).unwrap_or_else(empty))
+            })?),
+            None => None,
+        };
+        let mut units = Vec::new();
+        if let Some(dwarf) = &dwarf {
+            let mut headers = dwarf.units();
+            while let Some(header) = headers.next()? {
+                units.push(dwarf.unit(header)?);
+            }
+        }
+
+        Ok(Module {
+            path: path.to_path_buf(),
+            bias: base.wrapping_sub(first),
+            ranges,
+            symbols,
+            named,
+            eh_frame: section(".eh_frame").map(EhFrame::from),
+            debug_frame: section(".debug_frame").map(DebugFrame::from),
+            bases,
+            dwarf,
+            units,
+        })
+    }
+
+    /// A module whose code is mapped at `range`, with only function symbols,
+    /// given as `(name, address, size)` and sorted by address
+    #[cfg(test)]
+    pub(crate) fn with_symbols(range: Range<u64>, symbols: &[(&str, u64, u64)]) -> Module {
+        Module {
+            path: PathBuf::from("/bin/test"),
+            bias: 0,
+            ranges: vec![range],
+            symbols: symbols
+                .iter()
+                .map(|&(name, addr, size)| Symbol {
+                    name: name.to_string(),
+                    demangled: demangle::demangle(name),
+                    addr,
+                    size,
+                })
+                .collect(),
+            named: (0..symbols.len()).collect(),
+            eh_frame: None,
+            debug_frame: None,
+            bases: BaseAddresses::default(),
+            dwarf: None,
+            units: Vec::new(),
+        }
+    }
+
+    /// Whether the runtime address lies in the module's code
+    pub fn contains(&self, addr: u64) -> bool {
+        self.ranges.iter().any(|range| range.contains(&addr))
+    }
+
+    /// Finds the runtime address of the function symbol with the given name,
+    /// which may be mangled, demangled or the end of a path. Whole names are
+    /// preferred over the ends of paths
+    pub fn address_of(&self, name: &str) -> Option<u64> {
+        self.symbols
+            .iter()
+            .find(|sym| {
+                name == sym.name || name == sym.demangled.name || name == sym.demangled.path
+            })
+            .or_else(|| {
+                self.symbols
+                    .iter()
+                    .find(|sym| demangle::matches(name, &sym.name, &sym.demangled))
+            })
+            .map(|sym| sym.addr.wrapping_add(self.bias))
+    }
+
+    /// The runtime address just past the end of the function symbol that
+    /// starts at `addr`, if its size is known
+    pub fn function_end(&self, addr: u64) -> Option<u64> {
+        let link_addr = addr.wrapping_sub(self.bias);
+        let sym = self.symbols.iter().find(|sym| sym.addr == link_addr)?;
+        (sym.size != 0).then_some(addr + sym.size)
+    }
+
+    /// Finds the function symbol covering the runtime address, returning
+    /// its demangled name and the offset of the address into it
+    pub fn symbol_for(&self, addr: u64) -> Option<(&str, u64)> {
+        let addr = addr.wrapping_sub(self.bias);
+        let idx = self
+            .named
+            .partition_point(|&idx| self.symbols[idx].addr <= addr);
+        let sym = &self.symbols[self.named[idx.checked_sub(1)?]];
+
+        let offset = addr - sym.addr;
+        if sym.size != 0 && offset >= sym.size {
+            return None;
+        }
+        Some((&sym.demangled.name, offset))
+    }
+}
+
+/// Loads every module mapped into the target that isn't already loaded or
+/// known not to load, and drops the ones no longer mapped
+pub fn refresh(target: &dyn Target, modules: &mut Vec<Module>, unloadable: &mut HashSet<PathBuf>) {
+    let maps = target.mappings();
+    // Such as libraries closed with dlclose
+    modules.retain(|module| maps.iter().any(|m| m.path == module.path));
+
+    for mapping in &maps {
+        if unloadable.contains(&mapping.path)
+            || modules.iter().any(|module| module.path == mapping.path)
+        {
+            continue;
+        }
+
+        let Some(base) = maps
+            .iter()
+            .find(|m| m.path == mapping.path && m.offset == 0)
+            .map(|m| m.range.start)
+        else {
+            continue;
+        };
+        let ranges = maps
+            .iter()
+            .filter(|m| m.path == mapping.path && m.executable)
+            .map(|m| m.range.clone())
+            .collect();
+
+        // Files that aren't ELF images, such as locale data, and ones that
+        // can't be read are skipped, and not tried again
+        match Module::load(&mapping.path, base, ranges) {
+            Ok(module) => modules.push(module),
+            Err(_) => {
+                unloadable.insert(mapping.path.clone());
+            }
+        }
+    }
+}
+
+/// Finds the module whose code contains the runtime address
+pub fn find(modules: &[Module], addr: u64) -> Option<&Module> {
+    modules.iter().find(|module| module.contains(addr))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::mock::MockTarget;
+    use crate::target::Mapping;
+
+    fn mapped(path: &Path) -> MockTarget {
+        let mut target = MockTarget::new();
+        target.mappings.push(Mapping {
+            path: path.to_path_buf(),
+            range: 0x1000..0x2000,
+            executable: true,
+            offset: 0,
+        });
+        target
+    }
+
+    #[test]
+    fn whole_names_are_preferred_over_the_ends_of_paths() {
+        let module = Module::with_symbols(
+            0..0,
+            &[
+                ("_ZN8my_crate5other4func17h0123456789abcdefE", 0x1000, 0x10),
+                ("_ZN8my_crate6module4func17h0123456789abcdefE", 0x2000, 0x10),
+                ("func", 0x3000, 0x10),
+            ],
+        );
+
+        assert_eq!(module.address_of("func"), Some(0x3000));
+        assert_eq!(module.address_of("module::func"), Some(0x2000));
+        assert_eq!(module.address_of("my_crate::other::func"), Some(0x1000));
+        assert_eq!(module.address_of("ule::func"), None);
+    }
+
+    #[test]
+    fn ambiguous_ends_of_paths_give_the_lowest_address() {
+        let module = Module::with_symbols(
+            0..0,
+            &[
+                ("_ZN8my_crate5other4func17h0123456789abcdefE", 0x1000, 0x10),
+                ("_ZN8my_crate6module4func17h0123456789abcdefE", 0x2000, 0x10),
+            ],
+        );
+
+        assert_eq!(module.address_of("func"), Some(0x1000));
+    }
+
+    #[test]
+    fn files_that_are_not_elf_are_only_tried_once() {
+        let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("Cargo.toml");
+        let target = mapped(&path);
+        let mut modules = Vec::new();
+        let mut unloadable = HashSet::new();
+
+        refresh(&target, &mut modules, &mut unloadable);
+
+        assert!(modules.is_empty());
+        assert!(unloadable.contains(&path));
+    }
+
+    #[test]
+    fn modules_no_longer_mapped_are_dropped() {
+        let mut target = mapped(Path::new("/bin/true"));
+        let mut modules = Vec::new();
+        let mut unloadable = HashSet::new();
+
+        refresh(&target, &mut modules, &mut unloadable);
+        assert_eq!(modules.len(), 1);
+
+        target.mappings.clear();
+        refresh(&target, &mut modules, &mut unloadable);
+        assert!(modules.is_empty());
+    }
+}
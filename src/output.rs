@@ -0,0 +1,115 @@
+//! Where commands' output goes: printed as text at the console, or gathered
+//! into one JSON object per command for `--interpreter=json`
+
+use std::fmt::Display;
+
+use serde_json::{Map, Value};
+
+/// The output of the command being run
+#[derive(Default)]
+pub struct Output {
+    /// The command's JSON object so far, when not printing to the console
+    json: Option<Map<String, Value>>,
+}
+
+impl Output {
+    /// Starts gathering the next command's output as JSON
+    pub fn start_json(&mut self) {
+        self.json = Some(Map::new());
+    }
+
+    /// Stops gathering JSON, giving what the command produced
+    pub fn finish_json(&mut self) -> Map<String, Value> {
+        self.json.take().unwrap_or_default()
+    }
+
+    /// Prints a line, or gives it as a field of the JSON object
+    pub fn line(&mut self, text: impl Display, key: &str, value: Value) {
+        match &mut self.json {
+            Some(json) => {
+                json.insert(key.into(), value);
+            }
+            None => println!("{text}"),
+        }
+    }
+
+    /// Prints a line for each item, or gives them as an array, which is
+    /// there even when empty
+    pub fn list(&mut self, key: &str, items: impl IntoIterator<Item = (String, Value)>) {
+        let mut values = Vec::new();
+        for (text, value) in items {
+            match self.json {
+                Some(_) => values.push(value),
+                None => println!("{text}"),
+            }
+        }
+        if let Some(json) = &mut self.json {
+            json.insert(key.into(), Value::Array(values));
+        }
+    }
+
+    /// Gives a field that only JSON has, such as a detail the console leaves
+    /// for another command to show
+    pub fn field(&mut self, key: &str, value: Value) {
+        if let Some(json) = &mut self.json {
+            json.insert(key.into(), value);
+        }
+    }
+
+    /// Prints a line that only the console has, such as saying a list is
+    /// empty
+    pub fn text(&mut self, text: impl Display) {
+        if self.json.is_none() {
+            println!("{text}");
+        }
+    }
+
+    /// Prints a warning that doesn't stop the command, which JSON collects
+    /// in `messages`
+    pub fn note(&mut self, text: impl Display) {
+        let Some(json) = &mut self.json else {
+            println!("{text}");
+            return;
+        };
+        if let Value::Array(messages) = json
+            .entry("messages")
+            .or_insert_with(|| Value::Array(Vec::new()))
+        {
+            messages.push(Value::String(text.to_string()));
+        }
+    }
+}
+
+/// An address or other number as JSON, in hex like the console shows it
+pub fn hex(value: u64) -> Value {
+    Value::String(format!("{value:#x}"))
+}
+
+#[cfg(test)]
+mod tests {
+    use serde_json::json;
+
+    use super::*;
+
+    #[test]
+    fn gathers_fields_as_json() {
+        let mut out = Output::default();
+        out.start_json();
+        out.line("$1 = 2", "value", json!("2"));
+        out.list("variables", Vec::new());
+        out.text("No locals");
+        out.note("Cannot read source file a.c");
+        out.note("Cannot read source file b.c");
+
+        assert_eq!(
+            Value::Object(out.finish_json()),
+            json!({
+                "value": "2",
+                "variables": [],
+                "messages": ["Cannot read source file a.c", "Cannot read source file b.c"],
+            })
+        );
+        // Back to printing
+        assert!(out.finish_json().is_empty());
+    }
+}
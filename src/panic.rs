@@ -0,0 +1,164 @@
+use nix::libc::user_regs_struct;
+
+use crate::memory;
+use crate::module::{self, Module};
+use crate::target::Target;
+
+/// Functions that std calls when a panic starts, before running the panic
+/// hook and unwinding. Only `rust_begin_unwind` is passed the `PanicInfo`
+const HANDLERS: [&str; 2] = ["rust_begin_unwind", "rust_panic"];
+
+/// Stop decoding panic messages after this many bytes
+const MAX_MESSAGE_LEN: u64 = 4096;
+
+/// Finds the function std calls when a panic starts, returning its name and
+/// runtime address. Newer toolchains name it `__rustc::rust_begin_unwind`
+pub fn find_handler(modules: &[Module]) -> Option<(&'static str, u64)> {
+    HANDLERS.iter().find_map(|handler| {
+        let addr = modules
+            .iter()
+            .find_map(|module| module.address_of(handler))?;
+        Some((*handler, addr))
+    })
+}
+
+/// Describes the panic that is starting, given the registers at the entry of
+/// `rust_begin_unwind`, whose only argument is a `&PanicInfo`
+///
+/// std carries no type information for `PanicInfo`, so this relies on its
+/// layout: `{ message: &fmt::Arguments, location: &Location, .. }`
+pub fn describe(
+    target: &dyn Target,
+    modules: &[Module],
+    regs: &user_regs_struct,
+) -> Option<String> {
+    let info = regs.rdi;
+    let message = memory::read_word(target, info)?;
+    let location = memory::read_word(target, info + 8)?;
+
+    // Location is `{ file: &str, line: u32, col: u32 }`
+    let file_ptr = memory::read_word(target, location)?;
+    let file_len = memory::read_word(target, location + 8)?;
+    let line_col = memory::read_word(target, location + 16)?;
+    let file = read_str(target, file_ptr, file_len)?;
+    let (line, col) = (line_col as u32, (line_col >> 32) as u32);
+
+    let message = format_message(target, modules, message).unwrap_or_else(|| "<unknown>".into());
+    Some(format!("panicked at {file}:{line}:{col}:\n{message}"))
+}
+
+/// Formats a `fmt::Arguments`, which is `{ template: *const u8, args: usize }`.
+/// An odd `args` means the message is a plain string of length `args >> 1`.
+/// Otherwise `args` points to the arguments and the template is a sequence of
+/// literal pieces and placeholders, ended by a zero byte
+fn format_message(target: &dyn Target, modules: &[Module], arguments: u64) -> Option<String> {
+    let template = memory::read_word(target, arguments)?;
+    let args = memory::read_word(target, arguments + 8)?;
+    if args & 1 == 1 {
+        return read_str(target, template, args >> 1);
+    }
+
+    let mut message = String::new();
+    let mut pos = template;
+    let mut arg = args;
+    while pos - template < MAX_MESSAGE_LEN {
+        let byte = read_byte(target, pos)?;
+        pos += 1;
+        match byte {
+            0 => return Some(message),
+            // A literal of up to 127 bytes
+            1..=0x7f => {
+                message.push_str(&read_str(target, pos, byte as u64)?);
+                pos += byte as u64;
+            }
+            // A longer literal, with a 16 bit length
+            0x80 => {
+                let len =
+                    read_byte(target, pos)? as u64 | (read_byte(target, pos + 1)? as u64) << 8;
+                message.push_str(&read_str(target, pos + 2, len)?);
+                pos += 2 + len;
+            }
+            // A placeholder without formatting options, using the next argument
+            0xc0 => {
+                message.push_str(&format_argument(target, modules, arg));
+                arg += 16;
+            }
+            // Placeholders with options such as width or an explicit argument
+            _ => break,
+        }
+    }
+    message.push_str("...");
+    Some(message)
+}
+
+/// Formats an argument, which is `{ value: *const (), formatter: fn }`.
+/// The value's type is only known from the name of the formatter, so only
+/// integers and string slices are shown
+fn format_argument(target: &dyn Target, modules: &[Module], arg: u64) -> String {
+    let unknown = || "{?}".to_string();
+    let (Some(value), Some(formatter)) = (
+        memory::read_word(target, arg),
+        memory::read_word(target, arg + 8),
+    ) else {
+        return unknown();
+    };
+    let Some((name, 0)) = module::find(modules, formatter).and_then(|m| m.symbol_for(formatter))
+    else {
+        return unknown();
+    };
+
+    // v0 mangled names read `<T as core::fmt::Display>::fmt`, and legacy ones
+    // `core::fmt::num::imp::<impl core::fmt::Display for T>::fmt`
+    let Some(ty) = name
+        .strip_prefix('<')
+        .and_then(|rest| rest.strip_suffix(" as core::fmt::Display>::fmt"))
+        .or_else(|| {
+            let (_, rest) = name.split_once("<impl core::fmt::Display for ")?;
+            rest.strip_suffix(">::fmt")
+        })
+    else {
+        return unknown();
+    };
+
+    // The value of a `&str` argument is the `&str`
+    if ty == "&str" {
+        let ptr = memory::read_word(target, value);
+        let len = memory::read_word(target, value + 8);
+        return match ptr
+            .zip(len)
+            .and_then(|(ptr, len)| read_str(target, ptr, len))
+        {
+            Some(s) => s,
+            None => unknown(),
+        };
+    }
+
+    let (size, signed) = match ty {
+        "u8" => (1, false),
+        "u16" => (2, false),
+        "u32" => (4, false),
+        "u64" | "usize" => (8, false),
+        "i8" => (1, true),
+        "i16" => (2, true),
+        "i32" => (4, true),
+        "i64" | "isize" => (8, true),
+        _ => return unknown(),
+    };
+    let Some(word) = memory::read_word(target, value) else {
+        return unknown();
+    };
+    let shift = 64 - size * 8;
+    match signed {
+        true => (((word << shift) as i64) >> shift).to_string(),
+        false => ((word << shift) >> shift).to_string(),
+    }
+}
+
+fn read_byte(target: &dyn Target, addr: u64) -> Option<u8> {
+    memory::read(target, addr, 1).map(|bytes| bytes[0])
+}
+
+fn read_str(target: &dyn Target, addr: u64, len: u64) -> Option<String> {
+    let bytes = memory::read(target, addr, len.min(MAX_MESSAGE_LEN) as usize)?;
+    Some(String::from_utf8_lossy(&bytes).into_owned())
+}
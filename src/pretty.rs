@@ -0,0 +1,468 @@
+use gimli::UnitOffset;
+
+use crate::types::{Type, TypeKind, Types, MAX_ELEMENTS};
+
+/// Stop printing strings after this many bytes
+const MAX_STRING_LEN: u64 = 1024;
+
+/// Formats values of Rust standard library types the way `{:?}` would,
+/// rather than showing their internals. Returns `None` for other types
+pub fn format(types: &Types, ty: &Type, bytes: &[u8]) -> Option<String> {
+    // Pointer types such as `Box` are named with their full path
+    let base = ty.name.split('<').next()?;
+    let base = match base.starts_with(['&', '*']) {
+        true => base,
+        false => base.rsplit("::").next()?,
+    };
+    match base {
+        "String" => string(types, ty, bytes),
+        "&str" | "&mut str" => str_slice(types, ty, bytes),
+        "Vec" => vec(types, ty, bytes),
+        "Box" => boxed(types, ty, bytes),
+        "Rc" | "Arc" => counted(types, ty, bytes, base),
+        "HashMap" | "HashSet" => hash_table(types, ty, bytes, base == "HashSet"),
+        name if name.starts_with("&[") || name.starts_with("&mut [") => slice(types, ty, bytes),
+        _ => None,
+    }
+}
+
+/// `String` wraps a `Vec<u8>` of UTF-8
+fn string(types: &Types, ty: &Type, bytes: &[u8]) -> Option<String> {
+    let (vec_ty, vec) = types.field(ty, bytes, "vec")?;
+    let (addr, _) = types.find_pointer(&vec_ty, vec)?;
+    let (len_ty, len) = types.field(&vec_ty, vec, "len")?;
+    quoted(types, addr, types.uint(&len_ty, len)?)
+}
+
+/// `&str` is a pointer to the data and a length in bytes
+fn str_slice(types: &Types, ty: &Type, bytes: &[u8]) -> Option<String> {
+    let (ptr_ty, ptr) = types.field(ty, bytes, "data_ptr")?;
+    let (len_ty, len) = types.field(ty, bytes, "length")?;
+    let (addr, _) = types.find_pointer(&ptr_ty, ptr)?;
+    quoted(types, addr, types.uint(&len_ty, len)?)
+}
+
+fn quoted(types: &Types, addr: u64, len: u64) -> Option<String> {
+    let data = types.read_bytes(addr, len.min(MAX_STRING_LEN))?;
+    let text = String::from_utf8_lossy(&data);
+    if len > MAX_STRING_LEN {
+        Some(format!("{text:?}..."))
+    } else {
+        Some(format!("{text:?}"))
+    }
+}
+
+/// `&[T]` is a pointer to the first element and a length
+fn slice(types: &Types, ty: &Type, bytes: &[u8]) -> Option<String> {
+    let (ptr_ty, ptr) = types.field(ty, bytes, "data_ptr")?;
+    let (len_ty, len) = types.field(ty, bytes, "length")?;
+    let (addr, element) = types.find_pointer(&ptr_ty, ptr)?;
+    elements(types, element?, addr, types.uint(&len_ty, len)?)
+}
+
+/// `Vec<T>` keeps its elements behind a raw pointer to `u8` in its buffer, so
+/// the element type comes from the generic parameter
+fn vec(types: &Types, ty: &Type, bytes: &[u8]) -> Option<String> {
+    let element = types.template_param(ty, "T")?;
+    let (buf_ty, buf) = types.field(ty, bytes, "buf")?;
+    let (addr, _) = types.find_pointer(&buf_ty, buf)?;
+    let (len_ty, len) = types.field(ty, bytes, "len")?;
+    elements(types, element, addr, types.uint(&len_ty, len)?)
+}
+
+fn elements(types: &Types, element: UnitOffset, addr: u64, len: u64) -> Option<String> {
+    let size = types.decode(element)?.size;
+    let mut items = Vec::new();
+    for i in 0..len.min(MAX_ELEMENTS) {
+        let item = match types.read(element, addr.wrapping_add(i.wrapping_mul(size))) {
+            Some((ty, bytes)) => types.format_pointee(&ty, &bytes),
+            None => "<unreadable>".into(),
+        };
+        items.push(item);
+    }
+    if len > MAX_ELEMENTS {
+        items.push("...".into());
+    }
+    Some(format!("[{}]", items.join(", ")))
+}
+
+/// `Box<T>` of a sized `T` is described as a pointer type. Boxed slices and
+/// trait objects are fat pointers, which are left to the struct formatting
+fn boxed(types: &Types, ty: &Type, bytes: &[u8]) -> Option<String> {
+    let TypeKind::Pointer(Some(_)) = ty.kind else {
+        return None;
+    };
+    let (pointee, bytes) = types.deref(ty, bytes)?;
+    Some(format!("Box({})", types.format_pointee(&pointee, &bytes)))
+}
+
+/// `Rc<T>` and `Arc<T>` point to the reference counts followed by the value.
+/// Strong references collectively hold one weak reference, which isn't shown
+fn counted(types: &Types, ty: &Type, bytes: &[u8], name: &str) -> Option<String> {
+    let (addr, inner) = types.find_pointer(ty, bytes)?;
+    let (inner_ty, inner) = types.read(inner?, addr)?;
+    let (strong_ty, strong) = types.field(&inner_ty, &inner, "strong")?;
+    let (weak_ty, weak) = types.field(&inner_ty, &inner, "weak")?;
+    let strong = types.uint(&strong_ty, strong)?;
+    let weak = types.uint(&weak_ty, weak)?.saturating_sub(1);
+
+    let (value_ty, value) = types
+        .field(&inner_ty, &inner, "value")
+        .or_else(|| types.field(&inner_ty, &inner, "data"))?;
+    let value = types.format_pointee(&value_ty, value);
+    Some(format!("{name}(strong={strong}, weak={weak}) {value}"))
+}
+
+/// `HashMap` and `HashSet` wrap a hashbrown `RawTable`. Its control bytes
+/// have the top bit clear for full buckets, and bucket `i` is stored `i + 1`
+/// elements below the control bytes
+fn hash_table(types: &Types, ty: &Type, bytes: &[u8], is_set: bool) -> Option<String> {
+    let (table_ty, table) = raw_table(types, ty, bytes)?;
+    let element = types.template_param(&table_ty, "T")?;
+    let element_size = types.decode(element)?.size;
+
+    let (inner_ty, inner) = types.field(&table_ty, &table, "table")?;
+    let (ctrl_ty, ctrl) = types.field(&inner_ty, inner, "ctrl")?;
+    let (ctrl, _) = types.find_pointer(&ctrl_ty, ctrl)?;
+    let (mask_ty, mask) = types.field(&inner_ty, inner, "bucket_mask")?;
+    let (items_ty, items) = types.field(&inner_ty, inner, "items")?;
+    let items = types.uint(&items_ty, items)?;
+    if items == 0 {
+        return Some("{}".into());
+    }
+
+    let buckets = types.uint(&mask_ty, mask)?.checked_add(1)?;
+    let control = types.read_bytes(ctrl, buckets)?;
+    let mut entries = Vec::new();
+    for (i, _) in control.iter().enumerate().filter(|(_, c)| *c & 0x80 == 0) {
+        if entries.len() as u64 == MAX_ELEMENTS {
+            entries.push("...".into());
+            break;
+        }
+        let addr = ctrl.wrapping_sub((i as u64 + 1).wrapping_mul(element_size));
+        let Some((pair_ty, pair)) = types.read(element, addr) else {
+            entries.push("<unreadable>".into());
+            continue;
+        };
+        let key = types.field(&pair_ty, &pair, "__0");
+        let value = types.field(&pair_ty, &pair, "__1");
+        let entry = match (key, value) {
+            (Some((key_ty, key)), _) if is_set => types.format_pointee(&key_ty, key),
+            (Some((key_ty, key)), Some((value_ty, value))) => format!(
+                "{}: {}",
+                types.format_pointee(&key_ty, key),
+                types.format_pointee(&value_ty, value)
+            ),
+            _ => types.format_pointee(&pair_ty, &pair),
+        };
+        entries.push(entry);
+    }
+    Some(format!("{{{}}}", entries.join(", ")))
+}
+
+/// Finds the `RawTable` inside the std wrapper and hashbrown's own map types
+fn raw_table(types: &Types, ty: &Type, bytes: &[u8]) -> Option<(Type, Vec<u8>)> {
+    if let Some((table_ty, table)) = types.field(ty, bytes, "table") {
+        if let TypeKind::Struct(_) = table_ty.kind {
+            if table_ty.name.starts_with("RawTable<") {
+                return Some((table_ty, table.to_vec()));
+            }
+        }
+    }
+    ["base", "map"].iter().find_map(|name| {
+        let (inner_ty, inner) = types.field(ty, bytes, name)?;
+        raw_table(types, &inner_ty, inner)
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use std::rc::Rc;
+
+    use gimli::write::{self, DwarfUnit, EndianVec, Sections, UnitEntryId};
+    use gimli::{constants, DwAte, DwTag, Dwarf, RunTimeEndian, Unit};
+
+    use super::*;
+    use crate::mock::MockTarget;
+    use crate::module::Reader;
+
+    /// Builds the DWARF rustc gives for the standard library types
+    struct Dies {
+        unit: DwarfUnit,
+        u8: UnitEntryId,
+        u32: UnitEntryId,
+        usize: UnitEntryId,
+    }
+
+    impl Dies {
+        fn new() -> Dies {
+            let mut unit = DwarfUnit::new(gimli::Encoding {
+                format: gimli::Format::Dwarf32,
+                version: 5,
+                address_size: 8,
+            });
+            let root = unit.unit.root();
+            let mut base = |name: &str, size, encoding: DwAte| {
+                let id = unit.unit.add(root, constants::DW_TAG_base_type);
+                let entry = unit.unit.get_mut(id);
+                entry.set(
+                    constants::DW_AT_name,
+                    write::AttributeValue::String(name.into()),
+                );
+                entry.set(
+                    constants::DW_AT_byte_size,
+                    write::AttributeValue::Udata(size),
+                );
+                entry.set(
+                    constants::DW_AT_encoding,
+                    write::AttributeValue::Encoding(encoding),
+                );
+                id
+            };
+            let u8 = base("u8", 1, constants::DW_ATE_unsigned);
+            let u32 = base("u32", 4, constants::DW_ATE_unsigned);
+            let usize = base("usize", 8, constants::DW_ATE_unsigned);
+            Dies {
+                unit,
+                u8,
+                u32,
+                usize,
+            }
+        }
+
+        fn add(&mut self, parent: Option<UnitEntryId>, tag: DwTag, name: &str) -> UnitEntryId {
+            let parent = parent.unwrap_or(self.unit.unit.root());
+            let id = self.unit.unit.add(parent, tag);
+            let entry = self.unit.unit.get_mut(id);
+            entry.set(
+                constants::DW_AT_name,
+                write::AttributeValue::String(name.into()),
+            );
+            id
+        }
+
+        fn set(&mut self, id: UnitEntryId, name: gimli::DwAt, value: write::AttributeValue) {
+            self.unit.unit.get_mut(id).set(name, value);
+        }
+
+        fn structure(&mut self, name: &str, size: u64) -> UnitEntryId {
+            let id = self.add(None, constants::DW_TAG_structure_type, name);
+            self.set(
+                id,
+                constants::DW_AT_byte_size,
+                write::AttributeValue::Udata(size),
+            );
+            id
+        }
+
+        fn member(&mut self, parent: UnitEntryId, name: &str, ty: UnitEntryId, offset: u64) {
+            let id = self.add(Some(parent), constants::DW_TAG_member, name);
+            self.set(
+                id,
+                constants::DW_AT_type,
+                write::AttributeValue::UnitRef(ty),
+            );
+            self.set(
+                id,
+                constants::DW_AT_data_member_location,
+                write::AttributeValue::Udata(offset),
+            );
+        }
+
+        fn param(&mut self, parent: UnitEntryId, name: &str, ty: UnitEntryId) {
+            let id = self.add(
+                Some(parent),
+                constants::DW_TAG_template_type_parameter,
+                name,
+            );
+            self.set(
+                id,
+                constants::DW_AT_type,
+                write::AttributeValue::UnitRef(ty),
+            );
+        }
+
+        fn pointer(&mut self, name: &str, pointee: UnitEntryId) -> UnitEntryId {
+            let id = self.add(None, constants::DW_TAG_pointer_type, name);
+            self.set(
+                id,
+                constants::DW_AT_type,
+                write::AttributeValue::UnitRef(pointee),
+            );
+            id
+        }
+
+        /// `Vec<T>`, with its buffer behind a `*const u8` as in the standard library
+        fn vec(&mut self, name: &str, element: UnitEntryId) -> UnitEntryId {
+            let ptr = self.pointer("*const u8", self.u8);
+            let raw = self.structure("RawVec<u8>", 16);
+            self.member(raw, "ptr", ptr, 0);
+            self.member(raw, "cap", self.usize, 8);
+            let vec = self.structure(name, 24);
+            self.param(vec, "T", element);
+            self.member(vec, "buf", raw, 0);
+            self.member(vec, "len", self.usize, 16);
+            vec
+        }
+
+        fn finish(mut self) -> (Dwarf<Reader>, Unit<Reader>) {
+            let mut sections = Sections::new(EndianVec::new(RunTimeEndian::Little));
+            self.unit.write(&mut sections).unwrap();
+            let dwarf = Dwarf::load(|id| {
+                let data = sections.get(id).map_or(&[][..], |section| section.slice());
+                Ok::<_, gimli::Error>(Reader::new(Rc::from(data), RunTimeEndian::Little))
+            })
+            .unwrap();
+            let header = dwarf.units().next().unwrap().unwrap();
+            let unit = dwarf.unit(header).unwrap();
+            (dwarf, unit)
+        }
+    }
+
+    /// Finds a type by name in the unit that was built
+    fn named(dwarf: &Dwarf<Reader>, unit: &Unit<Reader>, name: &str) -> UnitOffset {
+        let mut entries = unit.entries();
+        while let Some((_, entry)) = entries.next_dfs().unwrap() {
+            if crate::dwarf::attr_string(dwarf, unit, entry, constants::DW_AT_name)
+                .is_some_and(|n| n == name)
+            {
+                return entry.offset();
+            }
+        }
+        panic!("No type {name}");
+    }
+
+    fn words(words: &[u64]) -> Vec<u8> {
+        words.iter().flat_map(|word| word.to_le_bytes()).collect()
+    }
+
+    fn format_value(dies: Dies, target: &MockTarget, name: &str, bytes: &[u8]) -> String {
+        let (dwarf, unit) = dies.finish();
+        let types = Types::new(target, &dwarf, &unit);
+        let ty = types.decode(named(&dwarf, &unit, name)).unwrap();
+        types.format(&ty, bytes)
+    }
+
+    #[test]
+    fn vecs_show_their_elements() {
+        let mut dies = Dies::new();
+        dies.vec("Vec<u32, alloc::alloc::Global>", dies.u32);
+        let target = MockTarget::new().map(0x2000, &[1, 0, 0, 0, 2, 0, 0, 0, 3, 0, 0, 0]);
+
+        let value = format_value(
+            dies,
+            &target,
+            "Vec<u32, alloc::alloc::Global>",
+            &words(&[0x2000, 4, 3]),
+        );
+
+        assert_eq!(value, "[1, 2, 3]");
+    }
+
+    #[test]
+    fn strings_are_quoted() {
+        let mut dies = Dies::new();
+        let vec = dies.vec("Vec<u8, alloc::alloc::Global>", dies.u8);
+        let string = dies.structure("alloc::string::String", 24);
+        dies.member(string, "vec", vec, 0);
+        let target = MockTarget::new().map(0x2000, b"hello\"");
+
+        let value = format_value(
+            dies,
+            &target,
+            "alloc::string::String",
+            &words(&[0x2000, 8, 6]),
+        );
+
+        assert_eq!(value, r#""hello\"""#);
+    }
+
+    #[test]
+    fn hash_maps_show_their_full_buckets() {
+        let mut dies = Dies::new();
+        let pair = dies.structure("(u32, u32)", 8);
+        dies.member(pair, "__0", dies.u32, 0);
+        dies.member(pair, "__1", dies.u32, 4);
+        let ptr = dies.pointer("*const u8", dies.u8);
+        let non_null = dies.structure("NonNull<u8>", 8);
+        dies.member(non_null, "pointer", ptr, 0);
+        let inner = dies.structure("RawTableInner", 32);
+        dies.member(inner, "bucket_mask", dies.usize, 0);
+        dies.member(inner, "ctrl", non_null, 8);
+        dies.member(inner, "growth_left", dies.usize, 16);
+        dies.member(inner, "items", dies.usize, 24);
+        let table = dies.structure("RawTable<(u32, u32), alloc::alloc::Global>", 32);
+        dies.param(table, "T", pair);
+        dies.member(table, "table", inner, 0);
+        let base = dies.structure("hashbrown::map::HashMap<u32, u32>", 32);
+        dies.member(base, "table", table, 0);
+        let map = dies.structure("std::collections::hash::map::HashMap<u32, u32>", 32);
+        dies.member(map, "base", base, 0);
+
+        // Four buckets below the control bytes, the last first, with 1 and 3 full
+        let mut memory = Vec::new();
+        for (key, value) in [(3u32, 30u32), (0, 0), (1, 10), (0, 0)] {
+            memory.extend(key.to_le_bytes());
+            memory.extend(value.to_le_bytes());
+        }
+        memory.extend([0xff, 0x12, 0xff, 0x05]);
+        let target = MockTarget::new().map(0x2fe0, &memory);
+
+        let value = format_value(
+            dies,
+            &target,
+            "std::collections::hash::map::HashMap<u32, u32>",
+            &words(&[3, 0x3000, 1, 2]),
+        );
+
+        assert_eq!(value, "{1: 10, 3: 30}");
+    }
+
+    #[test]
+    fn enums_show_their_active_variant() {
+        let mut dies = Dies::new();
+        let none = dies.structure("None", 8);
+        let some = dies.structure("Some", 8);
+        dies.member(some, "__0", dies.u32, 4);
+        let option = dies.structure("core::option::Option<u32>", 8);
+        let part = dies.add(Some(option), constants::DW_TAG_variant_part, "");
+        let discr = dies.add(Some(part), constants::DW_TAG_member, "");
+        dies.set(
+            discr,
+            constants::DW_AT_type,
+            write::AttributeValue::UnitRef(dies.u32),
+        );
+        dies.set(
+            discr,
+            constants::DW_AT_data_member_location,
+            write::AttributeValue::Udata(0),
+        );
+        dies.set(
+            part,
+            constants::DW_AT_discr,
+            write::AttributeValue::UnitRef(discr),
+        );
+        for (value, name, ty) in [(0, "None", none), (1, "Some", some)] {
+            let variant = dies.add(Some(part), constants::DW_TAG_variant, "");
+            dies.set(
+                variant,
+                constants::DW_AT_discr_value,
+                write::AttributeValue::Udata(value),
+            );
+            dies.member(variant, name, ty, 0);
+        }
+        let (dwarf, unit) = dies.finish();
+        let target = MockTarget::new();
+        let types = Types::new(&target, &dwarf, &unit);
+        let ty = types
+            .decode(named(&dwarf, &unit, "core::option::Option<u32>"))
+            .unwrap();
+
+        assert_eq!(types.format(&ty, &[1, 0, 0, 0, 5, 0, 0, 0]), "Some(5)");
+        assert_eq!(types.format(&ty, &[0; 8]), "None");
+        assert_eq!(
+            types.format(&ty, &[2, 0, 0, 0, 0, 0, 0, 0]),
+            "core::option::Option<u32> <invalid discriminant>"
+        );
+    }
+}
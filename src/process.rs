@@ -0,0 +1,510 @@
+use std::collections::BTreeMap;
+use std::ffi::{c_void, CString};
+use std::fs::{self, File};
+use std::mem::MaybeUninit;
+use std::os::unix::fs::FileExt;
+use std::ptr;
+use std::thread;
+use std::time::Duration;
+
+use nix::errno::Errno;
+use nix::libc::{self, user_fpregs_struct, user_regs_struct};
+use nix::sys::personality::{self, Persona};
+use nix::sys::ptrace::{self, AddressType, Options};
+use nix::sys::signal::{kill, Signal};
+use nix::sys::wait::{waitpid, WaitPidFlag, WaitStatus};
+use nix::unistd::{execvp, fork, ForkResult, Pid};
+
+use crate::breakpoint::Breakpoint;
+use crate::error::{Error, Result};
+use crate::target::{parse_maps, Mapping, Region, StopEvent, Target, Thread};
+
+/// How long to wait between checking each thread for a change of state
+const WAIT_INTERVAL: Duration = Duration::from_millis(1);
+
+/// A process being traced with ptrace, either started by the debugger or
+/// attached to. Dropping it kills a started process, and detaches from an
+/// attached one.
+///
+/// Every thread is traced, and they all stop whenever one of them does, so the
+/// program is stopped as a whole while the debugger looks at it. Only the
+/// thread that stopped is stepped, while the others stay stopped
+pub struct Process {
+    pid: Pid,
+    /// Whether the debugger started the process, rather than attaching to it
+    launched: bool,
+    /// The thread the debugger follows, which is the one that last stopped
+    tid: Pid,
+    threads: BTreeMap<Pid, Task>,
+}
+
+/// A thread of the process being traced
+#[derive(Default)]
+struct Task {
+    /// Whether it was let run, and hasn't been seen to stop since
+    running: bool,
+    /// Whether it was sent a SIGSTOP to stop it, which it stopped for another
+    /// reason before getting, so it will still stop with
+    stopping: bool,
+    /// A signal it stopped with, delivered when it next runs
+    signal: Option<Signal>,
+}
+
+impl Process {
+    /// Runs a program with arguments, stopped before its first instruction
+    pub fn launch(program: &str, args: &[String]) -> Result<Process> {
+        // Converted before forking, as the child can only exec or exit
+        let argv = std::iter::once(program)
+            .chain(args.iter().map(String::as_str))
+            .map(CString::new)
+            .collect::<std::result::Result<Vec<_>, _>>()
+            .map_err(|_| Error::Parse("Arguments can't contain NUL bytes".into()))?;
+
+        match unsafe { fork() } {
+            Err(e) => Err(Error::State(format!("Cannot start {program}: {e}"))),
+            Ok(ForkResult::Child) => {
+                // set this process to be traced
+                if let Err(e) = ptrace::traceme() {
+                    eprintln!("traceme call failed. error {e}");
+                    unsafe { libc::_exit(127) }
+                }
+
+                // Switch off address space layout randomization
+                if let Ok(pers) = personality::get() {
+                    let _ = personality::set(pers | Persona::ADDR_NO_RANDOMIZE);
+                }
+
+                // execvp only returns if it failed
+                let Err(e) = execvp(&argv[0], &argv);
+                eprintln!("failed to call program. error: {e}");
+                unsafe { libc::_exit(127) }
+            }
+            Ok(ForkResult::Parent { child }) => {
+                let mut process = Process {
+                    pid: child,
+                    launched: true,
+                    tid: child,
+                    threads: BTreeMap::from([(child, Task::default())]),
+                };
+                // The exec stops the child with a SIGTRAP, unless it failed
+                match waitpid(child, None)? {
+                    WaitStatus::Stopped(_, Signal::SIGTRAP) => {}
+                    _ => {
+                        process.launched = false;
+                        return Err(Error::State(format!("Cannot start {program}")));
+                    }
+                }
+                // Threads it starts are traced too, and it's killed if the
+                // debugger dies
+                ptrace::setoptions(
+                    child,
+                    Options::PTRACE_O_TRACECLONE | Options::PTRACE_O_EXITKILL,
+                )?;
+                Ok(process)
+            }
+        }
+    }
+
+    /// Starts tracing a running process and each of its threads, stopping
+    /// them
+    pub fn attach(pid: Pid) -> Result<Process> {
+        ptrace::attach(pid)?;
+        let mut process = Process {
+            pid,
+            launched: false,
+            tid: pid,
+            threads: BTreeMap::from([(pid, Task::default())]),
+        };
+        waitpid(pid, Some(WaitPidFlag::__WALL))?;
+        ptrace::setoptions(pid, Options::PTRACE_O_TRACECLONE)?;
+
+        let tids = fs::read_dir(format!("/proc/{pid}/task"))
+            .into_iter()
+            .flatten()
+            .filter_map(|task| task.ok()?.file_name().to_str()?.parse().ok())
+            .map(Pid::from_raw);
+        for tid in tids.filter(|&tid| tid != pid) {
+            // Threads that exit in the meantime are left out
+            if ptrace::attach(tid).is_err() {
+                continue;
+            }
+            if waitpid(tid, Some(WaitPidFlag::__WALL)).is_ok() {
+                let _ = ptrace::setoptions(tid, Options::PTRACE_O_TRACECLONE);
+                process.threads.insert(tid, Task::default());
+            }
+        }
+        Ok(process)
+    }
+
+    pub fn pid(&self) -> Pid {
+        self.pid
+    }
+
+    /// The thread the debugger follows, which is the one that last stopped
+    pub fn tid(&self) -> Pid {
+        self.tid
+    }
+
+    /// Lets the program run, delivering a signal to the thread that stopped
+    /// instead of the one it stopped with. Other threads get any signal they
+    /// stopped with
+    pub fn resume_with(&mut self, signal: Option<Signal>) -> Result<()> {
+        if let Some(task) = self.threads.get_mut(&self.tid) {
+            task.signal = signal;
+        }
+        for (&tid, task) in &mut self.threads {
+            let res = ptrace::cont(tid, task.signal.take());
+            match res {
+                Ok(()) => task.running = true,
+                Err(e) if tid == self.tid => return Err(e.into()),
+                // Exited, which the next wait finds
+                Err(_) => {}
+            }
+        }
+        Ok(())
+    }
+
+    /// Lets the thread that stopped execute a single instruction, delivering a
+    /// signal to it first instead of the one it stopped with. The other
+    /// threads stay stopped
+    pub fn step_with(&mut self, signal: Option<Signal>) -> Result<()> {
+        ptrace::step(self.tid, signal)?;
+        if let Some(task) = self.threads.get_mut(&self.tid) {
+            task.signal = None;
+            task.running = true;
+        }
+        Ok(())
+    }
+
+    /// The signal the thread that stopped will get when it next runs
+    fn pending(&self) -> Option<Signal> {
+        self.threads.get(&self.tid).and_then(|task| task.signal)
+    }
+
+    /// Starts tracking a thread the process created, once it has stopped
+    /// before its first instruction. Gives whether it's new
+    fn add_thread(&mut self, tid: Pid) -> bool {
+        if self.threads.contains_key(&tid) {
+            return false;
+        }
+        // Its first stop can come before or after the clone event
+        let _ = waitpid(tid, Some(WaitPidFlag::__WALL | WaitPidFlag::__WNOTHREAD));
+        self.threads.insert(tid, Task::default());
+        true
+    }
+
+    /// Stops every thread still running other than the one that stopped, so
+    /// the program is stopped as a whole
+    fn stop_others(&mut self) {
+        let running: Vec<Pid> = self
+            .threads
+            .iter()
+            .filter(|(&tid, task)| tid != self.tid && task.running)
+            .map(|(&tid, _)| tid)
+            .collect();
+        for &tid in &running {
+            // Ones already sent a SIGSTOP still have it coming
+            if !self.threads[&tid].stopping {
+                unsafe { libc::tgkill(self.pid.as_raw(), tid.as_raw(), libc::SIGSTOP) };
+            }
+        }
+
+        for tid in running {
+            let status = waitpid(tid, Some(WaitPidFlag::__WALL | WaitPidFlag::__WNOTHREAD));
+            let task = self.threads.entry(tid).or_default();
+            task.running = false;
+            // Stopping for anything else leaves the SIGSTOP to come
+            task.stopping = true;
+            match status {
+                Ok(WaitStatus::Stopped(_, Signal::SIGSTOP)) => task.stopping = false,
+                Ok(WaitStatus::Stopped(_, Signal::SIGTRAP)) => rewind_breakpoint(tid),
+                Ok(WaitStatus::Stopped(_, signal)) => task.signal = Some(signal),
+                Ok(WaitStatus::PtraceEvent(_, _, libc::PTRACE_EVENT_CLONE)) => {
+                    if let Ok(new) = ptrace::getevent(tid) {
+                        self.add_thread(Pid::from_raw(new as i32));
+                    }
+                }
+                Ok(WaitStatus::PtraceEvent(..)) => {}
+                Ok(_) | Err(_) => {
+                    self.threads.remove(&tid);
+                }
+            }
+        }
+    }
+
+    /// Waits for one of the process's threads to change state. Only they are
+    /// waited for, so the debugger's other children are left to whoever
+    /// started them
+    fn wait_thread(&self) -> Result<WaitStatus> {
+        let flags = WaitPidFlag::__WALL | WaitPidFlag::__WNOTHREAD;
+        let mut running = self.threads.iter().filter(|(_, task)| task.running);
+        // Such as when stepping, which needs to be quick
+        if let (Some((&tid, _)), None) = (running.next(), running.next()) {
+            return Ok(waitpid(tid, Some(flags))?);
+        }
+        loop {
+            let mut alive = false;
+            for &tid in self.threads.keys() {
+                match waitpid(tid, Some(flags | WaitPidFlag::WNOHANG)) {
+                    Ok(WaitStatus::StillAlive) => alive = true,
+                    Ok(status) => return Ok(status),
+                    // Gone without a status left to wait for
+                    Err(Errno::ECHILD) => {}
+                    Err(e) => return Err(e.into()),
+                }
+            }
+            if !alive {
+                return Err(Errno::ECHILD.into());
+            }
+            thread::sleep(WAIT_INTERVAL);
+        }
+    }
+
+    fn read_word(&self, addr: u64) -> Result<u64> {
+        Ok(ptrace::read(self.tid, addr as AddressType)? as u64)
+    }
+}
+
+/// Moves a thread that hit a breakpoint back onto it, for when another thread
+/// stopped first. It hits it again when it next runs, unless the breakpoint
+/// has been removed by then
+fn rewind_breakpoint(tid: Pid) {
+    let Ok(mut regs) = ptrace::getregs(tid) else {
+        return;
+    };
+    let addr = regs.rip.wrapping_sub(1);
+    let Ok(word) = ptrace::read(tid, addr as AddressType) else {
+        return;
+    };
+    if word as u8 == Breakpoint::BKPT_OPCODE {
+        regs.rip = addr;
+        let _ = ptrace::setregs(tid, regs);
+    }
+}
+
+impl Target for Process {
+    fn read_memory(&self, addr: u64, len: usize) -> Result<Vec<u8>> {
+        let unreadable = |at: u64| Error::Eval(format!("Cannot access memory at {at:#x}"));
+        // Much faster than ptrace, which reads a word at a time
+        let mem = File::open(format!("/proc/{}/mem", self.pid)).map_err(|_| unreadable(addr))?;
+        let mut bytes = vec![0; len];
+        let mut read = 0;
+        while read < len {
+            let at = addr.wrapping_add(read as u64);
+            match mem.read_at(&mut bytes[read..], at) {
+                Ok(count) if count > 0 => read += count,
+                _ => return Err(unreadable(at)),
+            }
+        }
+        Ok(bytes)
+    }
+
+    fn write_memory(&mut self, addr: u64, bytes: &[u8]) -> Result<()> {
+        for (word_addr, chunk) in (addr..).step_by(8).zip(bytes.chunks(8)) {
+            // A partial word keeps the bytes after it
+            let mut word = match chunk.len() {
+                8 => [0; 8],
+                _ => self.read_word(word_addr)?.to_le_bytes(),
+            };
+            word[..chunk.len()].copy_from_slice(chunk);
+            let word = u64::from_le_bytes(word);
+            unsafe {
+                ptrace::write(self.tid, word_addr as AddressType, word as *mut c_void)?;
+            }
+        }
+        Ok(())
+    }
+
+    fn regs(&self) -> Result<user_regs_struct> {
+        Ok(ptrace::getregs(self.tid)?)
+    }
+
+    fn set_regs(&mut self, regs: user_regs_struct) -> Result<()> {
+        Ok(ptrace::setregs(self.tid, regs)?)
+    }
+
+    fn fpregs(&self) -> Result<user_fpregs_struct> {
+        getfpregs(self.tid)
+    }
+
+    fn set_fpregs(&mut self, mut regs: user_fpregs_struct) -> Result<()> {
+        let res = unsafe {
+            libc::ptrace(
+                libc::PTRACE_SETFPREGS,
+                self.tid.as_raw(),
+                ptr::null_mut::<c_void>(),
+                &mut regs as *mut user_fpregs_struct,
+            )
+        };
+        Errno::result(res)?;
+        Ok(())
+    }
+
+    fn resume(&mut self) -> Result<()> {
+        self.resume_with(self.pending())
+    }
+
+    fn attached(&self) -> bool {
+        !self.launched
+    }
+
+    /// Stops tracing every thread, leaving the process running even if the
+    /// debugger started it
+    fn detach(&mut self) -> Result<()> {
+        for (&tid, task) in &mut self.threads {
+            let _ = ptrace::detach(tid, task.signal.take());
+        }
+        self.threads.clear();
+        self.launched = false;
+        Ok(())
+    }
+
+    fn step(&mut self) -> Result<()> {
+        self.step_with(self.pending())
+    }
+
+    fn wait(&mut self) -> Result<StopEvent> {
+        loop {
+            let status = self.wait_thread()?;
+            let Some(tid) = status.pid() else {
+                return Err(Error::State(format!("Unexpected wait status {status:?}")));
+            };
+            match status {
+                WaitStatus::Exited(_, code) if tid == self.pid => {
+                    self.threads.clear();
+                    return Ok(StopEvent::Exited(code));
+                }
+                WaitStatus::Signaled(_, signal, _) if tid == self.pid => {
+                    self.threads.clear();
+                    return Ok(StopEvent::Killed(signal));
+                }
+                WaitStatus::Exited(..) | WaitStatus::Signaled(..) => {
+                    self.threads.remove(&tid);
+                    if tid == self.tid {
+                        self.tid = self.pid;
+                    }
+                }
+                WaitStatus::PtraceEvent(_, _, libc::PTRACE_EVENT_CLONE) => {
+                    let new = Pid::from_raw(ptrace::getevent(tid)? as i32);
+                    if self.add_thread(new) {
+                        let _ = ptrace::cont(new, None);
+                        self.threads.entry(new).or_default().running = true;
+                    }
+                    ptrace::cont(tid, None)?;
+                }
+                WaitStatus::PtraceEvent(..) => ptrace::cont(tid, None)?,
+                // The SIGSTOP sent to stop a thread that had already stopped
+                // for something else
+                WaitStatus::Stopped(_, Signal::SIGSTOP)
+                    if self.threads.get(&tid).is_some_and(|task| task.stopping) =>
+                {
+                    let task = self.threads.entry(tid).or_default();
+                    task.stopping = false;
+                    ptrace::cont(tid, None)?;
+                }
+                WaitStatus::Stopped(_, signal) => {
+                    self.tid = tid;
+                    let task = self.threads.entry(tid).or_default();
+                    task.running = false;
+                    // The debugger's own traps, and interrupts from the
+                    // terminal, are for the debugger rather than the program
+                    if !matches!(signal, Signal::SIGTRAP | Signal::SIGINT) {
+                        task.signal = Some(signal);
+                    }
+                    self.stop_others();
+                    return Ok(StopEvent::Signal(signal));
+                }
+                status => return Err(Error::State(format!("Unexpected wait status {status:?}"))),
+            }
+        }
+    }
+
+    fn mappings(&self) -> Vec<Mapping> {
+        self.regions()
+            .into_iter()
+            .filter_map(|region| {
+                Some(Mapping {
+                    path: region.path?,
+                    range: region.range,
+                    executable: region.executable,
+                    offset: region.offset,
+                })
+            })
+            .collect()
+    }
+
+    fn threads(&self) -> Result<Vec<Thread>> {
+        let mut threads = vec![Thread {
+            tid: self.tid.as_raw(),
+            regs: self.regs()?,
+            fpregs: Some(self.fpregs()?),
+        }];
+        // The others are all stopped too
+        for &tid in self.threads.keys().filter(|&&tid| tid != self.tid) {
+            if let Ok(regs) = ptrace::getregs(tid) {
+                threads.push(Thread {
+                    tid: tid.as_raw(),
+                    regs,
+                    fpregs: getfpregs(tid).ok(),
+                });
+            }
+        }
+        Ok(threads)
+    }
+
+    fn select_thread(&mut self, tid: i32) -> Result<()> {
+        let tid = Pid::from_raw(tid);
+        if !self.threads.contains_key(&tid) {
+            return Err(Error::NotFound(format!("No thread {tid}")));
+        }
+        self.tid = tid;
+        Ok(())
+    }
+
+    fn regions(&self) -> Vec<Region> {
+        match fs::read_to_string(format!("/proc/{}/maps", self.pid)) {
+            Ok(maps) => parse_maps(&maps),
+            Err(_) => Vec::new(),
+        }
+    }
+
+    fn auxv(&self) -> Result<Vec<u8>> {
+        fs::read(format!("/proc/{}/auxv", self.pid))
+            .map_err(|e| Error::State(format!("Cannot read the auxiliary vector: {e}")))
+    }
+}
+
+/// Reads the x87 and SSE registers of a thread, which ptrace keeps apart from
+/// the general purpose ones
+fn getfpregs(tid: Pid) -> Result<user_fpregs_struct> {
+    let mut regs = MaybeUninit::<user_fpregs_struct>::uninit();
+    let res = unsafe {
+        libc::ptrace(
+            libc::PTRACE_GETFPREGS,
+            tid.as_raw(),
+            ptr::null_mut::<c_void>(),
+            regs.as_mut_ptr(),
+        )
+    };
+    Errno::result(res)?;
+    // The kernel fills in the whole struct when the call succeeds
+    Ok(unsafe { regs.assume_init() })
+}
+
+impl Drop for Process {
+    fn drop(&mut self) {
+        if self.launched {
+            let _ = kill(self.pid, Signal::SIGKILL);
+            // The main thread is only reaped once the others have been
+            let flags = Some(WaitPidFlag::__WALL | WaitPidFlag::__WNOTHREAD);
+            for &tid in self.threads.keys().filter(|&&tid| tid != self.pid) {
+                let _ = waitpid(tid, flags);
+            }
+            let _ = waitpid(self.pid, flags);
+        } else {
+            for (&tid, task) in &self.threads {
+                let _ = ptrace::detach(tid, task.signal);
+            }
+        }
+    }
+}
@@ -1,4 +1,7 @@
-use nix::{sys::ptrace, unistd::Pid};
+use nix::libc::user_regs_struct;
+
+use crate::error::Result;
+use crate::target::Target;
 
 pub struct Register {
     kind: RegisterKind,
@@ -6,11 +9,11 @@ pub struct Register {
 }
 
 struct RegisterDescriptor {
-    dwarf_no: i64,
     name: String,
 }
 
 // Found here: /usr/include/x86_64-linux-gnu/sys
+#[derive(Clone, Copy)]
 enum RegisterKind {
     // General purpose registers
     /// Accumulator register
@@ -62,22 +65,108 @@ enum RegisterKind {
     /// This is a register needed due to some Linux history
     OrigRax,
 
-    /// ?
+    /// Instruction pointer register
     Rip,
 
     /// FLAGS register
     RFlags,
+
+    /// 128-bit SSE register, kept with the x87 registers rather than in the
+    /// general purpose register file
+    Xmm(usize),
 }
 
+/// The general purpose registers, with their names and their DWARF numbers
+/// from the System V x86-64 ABI. The return address column, 16, is rip
+const REGISTERS: [(RegisterKind, &str, Option<i64>); 27] = [
+    (RegisterKind::Rax, "rax", Some(0)),
+    (RegisterKind::Rdx, "rdx", Some(1)),
+    (RegisterKind::Rcx, "rcx", Some(2)),
+    (RegisterKind::Rbx, "rbx", Some(3)),
+    (RegisterKind::Rsi, "rsi", Some(4)),
+    (RegisterKind::Rdi, "rdi", Some(5)),
+    (RegisterKind::Rbp, "rbp", Some(6)),
+    (RegisterKind::Rsp, "rsp", Some(7)),
+    (RegisterKind::R8, "r8", Some(8)),
+    (RegisterKind::R9, "r9", Some(9)),
+    (RegisterKind::R10, "r10", Some(10)),
+    (RegisterKind::R11, "r11", Some(11)),
+    (RegisterKind::R12, "r12", Some(12)),
+    (RegisterKind::R13, "r13", Some(13)),
+    (RegisterKind::R14, "r14", Some(14)),
+    (RegisterKind::R15, "r15", Some(15)),
+    (RegisterKind::Rip, "rip", Some(16)),
+    (RegisterKind::RFlags, "eflags", Some(49)),
+    (RegisterKind::Es, "es", Some(50)),
+    (RegisterKind::Cs, "cs", Some(51)),
+    (RegisterKind::Ss, "ss", Some(52)),
+    (RegisterKind::Ds, "ds", Some(53)),
+    (RegisterKind::Fs, "fs", Some(54)),
+    (RegisterKind::Gs, "gs", Some(55)),
+    (RegisterKind::FsBase, "fs_base", Some(58)),
+    (RegisterKind::GsBase, "gs_base", Some(59)),
+    // Only exists in the kernel's saved state, so it has no DWARF number
+    (RegisterKind::OrigRax, "orig_rax", None),
+];
+
+/// DWARF number of xmm0, with xmm1 to xmm15 following it
+const XMM_DWARF_START: i64 = 17;
+
+const XMM_COUNT: usize = 16;
+
 pub enum RegisterSelector<'a> {
     Dwarf(i64),
     Name(&'a str),
 }
 
 impl Register {
-    pub fn read(&self, pid: Pid) -> u64 {
-        let regs = ptrace::getregs(pid).unwrap();
-        match self.kind {
+    pub fn name(&self) -> &str {
+        &self.descriptor.name
+    }
+
+    /// The register's number in DWARF, if it has one
+    pub fn dwarf_no(&self) -> Option<i64> {
+        if let RegisterKind::Xmm(num) = self.kind {
+            return Some(XMM_DWARF_START + num as i64);
+        }
+        REGISTERS
+            .iter()
+            .find(|(_, name, _)| *name == self.name())
+            .and_then(|(_, _, dwarf_no)| *dwarf_no)
+    }
+
+    /// Reads the register from the target
+    pub fn read(&self, target: &dyn Target) -> Result<u128> {
+        if let RegisterKind::Xmm(num) = self.kind {
+            let regs = target.fpregs()?;
+            let words = &regs.xmm_space[num * 4..num * 4 + 4];
+            return Ok((0..)
+                .zip(words)
+                .fold(0, |value, (i, &word)| value | (word as u128) << (32 * i)));
+        }
+        // Every other register is in the general purpose register file
+        let regs = target.regs()?;
+        Ok(self.read_from(&regs).map_or(0, u128::from))
+    }
+
+    /// Writes the register in the target
+    pub fn write(&self, target: &mut dyn Target, value: u128) -> Result<()> {
+        if let RegisterKind::Xmm(num) = self.kind {
+            let mut regs = target.fpregs()?;
+            for (i, word) in (0..).zip(&mut regs.xmm_space[num * 4..num * 4 + 4]) {
+                *word = (value >> (32 * i)) as u32;
+            }
+            return target.set_fpregs(regs);
+        }
+        let mut regs = target.regs()?;
+        self.write_to(&mut regs, value as u64);
+        target.set_regs(regs)
+    }
+
+    /// Reads the register from a snapshot of the general purpose register
+    /// file, which doesn't hold the SSE registers
+    pub fn read_from(&self, regs: &user_regs_struct) -> Option<u64> {
+        let value = match self.kind {
             RegisterKind::Rax => regs.rax,
             RegisterKind::Rbx => regs.rbx,
             RegisterKind::Rcx => regs.rcx,
@@ -105,11 +194,14 @@ impl Register {
             RegisterKind::OrigRax => regs.orig_rax,
             RegisterKind::Rip => regs.rip,
             RegisterKind::RFlags => regs.eflags,
-        }
+            RegisterKind::Xmm(_) => return None,
+        };
+        Some(value)
     }
 
-    pub fn write(&self, pid: Pid, value: u64) {
-        let mut regs = ptrace::getregs(pid).unwrap();
+    /// Writes the register into a snapshot of the general purpose register
+    /// file, which doesn't hold the SSE registers
+    pub fn write_to(&self, regs: &mut user_regs_struct, value: u64) -> Option<()> {
         match self.kind {
             RegisterKind::Rax => regs.rax = value,
             RegisterKind::Rbx => regs.rbx = value,
@@ -138,202 +230,36 @@ impl Register {
             RegisterKind::OrigRax => regs.orig_rax = value,
             RegisterKind::Rip => regs.rip = value,
             RegisterKind::RFlags => regs.eflags = value,
+            RegisterKind::Xmm(_) => return None,
         };
-        ptrace::setregs(pid, regs).unwrap();
+        Some(())
     }
 
-    pub fn from_selector(selector: RegisterSelector) -> Register {
-        match selector {
-            RegisterSelector::Dwarf(-1) | RegisterSelector::Name("orig_rax") => Register {
-                kind: RegisterKind::OrigRax,
+    /// Finds a register by name or DWARF number
+    pub fn from_selector(selector: RegisterSelector) -> Option<Register> {
+        let general = REGISTERS.iter().find(|(_, name, dwarf_no)| match selector {
+            RegisterSelector::Dwarf(no) => *dwarf_no == Some(no),
+            RegisterSelector::Name(selected) => *name == selected,
+        });
+        if let Some((kind, name, _)) = general {
+            return Some(Register {
+                kind: *kind,
                 descriptor: RegisterDescriptor {
-                    dwarf_no: -1,
-                    name: "orig_rax".into(),
+                    name: name.to_string(),
                 },
-            },
-            RegisterSelector::Dwarf(-1) | RegisterSelector::Name("rip") => Register {
-                kind: RegisterKind::Rip,
-                descriptor: RegisterDescriptor {
-                    dwarf_no: -1,
-                    name: "rip".into(),
-                },
-            },
-            RegisterSelector::Dwarf(0) | RegisterSelector::Name("rax") => Register {
-                kind: RegisterKind::Rax,
-                descriptor: RegisterDescriptor {
-                    dwarf_no: 0,
-                    name: "rax".into(),
-                },
-            },
-            RegisterSelector::Dwarf(1) | RegisterSelector::Name("rdx") => Register {
-                kind: RegisterKind::Rdx,
-                descriptor: RegisterDescriptor {
-                    dwarf_no: 1,
-                    name: "rdx".into(),
-                },
-            },
-            RegisterSelector::Dwarf(2) | RegisterSelector::Name("rcx") => Register {
-                kind: RegisterKind::Rcx,
-                descriptor: RegisterDescriptor {
-                    dwarf_no: 2,
-                    name: "rcx".into(),
-                },
-            },
-            RegisterSelector::Dwarf(3) | RegisterSelector::Name("rbx") => Register {
-                kind: RegisterKind::Rbx,
-                descriptor: RegisterDescriptor {
-                    dwarf_no: 3,
-                    name: "rbx".into(),
-                },
-            },
-            RegisterSelector::Dwarf(4) | RegisterSelector::Name("rsi") => Register {
-                kind: RegisterKind::Rsi,
-                descriptor: RegisterDescriptor {
-                    dwarf_no: 4,
-                    name: "rsi".into(),
-                },
-            },
-            RegisterSelector::Dwarf(5) | RegisterSelector::Name("rdi") => Register {
-                kind: RegisterKind::Rdi,
-                descriptor: RegisterDescriptor {
-                    dwarf_no: 5,
-                    name: "rdi".into(),
-                },
-            },
-            RegisterSelector::Dwarf(6) | RegisterSelector::Name("rbp") => Register {
-                kind: RegisterKind::Rbp,
-                descriptor: RegisterDescriptor {
-                    dwarf_no: 6,
-                    name: "rbp".into(),
-                },
-            },
-            RegisterSelector::Dwarf(7) | RegisterSelector::Name("rsp") => Register {
-                kind: RegisterKind::Rsp,
-                descriptor: RegisterDescriptor {
-                    dwarf_no: 7,
-                    name: "rsp".into(),
-                },
-            },
-            RegisterSelector::Dwarf(8) | RegisterSelector::Name("r8") => Register {
-                kind: RegisterKind::R8,
-                descriptor: RegisterDescriptor {
-                    dwarf_no: 8,
-                    name: "r8".into(),
-                },
-            },
-            RegisterSelector::Dwarf(9) | RegisterSelector::Name("r9") => Register {
-                kind: RegisterKind::R9,
-                descriptor: RegisterDescriptor {
-                    dwarf_no: 9,
-                    name: "r9".into(),
-                },
-            },
-            RegisterSelector::Dwarf(10) | RegisterSelector::Name("r10") => Register {
-                kind: RegisterKind::R10,
-                descriptor: RegisterDescriptor {
-                    dwarf_no: 10,
-                    name: "r10".into(),
-                },
-            },
-            RegisterSelector::Dwarf(11) | RegisterSelector::Name("r11") => Register {
-                kind: RegisterKind::R11,
-                descriptor: RegisterDescriptor {
-                    dwarf_no: 11,
-                    name: "r11".into(),
-                },
-            },
-            RegisterSelector::Dwarf(12) | RegisterSelector::Name("r12") => Register {
-                kind: RegisterKind::R12,
-                descriptor: RegisterDescriptor {
-                    dwarf_no: 12,
-                    name: "r12".into(),
-                },
-            },
-            RegisterSelector::Dwarf(13) | RegisterSelector::Name("r13") => Register {
-                kind: RegisterKind::R13,
-                descriptor: RegisterDescriptor {
-                    dwarf_no: 13,
-                    name: "r13".into(),
-                },
-            },
-            RegisterSelector::Dwarf(14) | RegisterSelector::Name("r14") => Register {
-                kind: RegisterKind::R14,
-                descriptor: RegisterDescriptor {
-                    dwarf_no: 14,
-                    name: "r14".into(),
-                },
-            },
-            RegisterSelector::Dwarf(15) | RegisterSelector::Name("r15") => Register {
-                kind: RegisterKind::R15,
-                descriptor: RegisterDescriptor {
-                    dwarf_no: 15,
-                    name: "r15".into(),
-                },
-            },
-            RegisterSelector::Dwarf(49) | RegisterSelector::Name("eflags") => Register {
-                kind: RegisterKind::RFlags,
-                descriptor: RegisterDescriptor {
-                    dwarf_no: 49,
-                    name: "eflags".into(),
-                },
-            },
-            RegisterSelector::Dwarf(50) | RegisterSelector::Name("es") => Register {
-                kind: RegisterKind::Es,
-                descriptor: RegisterDescriptor {
-                    dwarf_no: 50,
-                    name: "es".into(),
-                },
-            },
-            RegisterSelector::Dwarf(51) | RegisterSelector::Name("cs") => Register {
-                kind: RegisterKind::Cs,
-                descriptor: RegisterDescriptor {
-                    dwarf_no: 51,
-                    name: "cs".into(),
-                },
-            },
-            RegisterSelector::Dwarf(52) | RegisterSelector::Name("ss") => Register {
-                kind: RegisterKind::Ss,
-                descriptor: RegisterDescriptor {
-                    dwarf_no: 52,
-                    name: "ss".into(),
-                },
-            },
-            RegisterSelector::Dwarf(53) | RegisterSelector::Name("ds") => Register {
-                kind: RegisterKind::Ds,
-                descriptor: RegisterDescriptor {
-                    dwarf_no: 53,
-                    name: "ds".into(),
-                },
-            },
-            RegisterSelector::Dwarf(54) | RegisterSelector::Name("fs") => Register {
-                kind: RegisterKind::Fs,
-                descriptor: RegisterDescriptor {
-                    dwarf_no: 54,
-                    name: "fs".into(),
-                },
-            },
-            RegisterSelector::Dwarf(55) | RegisterSelector::Name("gs") => Register {
-                kind: RegisterKind::Gs,
-                descriptor: RegisterDescriptor {
-                    dwarf_no: 55,
-                    name: "gs".into(),
-                },
-            },
-            RegisterSelector::Dwarf(58) | RegisterSelector::Name("fs_base") => Register {
-                kind: RegisterKind::FsBase,
-                descriptor: RegisterDescriptor {
-                    dwarf_no: 58,
-                    name: "fs_base".into(),
-                },
-            },
-            RegisterSelector::Dwarf(59) | RegisterSelector::Name("gs_base") => Register {
-                kind: RegisterKind::GsBase,
-                descriptor: RegisterDescriptor {
-                    dwarf_no: 59,
-                    name: "gs_base".into(),
-                },
-            },
-            _ => unreachable!(),
+            });
         }
+
+        let num = match selector {
+            RegisterSelector::Dwarf(no @ XMM_DWARF_START..) => (no - XMM_DWARF_START) as usize,
+            RegisterSelector::Dwarf(_) => return None,
+            RegisterSelector::Name(name) => name.strip_prefix("xmm")?.parse().ok()?,
+        };
+        (num < XMM_COUNT).then(|| Register {
+            kind: RegisterKind::Xmm(num),
+            descriptor: RegisterDescriptor {
+                name: format!("xmm{num}"),
+            },
+        })
     }
 }
@@ -0,0 +1,393 @@
+//! A target reached through a gdbserver-compatible stub, such as gdbserver,
+//! QEMU's user mode or `bkpt --gdbserver`, over gdb's Remote Serial Protocol
+
+use std::cell::RefCell;
+use std::io::Write;
+use std::net::TcpStream;
+
+use nix::libc::{user_fpregs_struct, user_regs_struct};
+use nix::sys::signal::Signal;
+
+use crate::error::{Error, Result};
+use crate::rsp::{self, Event, RegisterSpec};
+use crate::target::{parse_maps, Mapping, StopEvent, Target};
+
+/// Bytes asked for by each `m` packet, and sent by each `M` packet, which
+/// fits in the packets of any stub
+const MEMORY_CHUNK: usize = 0x400;
+
+/// Bytes asked for by each `vFile:pread` and `qXfer` read
+const FILE_CHUNK: usize = 0x800;
+
+/// A program being debugged by a stub, which bkpt drives over TCP
+pub struct Remote {
+    stream: TcpStream,
+    /// Read from while the target is borrowed immutably, such as when reading
+    /// memory
+    reader: RefCell<rsp::Reader<TcpStream>>,
+    /// The registers in the stub's `g` packets
+    layout: Vec<RegisterSpec>,
+    /// Whether the stub acknowledges packets, and expects acknowledgments
+    ack: bool,
+    /// Whether the stub understands `vCont`
+    vcont: bool,
+    /// Set once the program exits, after which the stub can't be asked about it
+    exited: bool,
+}
+
+impl Remote {
+    /// Connects to a stub listening on `addr`, such as `localhost:1234`. The
+    /// host can be left out for this machine
+    pub fn connect(addr: &str) -> Result<Remote> {
+        let addr = match addr.strip_prefix(':') {
+            Some(port) => format!("127.0.0.1:{port}"),
+            None => addr.to_string(),
+        };
+        let stream = TcpStream::connect(&addr)
+            .map_err(|e| Error::Remote(format!("Cannot connect to {addr}: {e}")))?;
+        // Packets are small and each waits for a reply, so send them at once
+        let _ = stream.set_nodelay(true);
+        let reader = stream
+            .try_clone()
+            .map_err(|e| Error::Remote(format!("Cannot read from {addr}: {e}")))?;
+
+        let mut remote = Remote {
+            stream,
+            reader: RefCell::new(rsp::Reader::new(reader)),
+            layout: rsp::layout(),
+            ack: true,
+            vcont: false,
+            exited: false,
+        };
+        let supported = remote.request_str("qSupported:swbreak+;xmlRegisters=i386")?;
+        let supports = |feature: &str| supported.split(';').any(|f| f == feature);
+        if supports("QStartNoAckMode+") && remote.request_str("QStartNoAckMode")? == "OK" {
+            remote.ack = false;
+        }
+        if supports("qXfer:features:read+") {
+            let xml = remote.target_description("target.xml")?;
+            if let Some(layout) = rsp::parse_target_xml(&xml) {
+                remote.layout = layout;
+            }
+        }
+        remote.vcont = remote.request_str("vCont?")?.split(';').any(|a| a == "s");
+        Ok(remote)
+    }
+
+    fn send(&self, packet: &str) -> Result<()> {
+        if self.exited {
+            return Err(Error::NoProcess);
+        }
+        rsp::write_packet(&mut &self.stream, packet.as_bytes()).map_err(lost)
+    }
+
+    /// Reads the next packet, acknowledging it
+    fn receive(&self) -> Result<Vec<u8>> {
+        let mut reader = self.reader.borrow_mut();
+        loop {
+            match reader.next().map_err(lost)? {
+                Some(Event::Packet(packet)) => {
+                    if self.ack {
+                        (&self.stream).write_all(b"+").map_err(lost)?;
+                    }
+                    return Ok(packet);
+                }
+                Some(Event::Corrupt) => (&self.stream).write_all(b"-").map_err(lost)?,
+                Some(_) => {}
+                None => {
+                    return Err(Error::Remote(
+                        "The remote side closed the connection".into(),
+                    ))
+                }
+            }
+        }
+    }
+
+    fn request(&self, packet: &str) -> Result<Vec<u8>> {
+        self.send(packet)?;
+        self.receive()
+    }
+
+    fn request_str(&self, packet: &str) -> Result<String> {
+        Ok(String::from_utf8_lossy(&self.request(packet)?).into_owned())
+    }
+
+    /// Reads a whole `qXfer` object, such as `features:read:target.xml`
+    fn xfer(&self, object: &str) -> Result<Vec<u8>> {
+        let mut data = Vec::new();
+        loop {
+            let reply = self.request(&format!("qXfer:{object}:{:x},{FILE_CHUNK:x}", data.len()))?;
+            match reply.split_first() {
+                Some((b'm', part)) => data.extend_from_slice(part),
+                Some((b'l', part)) => {
+                    data.extend_from_slice(part);
+                    return Ok(data);
+                }
+                _ => return Err(Error::Remote(format!("Cannot read {object}"))),
+            }
+        }
+    }
+
+    /// Reads a target description, with the documents it includes in place
+    fn target_description(&self, annex: &str) -> Result<String> {
+        let xml = self.xfer(&format!("features:read:{annex}"))?;
+        let xml = String::from_utf8_lossy(&xml).into_owned();
+
+        let mut expanded = String::new();
+        let mut rest = xml.as_str();
+        while let Some(start) = rest.find("<xi:include") {
+            let end = start
+                + rest[start..]
+                    .find("/>")
+                    .map_or(rest.len() - start, |end| end + 2);
+            expanded.push_str(&rest[..start]);
+            let include = &rest[start..end];
+            if let Some(href) = include
+                .split("href=\"")
+                .nth(1)
+                .and_then(|h| h.split('"').next())
+            {
+                expanded.push_str(&self.target_description(href)?);
+            }
+            rest = &rest[end..];
+        }
+        expanded.push_str(rest);
+        Ok(expanded)
+    }
+
+    /// The registers, as a `g` packet's data
+    fn register_bytes(&self) -> Result<Vec<u8>> {
+        let reply = self.request_str("g")?;
+        // Registers the stub can't read are sent as `xx`
+        rsp::from_hex(&reply.replace('x', "0")).ok_or_else(|| unexpected("g", &reply))
+    }
+
+    fn set_register_bytes(&mut self, bytes: &[u8]) -> Result<()> {
+        let reply = self.request_str(&format!("G{}", rsp::to_hex(bytes)))?;
+        ok(&reply, "G")
+    }
+
+    fn decoded(&self) -> Result<(user_regs_struct, user_fpregs_struct)> {
+        let bytes = self.register_bytes()?;
+        // Both are plain C structs, for which all zeroes is valid
+        let (mut regs, mut fpregs) = unsafe { std::mem::zeroed() };
+        rsp::decode_registers(&self.layout, &bytes, &mut regs, &mut fpregs);
+        Ok((regs, fpregs))
+    }
+
+    /// Reads a file on the stub's machine, such as the program's memory map
+    fn read_file(&self, path: &str) -> Result<Vec<u8>> {
+        let path = rsp::to_hex(path.as_bytes());
+        let fd = host_io_result(&self.request(&format!("vFile:open:{path},0,0"))?)?.0;
+        let mut data = Vec::new();
+        let read = loop {
+            let reply = self.request(&format!(
+                "vFile:pread:{fd:x},{FILE_CHUNK:x},{:x}",
+                data.len()
+            ));
+            match reply.and_then(|reply| host_io_result(&reply).map(|(len, d)| (len, d.to_vec()))) {
+                Ok((0, _)) => break Ok(()),
+                Ok((_, part)) => data.extend(part),
+                Err(e) => break Err(e),
+            }
+        };
+        self.request(&format!("vFile:close:{fd:x}"))?;
+        read.map(|_| data)
+    }
+
+    /// The process the stub is debugging
+    fn pid(&self) -> Option<u64> {
+        let reply = self.request_str("qC").ok()?;
+        let thread = reply.strip_prefix("QC")?;
+        // With multiprocess extensions, the thread is `p<pid>.<tid>`
+        let pid = thread
+            .strip_prefix('p')
+            .map_or(thread, |id| id.split('.').next().unwrap());
+        u64::from_str_radix(pid, 16).ok()
+    }
+
+    /// Parses a stop reply, giving `None` for output the program printed
+    fn stop_event(&self, reply: &[u8]) -> Result<Option<StopEvent>> {
+        let reply = String::from_utf8_lossy(reply);
+        let signal = || -> Result<Signal> {
+            let num = reply
+                .get(1..3)
+                .and_then(|num| u8::from_str_radix(num, 16).ok());
+            num.and_then(rsp::signal_from_gdb)
+                .ok_or_else(|| unexpected("stop", &reply))
+        };
+        match reply.chars().next() {
+            Some('T' | 'S') => Ok(Some(StopEvent::Signal(signal()?))),
+            Some('W') => {
+                let code = reply
+                    .get(1..3)
+                    .and_then(|code| i32::from_str_radix(code, 16).ok());
+                Ok(Some(StopEvent::Exited(
+                    code.ok_or_else(|| unexpected("stop", &reply))?,
+                )))
+            }
+            Some('X') => Ok(Some(StopEvent::Killed(signal()?))),
+            Some('O') if reply.len() > 1 => {
+                let output = rsp::from_hex(&reply[1..]).unwrap_or_default();
+                print!("{}", String::from_utf8_lossy(&output));
+                Ok(None)
+            }
+            _ => Err(unexpected("stop", &reply)),
+        }
+    }
+}
+
+impl Target for Remote {
+    fn read_memory(&self, addr: u64, len: usize) -> Result<Vec<u8>> {
+        let mut bytes = Vec::with_capacity(len);
+        while bytes.len() < len {
+            let at = addr + bytes.len() as u64;
+            let chunk = (len - bytes.len()).min(MEMORY_CHUNK);
+            let reply = self.request_str(&format!("m{at:x},{chunk:x}"))?;
+            // Errors and empty replies mean the memory isn't mapped
+            let part = rsp::from_hex(&reply)
+                .filter(|part| !part.is_empty() && !reply.starts_with('E'))
+                .ok_or_else(|| Error::Eval(format!("Cannot access memory at {at:#x}")))?;
+            bytes.extend(part);
+        }
+        bytes.truncate(len);
+        Ok(bytes)
+    }
+
+    fn write_memory(&mut self, addr: u64, bytes: &[u8]) -> Result<()> {
+        for (i, chunk) in bytes.chunks(MEMORY_CHUNK).enumerate() {
+            let at = addr + (i * MEMORY_CHUNK) as u64;
+            let packet = format!("M{at:x},{:x}:{}", chunk.len(), rsp::to_hex(chunk));
+            if self.request_str(&packet)? != "OK" {
+                return Err(Error::Eval(format!("Cannot access memory at {at:#x}")));
+            }
+        }
+        Ok(())
+    }
+
+    fn regs(&self) -> Result<user_regs_struct> {
+        Ok(self.decoded()?.0)
+    }
+
+    fn set_regs(&mut self, regs: user_regs_struct) -> Result<()> {
+        let mut bytes = self.register_bytes()?;
+        let (_, fpregs) = self.decoded()?;
+        rsp::update_registers(&self.layout, &mut bytes, &regs, &fpregs);
+        self.set_register_bytes(&bytes)
+    }
+
+    fn fpregs(&self) -> Result<user_fpregs_struct> {
+        Ok(self.decoded()?.1)
+    }
+
+    fn set_fpregs(&mut self, fpregs: user_fpregs_struct) -> Result<()> {
+        let mut bytes = self.register_bytes()?;
+        let (regs, _) = self.decoded()?;
+        rsp::update_registers(&self.layout, &mut bytes, &regs, &fpregs);
+        self.set_register_bytes(&bytes)
+    }
+
+    fn resume(&mut self) -> Result<()> {
+        // The stop reply is only sent once the program stops, so `wait`
+        // reads it
+        self.send(if self.vcont { "vCont;c" } else { "c" })
+    }
+
+    fn step(&mut self) -> Result<()> {
+        self.send(if self.vcont { "vCont;s" } else { "s" })
+    }
+
+    fn wait(&mut self) -> Result<StopEvent> {
+        loop {
+            let reply = self.receive()?;
+            if let Some(stop) = self.stop_event(&reply)? {
+                if let StopEvent::Exited(_) | StopEvent::Killed(_) = stop {
+                    self.exited = true;
+                }
+                return Ok(stop);
+            }
+        }
+    }
+
+    fn mappings(&self) -> Vec<Mapping> {
+        let Some(maps) = self
+            .pid()
+            .and_then(|pid| self.read_file(&format!("/proc/{pid}/maps")).ok())
+        else {
+            return Vec::new();
+        };
+
+        parse_maps(&String::from_utf8_lossy(&maps))
+            .into_iter()
+            .filter_map(|region| {
+                Some(Mapping {
+                    path: region.path?,
+                    range: region.range,
+                    executable: region.executable,
+                    offset: region.offset,
+                })
+            })
+            .collect()
+    }
+
+    fn insert_breakpoint(&mut self, addr: u64) -> Result<u8> {
+        // Stubs hide their breakpoints from memory reads, so this is the
+        // program's own byte
+        let old = self.read_memory(addr, 1)?[0];
+        ok(&self.request_str(&format!("Z0,{addr:x},1"))?, "Z0")?;
+        Ok(old)
+    }
+
+    fn remove_breakpoint(&mut self, addr: u64, _old: u8) -> Result<()> {
+        ok(&self.request_str(&format!("z0,{addr:x},1"))?, "z0")
+    }
+
+    fn decr_pc_after_break(&self) -> u64 {
+        // Stubs move the PC back onto the breakpoint themselves
+        0
+    }
+
+    fn auxv(&self) -> Result<Vec<u8>> {
+        self.xfer("auxv:read:")
+    }
+}
+
+impl Drop for Remote {
+    fn drop(&mut self) {
+        // Like gdb, kill the program rather than leave it stopped
+        let _ = self.send("k");
+    }
+}
+
+fn lost(e: std::io::Error) -> Error {
+    Error::Remote(format!("Lost the connection to the remote side: {e}"))
+}
+
+fn unexpected(packet: &str, reply: &str) -> Error {
+    Error::Remote(format!("Unexpected reply to {packet}: {reply}"))
+}
+
+fn ok(reply: &str, packet: &str) -> Result<()> {
+    match reply {
+        "OK" => Ok(()),
+        "" => Err(Error::Remote(format!(
+            "The remote side doesn't support {packet}"
+        ))),
+        _ => Err(unexpected(packet, reply)),
+    }
+}
+
+/// Parses a host I/O reply, `F<result>[,errno][;data]`, giving the result and
+/// any data
+fn host_io_result(reply: &[u8]) -> Result<(u64, &[u8])> {
+    let (header, data) = match reply.iter().position(|&b| b == b';') {
+        Some(end) => (&reply[..end], &reply[end + 1..]),
+        None => (reply, &[][..]),
+    };
+    let header = String::from_utf8_lossy(header);
+    let result = header
+        .strip_prefix('F')
+        .and_then(|result| u64::from_str_radix(result, 16).ok())
+        .ok_or_else(|| Error::Remote(format!("Host I/O failed: {header}")))?;
+    Ok((result, data))
+}
@@ -0,0 +1,581 @@
+//! Pieces of gdb's Remote Serial Protocol shared by the server and the client:
+//! packet framing, signal numbers and the layout of the registers
+
+use std::fmt::Write as _;
+use std::io::{self, BufRead, BufReader, Read, Write};
+
+use nix::libc::{user_fpregs_struct, user_regs_struct};
+use nix::sys::signal::Signal;
+
+use crate::register::{Register, RegisterSelector};
+
+/// What arrives on a connection
+#[derive(Debug, PartialEq, Eq)]
+pub enum Event {
+    Packet(Vec<u8>),
+    /// A packet whose checksum didn't match, which should be sent again
+    Corrupt,
+    /// `^C`, which asks for the running program to be stopped
+    Interrupt,
+    Ack,
+    Nack,
+}
+
+/// Reads packets and control bytes from a connection
+pub struct Reader<R> {
+    inner: BufReader<R>,
+}
+
+impl<R: Read> Reader<R> {
+    pub fn new(inner: R) -> Reader<R> {
+        Reader {
+            inner: BufReader::new(inner),
+        }
+    }
+
+    /// Reads the next packet or control byte, giving `None` once the
+    /// connection is closed
+    pub fn next(&mut self) -> io::Result<Option<Event>> {
+        loop {
+            let mut byte = [0];
+            if self.inner.read(&mut byte)? == 0 {
+                return Ok(None);
+            }
+            match byte[0] {
+                b'+' => return Ok(Some(Event::Ack)),
+                b'-' => return Ok(Some(Event::Nack)),
+                0x03 => return Ok(Some(Event::Interrupt)),
+                b'$' => break,
+                // Anything else between packets is noise
+                _ => {}
+            }
+        }
+
+        // `#` is always escaped in the data, so it ends the packet
+        let mut raw = Vec::new();
+        self.inner.read_until(b'#', &mut raw)?;
+        if raw.pop() != Some(b'#') {
+            return Ok(None);
+        }
+        let mut checksum = [0; 2];
+        self.inner.read_exact(&mut checksum)?;
+        let expected = std::str::from_utf8(&checksum)
+            .ok()
+            .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+        if expected != Some(checksum_of(&raw)) {
+            return Ok(Some(Event::Corrupt));
+        }
+        Ok(Some(Event::Packet(decode(&raw))))
+    }
+}
+
+/// Sends a packet, escaping the bytes that frame packets
+pub fn write_packet(writer: &mut impl Write, data: &[u8]) -> io::Result<()> {
+    let mut escaped = Vec::with_capacity(data.len() + 4);
+    escaped.push(b'$');
+    for &byte in data {
+        match byte {
+            b'$' | b'#' | b'}' | b'*' => escaped.extend([b'}', byte ^ 0x20]),
+            _ => escaped.push(byte),
+        }
+    }
+    let checksum = checksum_of(&escaped[1..]);
+    write!(escaped, "#{checksum:02x}")?;
+    writer.write_all(&escaped)?;
+    writer.flush()
+}
+
+fn checksum_of(bytes: &[u8]) -> u8 {
+    bytes.iter().fold(0, |sum, &byte| sum.wrapping_add(byte))
+}
+
+/// Undoes the escaping and run-length encoding of a packet's data
+fn decode(raw: &[u8]) -> Vec<u8> {
+    let mut data = Vec::with_capacity(raw.len());
+    let mut bytes = raw.iter();
+    while let Some(&byte) = bytes.next() {
+        match byte {
+            b'}' => data.extend(bytes.next().map(|byte| byte ^ 0x20)),
+            // The previous byte repeated, 29 less than this many more times
+            b'*' => {
+                let count = bytes.next().map_or(0, |count| count.saturating_sub(29));
+                if let Some(&last) = data.last() {
+                    data.extend(std::iter::repeat_n(last, count as usize));
+                }
+            }
+            _ => data.push(byte),
+        }
+    }
+    data
+}
+
+pub fn to_hex(bytes: &[u8]) -> String {
+    bytes.iter().fold(String::new(), |mut hex, byte| {
+        let _ = write!(hex, "{byte:02x}");
+        hex
+    })
+}
+
+pub fn from_hex(hex: &str) -> Option<Vec<u8>> {
+    if !hex.len().is_multiple_of(2) {
+        return None;
+    }
+    (0..hex.len())
+        .step_by(2)
+        .map(|i| u8::from_str_radix(hex.get(i..i + 2)?, 16).ok())
+        .collect()
+}
+
+/// Linux signals and the numbers gdb gives them, which differ after SIGTERM
+const SIGNALS: [(Signal, u8); 30] = [
+    (Signal::SIGHUP, 1),
+    (Signal::SIGINT, 2),
+    (Signal::SIGQUIT, 3),
+    (Signal::SIGILL, 4),
+    (Signal::SIGTRAP, 5),
+    (Signal::SIGABRT, 6),
+    (Signal::SIGFPE, 8),
+    (Signal::SIGKILL, 9),
+    (Signal::SIGBUS, 10),
+    (Signal::SIGSEGV, 11),
+    (Signal::SIGSYS, 12),
+    (Signal::SIGPIPE, 13),
+    (Signal::SIGALRM, 14),
+    (Signal::SIGTERM, 15),
+    (Signal::SIGURG, 16),
+    (Signal::SIGSTOP, 17),
+    (Signal::SIGTSTP, 18),
+    (Signal::SIGCONT, 19),
+    (Signal::SIGCHLD, 20),
+    (Signal::SIGTTIN, 21),
+    (Signal::SIGTTOU, 22),
+    (Signal::SIGIO, 23),
+    (Signal::SIGXCPU, 24),
+    (Signal::SIGXFSZ, 25),
+    (Signal::SIGVTALRM, 26),
+    (Signal::SIGPROF, 27),
+    (Signal::SIGWINCH, 28),
+    (Signal::SIGUSR1, 30),
+    (Signal::SIGUSR2, 31),
+    (Signal::SIGPWR, 32),
+];
+
+/// gdb's number for a signal it doesn't know
+const UNKNOWN_SIGNAL: u8 = 143;
+
+pub fn signal_to_gdb(signal: Signal) -> u8 {
+    SIGNALS
+        .iter()
+        .find(|(known, _)| *known == signal)
+        .map_or(UNKNOWN_SIGNAL, |(_, num)| *num)
+}
+
+pub fn signal_from_gdb(num: u8) -> Option<Signal> {
+    SIGNALS
+        .iter()
+        .find(|(_, known)| *known == num)
+        .map(|(signal, _)| *signal)
+}
+
+/// A register in a target description, which `g` packets hold in order
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct RegisterSpec {
+    pub name: String,
+    pub bits: usize,
+}
+
+/// A register's name, size in bits and gdb type
+type Described = (String, usize, &'static str);
+
+/// The features of gdb's x86-64 Linux target description, with their registers
+fn features() -> Vec<(&'static str, Vec<Described>)> {
+    let named = |names: &[&str], bits, kind| -> Vec<_> {
+        names
+            .iter()
+            .map(|name| (name.to_string(), bits, kind))
+            .collect()
+    };
+    let numbered = |prefix: &str, count, bits, kind| -> Vec<_> {
+        (0..count)
+            .map(|i| (format!("{prefix}{i}"), bits, kind))
+            .collect()
+    };
+
+    let mut core = named(&["rax", "rbx", "rcx", "rdx", "rsi", "rdi"], 64, "int64");
+    core.extend(named(&["rbp", "rsp"], 64, "data_ptr"));
+    core.extend(numbered("r", 16, 64, "int64").split_off(8));
+    core.extend(named(&["rip"], 64, "code_ptr"));
+    core.extend(named(
+        &["eflags", "cs", "ss", "ds", "es", "fs", "gs"],
+        32,
+        "int32",
+    ));
+    core.extend(numbered("st", 8, 80, "i387_ext"));
+    core.extend(named(
+        &[
+            "fctrl", "fstat", "ftag", "fiseg", "fioff", "foseg", "fooff", "fop",
+        ],
+        32,
+        "int",
+    ));
+    let mut sse = numbered("xmm", 16, 128, "vec128");
+    sse.extend(named(&["mxcsr"], 32, "int"));
+
+    vec![
+        ("org.gnu.gdb.i386.core", core),
+        ("org.gnu.gdb.i386.sse", sse),
+        ("org.gnu.gdb.i386.linux", named(&["orig_rax"], 64, "int")),
+        (
+            "org.gnu.gdb.i386.segments",
+            named(&["fs_base", "gs_base"], 64, "int"),
+        ),
+    ]
+}
+
+/// The registers bkpt's server has, in the order of its `g` packets
+pub fn layout() -> Vec<RegisterSpec> {
+    features()
+        .into_iter()
+        .flat_map(|(_, registers)| registers)
+        .map(|(name, bits, _)| RegisterSpec { name, bits })
+        .collect()
+}
+
+/// Types of the SSE registers' lanes, which gdb shows each register as
+const VECTOR_TYPES: &str = r#"<vector id="v4f" type="ieee_single" count="4"/>
+<vector id="v2d" type="ieee_double" count="2"/>
+<vector id="v16i8" type="int8" count="16"/>
+<vector id="v8i16" type="int16" count="8"/>
+<vector id="v4i32" type="int32" count="4"/>
+<vector id="v2i64" type="int64" count="2"/>
+<union id="vec128">
+<field name="v4_float" type="v4f"/>
+<field name="v2_double" type="v2d"/>
+<field name="v16_int8" type="v16i8"/>
+<field name="v8_int16" type="v8i16"/>
+<field name="v4_int32" type="v4i32"/>
+<field name="v2_int64" type="v2i64"/>
+<field name="uint128" type="uint128"/>
+</union>
+"#;
+
+/// The target description sent for `qXfer:features:read:target.xml`
+pub fn target_xml() -> String {
+    let mut xml = String::from(
+        "<?xml version=\"1.0\"?>\n<!DOCTYPE target SYSTEM \"gdb-target.dtd\">\n<target>\n\
+         <architecture>i386:x86-64</architecture>\n<osabi>GNU/Linux</osabi>\n",
+    );
+    for (feature, registers) in features() {
+        let _ = writeln!(xml, "<feature name=\"{feature}\">");
+        if feature.ends_with(".sse") {
+            xml.push_str(VECTOR_TYPES);
+        }
+        for (name, bits, kind) in registers {
+            let _ = writeln!(
+                xml,
+                "<reg name=\"{name}\" bitsize=\"{bits}\" type=\"{kind}\"/>"
+            );
+        }
+        xml.push_str("</feature>\n");
+    }
+    xml.push_str("</target>\n");
+    xml
+}
+
+/// Parses the registers out of a target description, in the order of their
+/// numbers, giving `None` if it has none
+pub fn parse_target_xml(xml: &str) -> Option<Vec<RegisterSpec>> {
+    let attribute = |tag: &str, name: &str| -> Option<String> {
+        let start = tag.find(&format!(" {name}=\""))? + name.len() + 3;
+        let len = tag[start..].find('"')?;
+        Some(tag[start..start + len].to_string())
+    };
+    // Registers are numbered in order, unless they give their own number
+    let mut next = 0;
+    let mut registers: Vec<(usize, RegisterSpec)> = xml
+        .split("<reg ")
+        .skip(1)
+        .filter_map(|tag| {
+            let tag = format!(" {}", &tag[..tag.find('>')?]);
+            let num = attribute(&tag, "regnum").and_then(|num| num.parse().ok());
+            let num = num.unwrap_or(next);
+            next = num + 1;
+            let spec = RegisterSpec {
+                name: attribute(&tag, "name")?,
+                bits: attribute(&tag, "bitsize")?.parse().ok()?,
+            };
+            Some((num, spec))
+        })
+        .collect();
+    registers.sort_by_key(|(num, _)| *num);
+    let registers: Vec<RegisterSpec> = registers.into_iter().map(|(_, spec)| spec).collect();
+    (!registers.is_empty()).then_some(registers)
+}
+
+/// Encodes the registers in `layout` as a `g` packet's data. Registers bkpt
+/// doesn't know are sent as zeroes
+pub fn encode_registers(
+    layout: &[RegisterSpec],
+    regs: &user_regs_struct,
+    fpregs: &user_fpregs_struct,
+) -> Vec<u8> {
+    let mut bytes = vec![0; layout.iter().map(|spec| spec.bits / 8).sum()];
+    update_registers(layout, &mut bytes, regs, fpregs);
+    bytes
+}
+
+/// Overwrites the registers bkpt knows in a `g` packet's data, keeping the
+/// values of the rest
+pub fn update_registers(
+    layout: &[RegisterSpec],
+    mut bytes: &mut [u8],
+    regs: &user_regs_struct,
+    fpregs: &user_fpregs_struct,
+) {
+    for spec in layout {
+        let Some((slot, rest)) = bytes.split_at_mut_checked(spec.bits / 8) else {
+            break;
+        };
+        if let Some(mut value) = read_register(&spec.name, regs, fpregs) {
+            value.resize(slot.len(), 0);
+            slot.copy_from_slice(&value);
+        }
+        bytes = rest;
+    }
+}
+
+/// Decodes a `G` packet's data into the registers, leaving any it doesn't
+/// cover as they were
+pub fn decode_registers(
+    layout: &[RegisterSpec],
+    mut bytes: &[u8],
+    regs: &mut user_regs_struct,
+    fpregs: &mut user_fpregs_struct,
+) {
+    for spec in layout {
+        let Some((value, rest)) = bytes.split_at_checked(spec.bits / 8) else {
+            break;
+        };
+        write_register(&spec.name, value, regs, fpregs);
+        bytes = rest;
+    }
+}
+
+/// The index of an x87 or SSE register, such as 3 for `st3`
+fn numbered(name: &str, prefix: &str, count: usize) -> Option<usize> {
+    name.strip_prefix(prefix)?
+        .parse()
+        .ok()
+        .filter(|&num| num < count)
+}
+
+/// Reads a register's little endian bytes, which can be more than its size
+pub fn read_register(
+    name: &str,
+    regs: &user_regs_struct,
+    fpregs: &user_fpregs_struct,
+) -> Option<Vec<u8>> {
+    if let Some(num) = numbered(name, "st", 8) {
+        return Some(words_to_bytes(&fpregs.st_space[num * 4..num * 4 + 4]));
+    }
+    if let Some(num) = numbered(name, "xmm", 16) {
+        return Some(words_to_bytes(&fpregs.xmm_space[num * 4..num * 4 + 4]));
+    }
+    let value = match name {
+        "fctrl" => fpregs.cwd.into(),
+        "fstat" => fpregs.swd.into(),
+        "ftag" => full_tag(fpregs).into(),
+        // In 64-bit mode, the last instruction and operand are full addresses
+        "fiseg" => fpregs.rip >> 32 & 0xffff,
+        "fioff" => fpregs.rip & 0xffff_ffff,
+        "foseg" => fpregs.rdp >> 32 & 0xffff,
+        "fooff" => fpregs.rdp & 0xffff_ffff,
+        "fop" => (fpregs.fop & 0x7ff).into(),
+        "mxcsr" => fpregs.mxcsr.into(),
+        _ => Register::from_selector(RegisterSelector::Name(name))?.read_from(regs)?,
+    };
+    Some(value.to_le_bytes().to_vec())
+}
+
+/// Writes a register from its little endian bytes, giving `None` if bkpt
+/// doesn't know it
+pub fn write_register(
+    name: &str,
+    bytes: &[u8],
+    regs: &mut user_regs_struct,
+    fpregs: &mut user_fpregs_struct,
+) -> Option<()> {
+    let mut padded = [0; 16];
+    let len = bytes.len().min(16);
+    padded[..len].copy_from_slice(&bytes[..len]);
+
+    if let Some(num) = numbered(name, "st", 8) {
+        // Only the first 10 of each register's 16 bytes are used
+        let mut slot = words_to_bytes(&fpregs.st_space[num * 4..num * 4 + 4]);
+        slot[..10].copy_from_slice(&padded[..10]);
+        bytes_to_words(&slot, &mut fpregs.st_space[num * 4..num * 4 + 4]);
+        return Some(());
+    }
+    if let Some(num) = numbered(name, "xmm", 16) {
+        bytes_to_words(&padded, &mut fpregs.xmm_space[num * 4..num * 4 + 4]);
+        return Some(());
+    }
+    let value = u128::from_le_bytes(padded) as u64;
+    match name {
+        "fctrl" => fpregs.cwd = value as u16,
+        "fstat" => fpregs.swd = value as u16,
+        // The kernel only keeps whether each register is empty
+        "ftag" => {
+            fpregs.ftw = (0..8)
+                .filter(|i| value >> (2 * i) & 3 != 3)
+                .fold(0, |ftw, i| ftw | 1 << i)
+        }
+        "fiseg" => fpregs.rip = fpregs.rip & 0xffff_ffff | (value & 0xffff) << 32,
+        "fioff" => fpregs.rip = fpregs.rip & !0xffff_ffff | value & 0xffff_ffff,
+        "foseg" => fpregs.rdp = fpregs.rdp & 0xffff_ffff | (value & 0xffff) << 32,
+        "fooff" => fpregs.rdp = fpregs.rdp & !0xffff_ffff | value & 0xffff_ffff,
+        "fop" => fpregs.fop = value as u16 & 0x7ff,
+        "mxcsr" => fpregs.mxcsr = value as u32,
+        _ => Register::from_selector(RegisterSelector::Name(name))?.write_to(regs, value)?,
+    }
+    Some(())
+}
+
+/// Expands the kernel's abridged x87 tag word, which only has whether each
+/// register is empty, to the full one gdb shows, which classifies the values
+fn full_tag(fpregs: &user_fpregs_struct) -> u16 {
+    let top = (fpregs.swd >> 11 & 7) as usize;
+    (0..8).fold(0, |tag, physical| {
+        let kind = if fpregs.ftw & 1 << physical == 0 {
+            // Empty
+            3
+        } else {
+            // The registers are stored in stack order, from the top
+            let st = (physical + 8 - top) % 8;
+            let bytes = words_to_bytes(&fpregs.st_space[st * 4..st * 4 + 4]);
+            let exponent = u16::from_le_bytes([bytes[8], bytes[9]]) & 0x7fff;
+            let mantissa = u64::from_le_bytes(bytes[..8].try_into().unwrap());
+            match exponent {
+                // Infinity or NaN
+                0x7fff => 2,
+                0 if mantissa == 0 => 1,
+                // Denormal
+                0 => 2,
+                _ if mantissa >> 63 == 1 => 0,
+                // Unnormal
+                _ => 2,
+            }
+        };
+        tag | kind << (2 * physical)
+    })
+}
+
+fn words_to_bytes(words: &[u32]) -> Vec<u8> {
+    words.iter().flat_map(|word| word.to_le_bytes()).collect()
+}
+
+fn bytes_to_words(bytes: &[u8], words: &mut [u32]) {
+    for (word, chunk) in words.iter_mut().zip(bytes.chunks_exact(4)) {
+        *word = u32::from_le_bytes(chunk.try_into().unwrap());
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn read(bytes: &[u8]) -> Vec<Event> {
+        let mut reader = Reader::new(bytes);
+        std::iter::from_fn(|| reader.next().unwrap()).collect()
+    }
+
+    #[test]
+    fn packets_round_trip_with_escapes() {
+        let data = b"m$#}*x";
+        let mut sent = Vec::new();
+        write_packet(&mut sent, data).unwrap();
+
+        assert_eq!(&sent[..2], b"$m");
+        assert_eq!(read(&sent), [Event::Packet(data.to_vec())]);
+    }
+
+    #[test]
+    fn reader_checks_checksums() {
+        assert_eq!(
+            read(b"+$OK#9a"),
+            [Event::Ack, Event::Packet(b"OK".to_vec())]
+        );
+        assert_eq!(read(b"$OK#00"), [Event::Corrupt]);
+        assert_eq!(read(b"\x03-"), [Event::Interrupt, Event::Nack]);
+    }
+
+    #[test]
+    fn reader_expands_run_lengths() {
+        // `*` followed by ' ' repeats the previous byte 3 more times
+        assert_eq!(read(b"$0* #7a"), [Event::Packet(b"0000".to_vec())]);
+    }
+
+    #[test]
+    fn hex_round_trips() {
+        assert_eq!(to_hex(&[0x00, 0xab, 0x10]), "00ab10");
+        assert_eq!(from_hex("00ab10"), Some(vec![0x00, 0xab, 0x10]));
+        assert_eq!(from_hex("0"), None);
+        assert_eq!(from_hex("zz"), None);
+    }
+
+    #[test]
+    fn signals_use_gdb_numbers() {
+        assert_eq!(signal_to_gdb(Signal::SIGTRAP), 5);
+        assert_eq!(signal_to_gdb(Signal::SIGUSR1), 30);
+        assert_eq!(signal_from_gdb(11), Some(Signal::SIGSEGV));
+        assert_eq!(signal_from_gdb(0), None);
+    }
+
+    #[test]
+    fn registers_round_trip_through_packets() {
+        let mut regs: user_regs_struct = unsafe { std::mem::zeroed() };
+        let mut fpregs: user_fpregs_struct = unsafe { std::mem::zeroed() };
+        regs.rip = 0x5555_5555_5129;
+        regs.eflags = 0x246;
+        regs.fs_base = 0x7fff_f7d8_a740;
+        fpregs.xmm_space[4] = 0x3ff0_0000;
+        fpregs.st_space[0] = 0x1234;
+        fpregs.mxcsr = 0x1f80;
+
+        let layout = layout();
+        let bytes = encode_registers(&layout, &regs, &fpregs);
+        // 17 64-bit registers come before eflags
+        assert_eq!(&bytes[16 * 8..17 * 8], 0x5555_5555_5129u64.to_le_bytes());
+        assert_eq!(&bytes[17 * 8..17 * 8 + 4], 0x246u32.to_le_bytes());
+
+        let (mut decoded_regs, mut decoded_fpregs) = unsafe { std::mem::zeroed() };
+        decode_registers(&layout, &bytes, &mut decoded_regs, &mut decoded_fpregs);
+        assert_eq!(decoded_regs.rip, regs.rip);
+        assert_eq!(decoded_regs.fs_base, regs.fs_base);
+        assert_eq!(decoded_fpregs.xmm_space[4], 0x3ff0_0000);
+        assert_eq!(decoded_fpregs.st_space[0], 0x1234);
+        assert_eq!(decoded_fpregs.mxcsr, 0x1f80);
+    }
+
+    #[test]
+    fn target_description_matches_layout() {
+        let xml = target_xml();
+
+        assert_eq!(parse_target_xml(&xml), Some(layout()));
+        assert!(xml.contains("<feature name=\"org.gnu.gdb.i386.core\">"));
+        assert_eq!(parse_target_xml("<target></target>"), None);
+    }
+
+    #[test]
+    fn target_description_registers_are_ordered_by_number() {
+        let xml = r#"<reg name="b" bitsize="32" regnum="1"/>
+            <reg name="a" bitsize="64" regnum="0"/><reg name="c" bitsize="8"/>"#;
+
+        let names: Vec<String> = parse_target_xml(xml)
+            .unwrap()
+            .into_iter()
+            .map(|spec| spec.name)
+            .collect();
+        assert_eq!(names, ["a", "b", "c"]);
+    }
+}
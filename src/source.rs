@@ -0,0 +1,193 @@
+use std::fs;
+use std::ops::Range;
+use std::path::{Path, PathBuf};
+
+use gimli::Reader as _;
+use gimli::{AttributeValue, Dwarf, FileEntry, LineProgramHeader, Unit};
+
+use crate::dwarf;
+use crate::error::{Error, Result};
+use crate::module::{self, Module, Reader};
+
+/// A line in a source file
+#[derive(Clone)]
+pub struct SourceLine {
+    pub path: PathBuf,
+    pub line: u64,
+}
+
+/// Finds the source line a runtime address was compiled from, using the
+/// line table in `.debug_line`
+pub fn line_for(modules: &[Module], addr: u64) -> Option<SourceLine> {
+    line_range_for(modules, addr).map(|(line, _)| line)
+}
+
+/// Like `line_for`, also giving the runtime addresses from `addr`'s up to the
+/// end of the line's code there, which stepping stays on the line within
+pub fn line_range_for(modules: &[Module], addr: u64) -> Option<(SourceLine, Range<u64>)> {
+    let module = module::find(modules, addr)?;
+    let dwarf = module.dwarf.as_ref()?;
+    let addr = addr - module.bias;
+
+    for unit in &module.units {
+        if !dwarf::contains(dwarf.unit_ranges(unit), addr) {
+            continue;
+        }
+        let Some(program) = unit.line_program.clone() else {
+            continue;
+        };
+
+        // Each row starts a range of addresses that ends at the next row, and
+        // the last row of a sequence only marks where it ends
+        let mut rows = program.rows();
+        let mut prev: Option<(u64, u64, u64)> = None;
+        // The line and file of the row holding `addr`, and the range of it
+        // and the rows after it on the same line
+        let mut found: Option<(u64, u64, Range<u64>)> = None;
+        while let Ok(Some((_, row))) = rows.next_row() {
+            if let Some((start, line, file)) = prev {
+                match &mut found {
+                    None if start <= addr && addr < row.address() && line != 0 => {
+                        found = Some((line, file, start..row.address()));
+                    }
+                    Some((found_line, found_file, range))
+                        if (line, file) == (*found_line, *found_file) && start == range.end =>
+                    {
+                        range.end = row.address();
+                    }
+                    Some(_) => break,
+                    None => {}
+                }
+            }
+            prev = match row.end_sequence() {
+                true => None,
+                false => Some((
+                    row.address(),
+                    row.line().map_or(0, |line| line.get()),
+                    row.file_index(),
+                )),
+            };
+        }
+        if let Some((line, file, range)) = found {
+            let header = rows.header();
+            let path = file_path(dwarf, unit, header, header.file(file)?)?;
+            let range = range.start + module.bias..range.end + module.bias;
+            return Some((SourceLine { path, line }, range));
+        }
+    }
+    None
+}
+
+/// Finds the runtime address of a line in a source file whose path ends with
+/// `file`, or of the next line with code if it has none, using the first
+/// statement the line table has for it
+pub fn address_for(modules: &[Module], file: &Path, line: u64) -> Option<u64> {
+    // The closest line at or after `line`, and its lowest address
+    let mut best: Option<(u64, u64)> = None;
+    for module in modules {
+        let Some(dwarf) = module.dwarf.as_ref() else {
+            continue;
+        };
+        for unit in &module.units {
+            let Some(program) = unit.line_program.clone() else {
+                continue;
+            };
+            let mut rows = program.rows();
+            while let Ok(Some((header, row))) = rows.next_row() {
+                let Some(row_line) = row.line().map(|line| line.get()) else {
+                    continue;
+                };
+                if !row.is_stmt() || row.end_sequence() || row_line < line {
+                    continue;
+                }
+                let candidate = (row_line, row.address() + module.bias);
+                if best.is_some_and(|best| best <= candidate) {
+                    continue;
+                }
+                let path = header
+                    .file(row.file_index())
+                    .and_then(|entry| file_path(dwarf, unit, header, entry));
+                if path.is_some_and(|path| path.ends_with(file)) {
+                    best = Some(candidate);
+                }
+            }
+        }
+    }
+    best.map(|(_, addr)| addr)
+}
+
+/// Finds a source file in the line tables whose path ends with `name`, such
+/// as `main.rs` or `src/main.rs`
+pub fn find_file(modules: &[Module], name: &str) -> Option<PathBuf> {
+    modules.iter().find_map(|module| {
+        let dwarf = module.dwarf.as_ref()?;
+        module.units.iter().find_map(|unit| {
+            let header = unit.line_program.as_ref()?.header();
+            header
+                .file_names()
+                .iter()
+                .filter_map(|file| file_path(dwarf, unit, header, file))
+                .find(|path| path.ends_with(name))
+        })
+    })
+}
+
+/// The path of a file in the line table, which is relative to its directory
+/// entry and the unit's compilation directory
+fn file_path(
+    dwarf: &Dwarf<Reader>,
+    unit: &Unit<Reader>,
+    header: &LineProgramHeader<Reader>,
+    file: &FileEntry<Reader>,
+) -> Option<PathBuf> {
+    let string = |value: AttributeValue<Reader>| -> Option<String> {
+        let value = dwarf.attr_string(unit, value).ok()?;
+        Some(value.to_string_lossy().ok()?.into_owned())
+    };
+
+    // Pushing an absolute path replaces what came before it
+    let mut path = PathBuf::new();
+    if let Some(comp_dir) = &unit.comp_dir {
+        path.push(&*comp_dir.to_string_lossy().ok()?);
+    }
+    if let Some(dir) = file.directory(header) {
+        path.push(string(dir)?);
+    }
+    path.push(string(file.path_name())?);
+    Some(path)
+}
+
+/// Rewrites the start of a path, for sources that have moved since the
+/// program was built, such as ones built on another machine
+pub fn substitute(path: &Path, substitutions: &[(PathBuf, PathBuf)]) -> PathBuf {
+    for (from, to) in substitutions {
+        if let Ok(rest) = path.strip_prefix(from) {
+            return to.join(rest);
+        }
+    }
+    path.to_path_buf()
+}
+
+/// Lines `first` to `last` of a source file, with their numbers. Fails if
+/// the file can't be read or has none of the lines
+pub fn read_lines(path: &Path, first: u64, last: u64) -> Result<Vec<(u64, String)>> {
+    let Ok(text) = fs::read_to_string(path) else {
+        return Err(Error::NotFound(format!(
+            "Cannot read source file {}",
+            path.display()
+        )));
+    };
+
+    let lines: Vec<(u64, String)> = (1..)
+        .zip(text.lines())
+        .filter(|(num, _)| (first..=last).contains(num))
+        .map(|(num, text)| (num, text.to_string()))
+        .collect();
+    if lines.is_empty() {
+        return Err(Error::NotFound(format!(
+            "Line number {first} out of range for {}",
+            path.display()
+        )));
+    }
+    Ok(lines)
+}
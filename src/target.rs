@@ -0,0 +1,235 @@
+use std::ops::Range;
+use std::path::PathBuf;
+
+use nix::libc::{user_fpregs_struct, user_regs_struct};
+use nix::sys::signal::Signal;
+
+use crate::breakpoint::Breakpoint;
+use crate::error::{Error, Result};
+
+/// What the debugger runs against: a live process, or something standing in
+/// for one, such as a core dump or a remote stub
+pub trait Target {
+    /// Reads `len` bytes of memory, failing if any of them aren't mapped
+    fn read_memory(&self, addr: u64, len: usize) -> Result<Vec<u8>>;
+    fn write_memory(&mut self, addr: u64, bytes: &[u8]) -> Result<()>;
+    /// The general purpose registers
+    fn regs(&self) -> Result<user_regs_struct>;
+    fn set_regs(&mut self, regs: user_regs_struct) -> Result<()>;
+    /// The x87 and SSE registers
+    fn fpregs(&self) -> Result<user_fpregs_struct>;
+    fn set_fpregs(&mut self, regs: user_fpregs_struct) -> Result<()>;
+    /// Lets the program run until it next stops, which `wait` reports
+    fn resume(&mut self) -> Result<()>;
+    /// Lets the program execute a single instruction, which `wait` reports
+    fn step(&mut self) -> Result<()>;
+    /// Waits for the program to stop
+    fn wait(&mut self) -> Result<StopEvent>;
+    /// The files mapped into memory, which symbols and debug information are
+    /// loaded from
+    fn mappings(&self) -> Vec<Mapping>;
+
+    /// The registers of each thread, the one the debugger follows first
+    fn threads(&self) -> Result<Vec<Thread>> {
+        Ok(vec![Thread {
+            tid: 0,
+            regs: self.regs()?,
+            fpregs: self.fpregs().ok(),
+        }])
+    }
+    /// Follows another thread, by its id, which `threads` then gives first
+    fn select_thread(&mut self, tid: i32) -> Result<()> {
+        match self.threads()?.first() {
+            Some(thread) if thread.tid == tid => Ok(()),
+            _ => Err(Error::NotFound(format!("No thread {tid}"))),
+        }
+    }
+    /// Whether the debugger attached to the program rather than starting it,
+    /// so it's left running once debugging ends
+    fn attached(&self) -> bool {
+        false
+    }
+    /// Stops debugging the program, leaving it running. Breakpoints are
+    /// removed first
+    fn detach(&mut self) -> Result<()> {
+        Ok(())
+    }
+    /// All of the program's memory, including memory not mapped from a file
+    fn regions(&self) -> Vec<Region> {
+        Vec::new()
+    }
+    /// The auxiliary vector the kernel passed the program, as raw bytes
+    fn auxv(&self) -> Result<Vec<u8>> {
+        Ok(Vec::new())
+    }
+
+    /// Inserts a breakpoint, giving the byte of the instruction it replaces
+    fn insert_breakpoint(&mut self, addr: u64) -> Result<u8> {
+        let old = self.read_memory(addr, 1)?[0];
+        self.write_memory(addr, &[Breakpoint::BKPT_OPCODE])?;
+        Ok(old)
+    }
+    fn remove_breakpoint(&mut self, addr: u64, old: u8) -> Result<()> {
+        self.write_memory(addr, &[old])
+    }
+    /// How far past a breakpoint the PC is when it's hit, which is after its
+    /// int3 unless the target moves it back
+    fn decr_pc_after_break(&self) -> u64 {
+        1
+    }
+
+    /// The address of the next instruction to execute
+    fn pc(&self) -> Result<u64> {
+        Ok(self.regs()?.rip)
+    }
+}
+
+/// Stands in for the program before one is started or connected to
+pub struct NoTarget;
+
+impl Target for NoTarget {
+    fn read_memory(&self, _addr: u64, _len: usize) -> Result<Vec<u8>> {
+        Err(Error::NoProcess)
+    }
+
+    fn write_memory(&mut self, _addr: u64, _bytes: &[u8]) -> Result<()> {
+        Err(Error::NoProcess)
+    }
+
+    fn regs(&self) -> Result<user_regs_struct> {
+        Err(Error::NoProcess)
+    }
+
+    fn set_regs(&mut self, _regs: user_regs_struct) -> Result<()> {
+        Err(Error::NoProcess)
+    }
+
+    fn fpregs(&self) -> Result<user_fpregs_struct> {
+        Err(Error::NoProcess)
+    }
+
+    fn set_fpregs(&mut self, _regs: user_fpregs_struct) -> Result<()> {
+        Err(Error::NoProcess)
+    }
+
+    fn resume(&mut self) -> Result<()> {
+        Err(Error::NoProcess)
+    }
+
+    fn step(&mut self) -> Result<()> {
+        Err(Error::NoProcess)
+    }
+
+    fn wait(&mut self) -> Result<StopEvent> {
+        Err(Error::NoProcess)
+    }
+
+    fn mappings(&self) -> Vec<Mapping> {
+        Vec::new()
+    }
+}
+
+/// Why the program stopped running
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum StopEvent {
+    /// Hit the breakpoint with this number, at its address
+    Breakpoint { num: u32, addr: u64 },
+    /// Finished executing a single instruction
+    Step { pc: u64 },
+    /// Received a signal, which is passed on to the program when it next
+    /// runs, unless it was a trap or an interrupt meant for the debugger
+    Signal(Signal),
+    /// Exited with this status code
+    Exited(i32),
+    /// Was killed by a signal
+    Killed(Signal),
+}
+
+/// A range of memory mapped from a file, like a line of `/proc/<pid>/maps`
+#[derive(Debug, Clone)]
+pub struct Mapping {
+    pub range: Range<u64>,
+    pub executable: bool,
+    /// Offset in the file the range starts at
+    pub offset: u64,
+    pub path: PathBuf,
+}
+
+/// A range of memory and its permissions, like any line of `/proc/<pid>/maps`
+#[derive(Debug, Clone)]
+pub struct Region {
+    pub range: Range<u64>,
+    pub readable: bool,
+    pub writable: bool,
+    pub executable: bool,
+    /// Offset in the file the range starts at
+    pub offset: u64,
+    /// The file the range is mapped from, if any
+    pub path: Option<PathBuf>,
+}
+
+/// Parses `/proc/<pid>/maps`, each line of which has the format
+/// `start-end perms offset dev inode [path]`
+pub(crate) fn parse_maps(maps: &str) -> Vec<Region> {
+    maps.lines()
+        .filter_map(|line| {
+            let mut fields = line.splitn(6, ' ');
+            let (start, end) = fields.next()?.split_once('-')?;
+            let perms = fields.next()?;
+            let offset = fields.next()?;
+            // The path is padded, and may have spaces of its own. Anonymous
+            // memory has no path, or a name such as `[stack]`, and memory
+            // from a deleted file can't be read back from the path
+            let path = fields
+                .nth(2)
+                .map(str::trim_start)
+                .filter(|path| path.starts_with('/') && !path.ends_with(" (deleted)"));
+
+            Some(Region {
+                range: u64::from_str_radix(start, 16).ok()?..u64::from_str_radix(end, 16).ok()?,
+                readable: perms.contains('r'),
+                writable: perms.contains('w'),
+                executable: perms.contains('x'),
+                offset: u64::from_str_radix(offset, 16).ok()?,
+                path: path.map(PathBuf::from),
+            })
+        })
+        .collect()
+}
+
+/// The registers of one of the program's threads
+#[derive(Clone)]
+pub struct Thread {
+    pub tid: i32,
+    pub regs: user_regs_struct,
+    pub fpregs: Option<user_fpregs_struct>,
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use std::path::Path;
+
+    #[test]
+    fn maps_paths_keep_their_spaces() {
+        let maps = "\
+55d0c0a00000-55d0c0a01000 r-xp 00001000 fd:01 1234                       /home/me/my project/a.out
+7f0000000000-7f0000001000 rw-p 00000000 00:00 0 
+7ffc0d000000-7ffc0d021000 rw-p 00000000 00:00 0                          [stack]
+7f0000002000-7f0000003000 r-xp 00002000 fd:01 5678                       /tmp/libold.so (deleted)
+";
+        let regions = parse_maps(maps);
+
+        assert_eq!(regions.len(), 4);
+        assert_eq!(regions[0].range, 0x55d0c0a00000..0x55d0c0a01000);
+        assert_eq!(regions[0].offset, 0x1000);
+        assert!(regions[0].executable && !regions[0].writable);
+        assert_eq!(
+            regions[0].path.as_deref(),
+            Some(Path::new("/home/me/my project/a.out"))
+        );
+        assert_eq!(regions[1].path, None);
+        assert_eq!(regions[2].path, None);
+        assert_eq!(regions[3].path, None);
+    }
+}
@@ -0,0 +1,686 @@
+use std::cell::Cell;
+
+use gimli::{constants, AttributeValue, DebuggingInformationEntry, DwAte, Dwarf, Unit, UnitOffset};
+
+use crate::dwarf;
+use crate::memory;
+use crate::module::Reader;
+use crate::pretty;
+use crate::target::Target;
+
+/// A type from `.debug_info`, describing how to interpret a value's bytes.
+/// Types it refers to are kept as DIE offsets and decoded when needed, as
+/// types such as linked list nodes refer to themselves
+pub struct Type {
+    /// Offset of the type's DIE
+    pub offset: UnitOffset,
+    pub name: String,
+    pub size: u64,
+    pub kind: TypeKind,
+}
+
+pub enum TypeKind {
+    Base(DwAte),
+    Pointer(Option<UnitOffset>),
+    Struct(Vec<Member>),
+    Union(Vec<Member>),
+    /// A C-style enumeration of named values
+    Enum(Vec<(String, i64)>),
+    /// A Rust enum, which is a tagged union of variants
+    Variants {
+        /// The member holding the discriminant, absent for single variant enums
+        discr: Option<Member>,
+        variants: Vec<Variant>,
+    },
+    Array {
+        element: UnitOffset,
+        /// Lengths of each dimension, outermost first
+        dims: Vec<u64>,
+    },
+    /// A type whose layout isn't understood, shown as raw bytes
+    Opaque,
+}
+
+pub struct Member {
+    pub name: String,
+    /// Offset of the member from the start of the containing value, in bytes
+    pub offset: u64,
+    pub ty: Option<UnitOffset>,
+    /// Offset and size in bits, for bit fields
+    bits: Option<(u64, u64)>,
+}
+
+pub struct Variant {
+    /// The discriminant value selecting this variant, absent for the variant
+    /// used when no other matches
+    discr_value: Option<u64>,
+    member: Member,
+}
+
+/// Stop printing arrays and collections after this many elements
+pub const MAX_ELEMENTS: u64 = 200;
+
+/// Stop following pointers to the values they point to this many deep, as
+/// lists can be long and `Rc`s can point back to themselves
+const MAX_DEPTH: usize = 8;
+
+/// Decodes the types in a compilation unit and formats values of them
+pub struct Types<'a> {
+    target: &'a dyn Target,
+    dwarf: &'a Dwarf<Reader>,
+    unit: &'a Unit<Reader>,
+    /// How many pointers the value being formatted was reached through
+    depth: Cell<usize>,
+}
+
+impl<'a> Types<'a> {
+    pub fn new(target: &'a dyn Target, dwarf: &'a Dwarf<Reader>, unit: &'a Unit<Reader>) -> Self {
+        Types {
+            target,
+            dwarf,
+            unit,
+            depth: Cell::new(0),
+        }
+    }
+
+    pub fn decode(&self, offset: UnitOffset) -> Option<Type> {
+        let entry = self.unit.entry(offset).ok()?;
+        let name = dwarf::attr_string(self.dwarf, self.unit, &entry, constants::DW_AT_name);
+        let size = udata(&entry, constants::DW_AT_byte_size);
+        let inner = type_attr(&entry);
+
+        let kind = match entry.tag() {
+            constants::DW_TAG_base_type => {
+                let Ok(Some(AttributeValue::Encoding(encoding))) =
+                    entry.attr_value(constants::DW_AT_encoding)
+                else {
+                    return None;
+                };
+                TypeKind::Base(encoding)
+            }
+            // Typedefs and qualifiers have the layout of the type they refer to
+            tag if tag == constants::DW_TAG_typedef || dwarf::is_qualifier(tag) => {
+                let mut ty = self.decode(inner?)?;
+                if let Some(name) = name.filter(|_| tag == constants::DW_TAG_typedef) {
+                    ty.name = name;
+                }
+                return Some(ty);
+            }
+            constants::DW_TAG_pointer_type
+            | constants::DW_TAG_reference_type
+            | constants::DW_TAG_rvalue_reference_type => {
+                let name = match (name, inner.and_then(|inner| self.decode(inner))) {
+                    (Some(name), _) => name,
+                    (None, Some(pointee)) => format!("{} *", pointee.name),
+                    (None, None) => "void *".into(),
+                };
+                return Some(Type {
+                    offset,
+                    name,
+                    size: size.unwrap_or(8),
+                    kind: TypeKind::Pointer(inner),
+                });
+            }
+            constants::DW_TAG_structure_type | constants::DW_TAG_class_type => {
+                self.decode_struct(offset)?
+            }
+            constants::DW_TAG_union_type => TypeKind::Union(self.members(offset)?),
+            constants::DW_TAG_enumeration_type => {
+                let mut enumerators = Vec::new();
+                self.for_each_child(offset, |entry| {
+                    if entry.tag() == constants::DW_TAG_enumerator {
+                        let name = dwarf::attr_string(
+                            self.dwarf,
+                            self.unit,
+                            entry,
+                            constants::DW_AT_name,
+                        )?;
+                        let value = match entry.attr_value(constants::DW_AT_const_value).ok()?? {
+                            AttributeValue::Sdata(value) => value,
+                            value => value.udata_value()? as i64,
+                        };
+                        enumerators.push((name, value));
+                    }
+                    Some(())
+                })?;
+                TypeKind::Enum(enumerators)
+            }
+            constants::DW_TAG_array_type => {
+                let mut dims = Vec::new();
+                self.for_each_child(offset, |entry| {
+                    if entry.tag() == constants::DW_TAG_subrange_type {
+                        let len = match udata(entry, constants::DW_AT_count) {
+                            Some(count) => count,
+                            None => udata(entry, constants::DW_AT_upper_bound)
+                                .map_or(Some(0), |u| u.checked_add(1))?,
+                        };
+                        dims.push(len);
+                    }
+                    Some(())
+                })?;
+                let element = inner?;
+                let element_size = self.decode(element)?.size;
+                let len = dims
+                    .iter()
+                    .try_fold(1u64, |len, &dim| len.checked_mul(dim))?;
+                return Some(Type {
+                    offset,
+                    name: name.unwrap_or_else(|| "[]".into()),
+                    size: size.or_else(|| element_size.checked_mul(len))?,
+                    kind: TypeKind::Array { element, dims },
+                });
+            }
+            _ => TypeKind::Opaque,
+        };
+
+        Some(Type {
+            offset,
+            name: name.unwrap_or_else(|| "?".into()),
+            size: size?,
+            kind,
+        })
+    }
+
+    /// Decodes a structure, which is a Rust enum if it has a variant part
+    fn decode_struct(&self, offset: UnitOffset) -> Option<TypeKind> {
+        let mut variant_part = None;
+        self.for_each_child(offset, |entry| {
+            if entry.tag() == constants::DW_TAG_variant_part {
+                variant_part = Some(entry.offset());
+            }
+            Some(())
+        })?;
+        let Some(variant_part) = variant_part else {
+            return Some(TypeKind::Struct(self.members(offset)?));
+        };
+
+        let part = self.unit.entry(variant_part).ok()?;
+        let discr = match part.attr_value(constants::DW_AT_discr).ok()? {
+            Some(AttributeValue::UnitRef(discr)) => {
+                let entry = self.unit.entry(discr).ok()?;
+                Some(self.member(&entry)?)
+            }
+            _ => None,
+        };
+
+        let mut variants = Vec::new();
+        self.for_each_child(variant_part, |entry| {
+            if entry.tag() != constants::DW_TAG_variant {
+                return Some(());
+            }
+            let discr_value = entry
+                .attr_value(constants::DW_AT_discr_value)
+                .ok()?
+                .and_then(|value| match value {
+                    AttributeValue::Sdata(value) => Some(value as u64),
+                    value => value.udata_value(),
+                });
+            let mut member = None;
+            self.for_each_child(entry.offset(), |entry| {
+                if entry.tag() == constants::DW_TAG_member {
+                    member = self.member(entry);
+                }
+                Some(())
+            })?;
+            if let Some(member) = member {
+                variants.push(Variant {
+                    discr_value,
+                    member,
+                });
+            }
+            Some(())
+        })?;
+
+        Some(TypeKind::Variants { discr, variants })
+    }
+
+    fn members(&self, offset: UnitOffset) -> Option<Vec<Member>> {
+        let mut members = Vec::new();
+        self.for_each_child(offset, |entry| {
+            // Static members have no location in the value
+            if entry.tag() == constants::DW_TAG_member
+                && entry.attr_value(constants::DW_AT_external).ok()?.is_none()
+            {
+                if let Some(member) = self.member(entry) {
+                    members.push(member);
+                }
+            }
+            Some(())
+        })?;
+        Some(members)
+    }
+
+    fn member(&self, entry: &DebuggingInformationEntry<Reader>) -> Option<Member> {
+        let name = dwarf::attr_string(self.dwarf, self.unit, entry, constants::DW_AT_name);
+        let ty = type_attr(entry);
+        // Absent in unions, and for bit fields from DWARF 4 on
+        let location = match entry
+            .attr_value(constants::DW_AT_data_member_location)
+            .ok()?
+        {
+            None => 0,
+            Some(AttributeValue::Exprloc(expr)) => member_location(expr, self.unit.encoding())?,
+            Some(value) => value.udata_value()?,
+        };
+
+        let (offset, bits) = match udata(entry, constants::DW_AT_bit_size) {
+            None => (location, None),
+            Some(size) => {
+                let bit_offset = match udata(entry, constants::DW_AT_data_bit_offset) {
+                    Some(bit_offset) => bit_offset,
+                    // DWARF 2 and 3 count from the top of the field's storage
+                    None => {
+                        let storage = match udata(entry, constants::DW_AT_byte_size) {
+                            Some(size) => size,
+                            None => self.decode(ty?)?.size,
+                        };
+                        let from_msb = udata(entry, constants::DW_AT_bit_offset)?;
+                        location
+                            .checked_mul(8)?
+                            .checked_add(lsb_bit_offset(storage, from_msb, size)?)?
+                    }
+                };
+                (0, Some((bit_offset, size)))
+            }
+        };
+        Some(Member {
+            name: name.unwrap_or_default(),
+            offset,
+            ty,
+            bits,
+        })
+    }
+
+    fn for_each_child<F>(&self, offset: UnitOffset, mut f: F) -> Option<()>
+    where
+        F: FnMut(&DebuggingInformationEntry<Reader>) -> Option<()>,
+    {
+        let mut tree = self.unit.entries_tree(Some(offset)).ok()?;
+        let root = tree.root().ok()?;
+        let mut children = root.children();
+        while let Ok(Some(child)) = children.next() {
+            f(child.entry())?;
+        }
+        Some(())
+    }
+
+    /// Finds the type given for a generic parameter, such as `T` in `Vec<T>`
+    pub fn template_param(&self, ty: &Type, name: &str) -> Option<UnitOffset> {
+        let mut param = None;
+        self.for_each_child(ty.offset, |entry| {
+            if entry.tag() == constants::DW_TAG_template_type_parameter
+                && dwarf::attr_string(self.dwarf, self.unit, entry, constants::DW_AT_name)
+                    .is_some_and(|n| n == name)
+            {
+                param = type_attr(entry);
+            }
+            Some(())
+        })?;
+        param
+    }
+
+    /// Looks up a member of a struct value by name
+    pub fn field<'b>(&self, ty: &Type, bytes: &'b [u8], name: &str) -> Option<(Type, &'b [u8])> {
+        let (TypeKind::Struct(members) | TypeKind::Union(members)) = &ty.kind else {
+            return None;
+        };
+        let member = members.iter().find(|member| member.name == name)?;
+        let member_ty = self.decode(member.ty?)?;
+        let bytes = slice(bytes, member.offset, member_ty.size);
+        Some((member_ty, bytes))
+    }
+
+    /// Finds the first pointer in a value, looking through wrappers such as
+    /// `NonNull` and `Unique`, returning its address and what it points to
+    pub fn find_pointer(&self, ty: &Type, bytes: &[u8]) -> Option<(u64, Option<UnitOffset>)> {
+        match &ty.kind {
+            TypeKind::Pointer(pointee) => Some((read_uint(bytes, 0, 8), *pointee)),
+            TypeKind::Struct(members) => members.iter().find_map(|member| {
+                let member_ty = self.decode(member.ty?)?;
+                self.find_pointer(&member_ty, slice(bytes, member.offset, member_ty.size))
+            }),
+            _ => None,
+        }
+    }
+
+    /// Reads an integer, looking through single field wrappers such as `Cell`
+    pub fn uint(&self, ty: &Type, bytes: &[u8]) -> Option<u64> {
+        match &ty.kind {
+            TypeKind::Base(_) | TypeKind::Pointer(_) => Some(read_uint(bytes, 0, ty.size)),
+            TypeKind::Struct(members) if members.len() == 1 => {
+                let member_ty = self.decode(members[0].ty?)?;
+                self.uint(&member_ty, slice(bytes, members[0].offset, member_ty.size))
+            }
+            _ => None,
+        }
+    }
+
+    /// Reads raw bytes from the tracee's memory
+    pub fn read_bytes(&self, addr: u64, len: u64) -> Option<Vec<u8>> {
+        memory::read(self.target, addr, len as usize)
+    }
+
+    /// Reads a value of the type at `offset` from the tracee's memory
+    pub fn read(&self, offset: UnitOffset, addr: u64) -> Option<(Type, Vec<u8>)> {
+        let ty = self.decode(offset)?;
+        let bytes = self.read_bytes(addr, ty.size)?;
+        Some((ty, bytes))
+    }
+
+    /// Formats a value that was reached through a pointer, or `...` once
+    /// `MAX_DEPTH` pointers have been followed
+    pub fn format_pointee(&self, ty: &Type, bytes: &[u8]) -> String {
+        let depth = self.depth.get();
+        if depth >= MAX_DEPTH {
+            return "...".into();
+        }
+        self.depth.set(depth + 1);
+        let value = self.format(ty, bytes);
+        self.depth.set(depth);
+        value
+    }
+
+    /// Formats a value of the type from its bytes
+    pub fn format(&self, ty: &Type, bytes: &[u8]) -> String {
+        if let TypeKind::Struct(_) | TypeKind::Pointer(_) = ty.kind {
+            if let Some(value) = pretty::format(self, ty, bytes) {
+                return value;
+            }
+        }
+
+        match &ty.kind {
+            TypeKind::Base(encoding) => format_base(*encoding, ty.size, bytes),
+            TypeKind::Pointer(_) => format!("{:#x}", read_uint(bytes, 0, 8)),
+            TypeKind::Struct(members) => self.format_struct(&ty.name, members, bytes),
+            TypeKind::Union(members) => self.format_struct("", members, bytes),
+            TypeKind::Enum(enumerators) => {
+                let value = read_uint(bytes, 0, ty.size) as i64;
+                match enumerators.iter().find(|(_, v)| *v == value) {
+                    Some((name, _)) => name.clone(),
+                    None => value.to_string(),
+                }
+            }
+            TypeKind::Variants { discr, variants } => {
+                let Some(variant) = active_variant(discr.as_ref(), variants, bytes, self) else {
+                    return format!("{} <invalid discriminant>", ty.name);
+                };
+                let member = &variant.member;
+                match member.ty.and_then(|ty| self.decode(ty)) {
+                    Some(variant_ty) => {
+                        let bytes = slice(bytes, member.offset, variant_ty.size);
+                        match &variant_ty.kind {
+                            TypeKind::Struct(members) => {
+                                self.format_struct(&member.name, members, bytes)
+                            }
+                            _ => self.format(&variant_ty, bytes),
+                        }
+                    }
+                    None => member.name.clone(),
+                }
+            }
+            TypeKind::Array { element, dims } => match self.decode(*element) {
+                Some(element) => self.format_array(&element, dims, bytes),
+                None => "[?]".into(),
+            },
+            TypeKind::Opaque => {
+                let bytes: Vec<String> = bytes.iter().map(|b| format!("{b:#04x}")).collect();
+                format!("{} {{{}}}", ty.name, bytes.join(", "))
+            }
+        }
+    }
+
+    /// Formats a struct as Rust would: fields of tuples and tuple structs are
+    /// named `__0`, `__1` and so on, and are shown without names
+    fn format_struct(&self, name: &str, members: &[Member], bytes: &[u8]) -> String {
+        if members.is_empty() {
+            return name.to_string();
+        }
+
+        let fields: Vec<String> = members
+            .iter()
+            .map(|member| self.format_member(member, bytes))
+            .collect();
+        let is_tuple = members.iter().all(|member| member.name.starts_with("__"));
+        if is_tuple {
+            // Tuple types are named like `(i32, i64)` already
+            let name = if name.starts_with('(') { "" } else { name };
+            format!("{name}({})", fields.join(", "))
+        } else {
+            let fields: Vec<String> = members
+                .iter()
+                .zip(fields)
+                .map(|(member, value)| format!("{}: {value}", member.name))
+                .collect();
+            match name {
+                "" => format!("{{ {} }}", fields.join(", ")),
+                name => format!("{name} {{ {} }}", fields.join(", ")),
+            }
+        }
+    }
+
+    fn format_member(&self, member: &Member, bytes: &[u8]) -> String {
+        let Some(ty) = member.ty.and_then(|ty| self.decode(ty)) else {
+            return "?".into();
+        };
+        match member.bits {
+            Some((bit_offset, bit_size)) => {
+                let mut value = read_bits(bytes, bit_offset, bit_size);
+                if let TypeKind::Base(constants::DW_ATE_signed | constants::DW_ATE_signed_char) =
+                    ty.kind
+                {
+                    value = sign_extend(value, bit_size);
+                }
+                self.format(&ty, &value.to_le_bytes()[..ty.size.min(8) as usize])
+            }
+            None => self.format(&ty, slice(bytes, member.offset, ty.size)),
+        }
+    }
+
+    fn format_array(&self, element: &Type, dims: &[u64], bytes: &[u8]) -> String {
+        let Some((&len, inner)) = dims.split_first() else {
+            return self.format(element, bytes);
+        };
+        let stride = element.size * inner.iter().product::<u64>();
+
+        let mut items: Vec<String> = (0..len.min(MAX_ELEMENTS))
+            .map(|i| self.format_array(element, inner, slice(bytes, i * stride, stride)))
+            .collect();
+        if len > MAX_ELEMENTS {
+            items.push("...".into());
+        }
+        format!("[{}]", items.join(", "))
+    }
+
+    /// Follows a pointer, returning the type and bytes of the value it points at
+    pub fn deref(&self, ty: &Type, bytes: &[u8]) -> Option<(Type, Vec<u8>)> {
+        let TypeKind::Pointer(Some(pointee)) = ty.kind else {
+            return None;
+        };
+        self.read(pointee, read_uint(bytes, 0, 8))
+    }
+}
+
+fn active_variant<'v>(
+    discr: Option<&Member>,
+    variants: &'v [Variant],
+    bytes: &[u8],
+    types: &Types,
+) -> Option<&'v Variant> {
+    let Some(discr) = discr else {
+        return variants.first();
+    };
+    let size = discr.ty.and_then(|ty| types.decode(ty))?.size;
+    let value = read_uint(bytes, discr.offset, size);
+
+    variants
+        .iter()
+        .find(|variant| variant.discr_value.is_some_and(|v| v & mask(size) == value))
+        .or_else(|| {
+            variants
+                .iter()
+                .find(|variant| variant.discr_value.is_none())
+        })
+}
+
+fn type_attr(entry: &DebuggingInformationEntry<Reader>) -> Option<UnitOffset> {
+    match entry.attr_value(constants::DW_AT_type) {
+        Ok(Some(AttributeValue::UnitRef(offset))) => Some(offset),
+        _ => None,
+    }
+}
+
+fn udata(entry: &DebuggingInformationEntry<Reader>, name: gimli::DwAt) -> Option<u64> {
+    entry.attr_value(name).ok()??.udata_value()
+}
+
+/// Decodes a `DW_AT_data_member_location` given as an expression, which
+/// DWARF 2 producers emit as a single `DW_OP_plus_uconst` of the offset
+fn member_location(expr: gimli::Expression<Reader>, encoding: gimli::Encoding) -> Option<u64> {
+    let mut ops = expr.operations(encoding);
+    let offset = match ops.next().ok()?? {
+        gimli::Operation::PlusConstant { value } => value,
+        gimli::Operation::UnsignedConstant { value } => value,
+        _ => return None,
+    };
+    // Anything further would need the address of the containing value
+    ops.next().ok()?.is_none().then_some(offset)
+}
+
+/// Converts a DWARF 2 `DW_AT_bit_offset`, from the most significant bit of
+/// the field's storage unit, to an offset from its least significant bit
+fn lsb_bit_offset(storage: u64, from_msb: u64, bit_size: u64) -> Option<u64> {
+    storage
+        .checked_mul(8)?
+        .checked_sub(from_msb)?
+        .checked_sub(bit_size)
+}
+
+/// The bytes of a value at `offset` into its containing value
+fn slice(bytes: &[u8], offset: u64, size: u64) -> &[u8] {
+    let start = (offset as usize).min(bytes.len());
+    let end = offset.saturating_add(size).min(bytes.len() as u64) as usize;
+    &bytes[start..end]
+}
+
+fn read_uint(bytes: &[u8], offset: u64, size: u64) -> u64 {
+    let mut raw = [0; 8];
+    let bytes = slice(bytes, offset, size.min(8));
+    raw[..bytes.len()].copy_from_slice(bytes);
+    u64::from_le_bytes(raw)
+}
+
+/// Reads a bit field. Fields needn't start on a byte, so one of 64 bits can
+/// span nine bytes
+fn read_bits(bytes: &[u8], bit_offset: u64, bit_size: u64) -> u64 {
+    let mut raw = [0; 16];
+    let bytes = slice(bytes, bit_offset / 8, 16);
+    raw[..bytes.len()].copy_from_slice(bytes);
+    let value = (u128::from_le_bytes(raw) >> (bit_offset % 8)) as u64;
+    let mask = u64::MAX.checked_shr(64 - bit_size.min(64) as u32);
+    value & mask.unwrap_or(0)
+}
+
+/// Extends the sign bit of a `bit_size` wide value to the rest of it
+fn sign_extend(value: u64, bit_size: u64) -> u64 {
+    match bit_size {
+        1..=63 => ((value << (64 - bit_size)) as i64 >> (64 - bit_size)) as u64,
+        _ => value,
+    }
+}
+
+fn mask(size: u64) -> u64 {
+    match size {
+        0..=7 => (1 << (size * 8)) - 1,
+        _ => u64::MAX,
+    }
+}
+
+fn format_base(encoding: DwAte, size: u64, bytes: &[u8]) -> String {
+    let mut raw = [0; 16];
+    let len = bytes.len().min(16);
+    raw[..len].copy_from_slice(&bytes[..len]);
+    let unsigned = u128::from_le_bytes(raw);
+    // Sign extend from the value's width
+    let shift = 128 - size.clamp(1, 16) * 8;
+    let signed = ((unsigned << shift) as i128) >> shift;
+
+    match encoding {
+        constants::DW_ATE_boolean => (unsigned != 0).to_string(),
+        constants::DW_ATE_float if size == 4 => f32::from_bits(unsigned as u32).to_string(),
+        constants::DW_ATE_float if size == 8 => f64::from_bits(unsigned as u64).to_string(),
+        constants::DW_ATE_signed => signed.to_string(),
+        constants::DW_ATE_signed_char => format!("{signed} {:?}", unsigned as u8 as char),
+        constants::DW_ATE_unsigned_char => format!("{unsigned} {:?}", unsigned as u8 as char),
+        constants::DW_ATE_UTF => match char::from_u32(unsigned as u32) {
+            Some(c) => format!("{c:?}"),
+            None => format!("{unsigned:#x}"),
+        },
+        constants::DW_ATE_address => format!("{unsigned:#x}"),
+        _ => unsigned.to_string(),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn bit_fields_are_masked_to_their_size() {
+        let bytes = [0b1011_0110, 0xff];
+
+        assert_eq!(read_bits(&bytes, 1, 3), 0b011);
+        assert_eq!(read_bits(&bytes, 4, 8), 0xfb);
+        assert_eq!(read_bits(&bytes, 0, 0), 0);
+    }
+
+    #[test]
+    fn bit_fields_can_be_64_bits_across_nine_bytes() {
+        let mut bytes = [0xff; 9];
+        bytes[0] = 0x0f;
+        bytes[8] = 0x01;
+
+        assert_eq!(read_bits(&bytes, 4, 64), 0x1fff_ffff_ffff_fff0);
+        assert_eq!(read_bits(&bytes, 0, 64), 0xffff_ffff_ffff_ff0f);
+    }
+
+    #[test]
+    fn signed_bit_fields_are_sign_extended() {
+        assert_eq!(sign_extend(0b110, 3) as i64, -2);
+        assert_eq!(sign_extend(0b010, 3), 2);
+        assert_eq!(sign_extend(u64::MAX, 64), u64::MAX);
+    }
+
+    #[test]
+    fn dwarf_2_bit_offsets_count_from_the_top() {
+        // `int a : 3; int b : 5;` on x86-64
+        assert_eq!(lsb_bit_offset(4, 29, 3), Some(0));
+        assert_eq!(lsb_bit_offset(4, 24, 5), Some(3));
+        assert_eq!(lsb_bit_offset(4, 30, 3), None);
+    }
+
+    fn expression(bytes: &[u8]) -> gimli::Expression<Reader> {
+        gimli::Expression(Reader::new(bytes.into(), gimli::RunTimeEndian::Little))
+    }
+
+    #[test]
+    fn member_locations_can_be_expressions() {
+        let encoding = gimli::Encoding {
+            format: gimli::Format::Dwarf32,
+            version: 2,
+            address_size: 8,
+        };
+
+        // DW_OP_plus_uconst 0x90
+        assert_eq!(
+            member_location(expression(&[0x23, 0x90, 0x01]), encoding),
+            Some(0x90)
+        );
+        // DW_OP_plus_uconst 8; DW_OP_deref, as for a virtual base class
+        assert_eq!(
+            member_location(expression(&[0x23, 0x08, 0x06]), encoding),
+            None
+        );
+        // DW_OP_dup
+        assert_eq!(member_location(expression(&[0x12]), encoding), None);
+    }
+}
@@ -0,0 +1,253 @@
+use gimli::{
+    CfaRule, Encoding, EvaluationResult, Expression, Format, Location, RegisterRule, UnwindContext,
+    UnwindSection, UnwindTableRow, Value,
+};
+use nix::libc::user_regs_struct;
+
+use crate::memory;
+use crate::module::{self, Module, Reader};
+use crate::register::{Register, RegisterSelector};
+use crate::target::Target;
+
+/// A frame on the call stack, with the register values as they were in it
+#[derive(Clone)]
+pub struct Frame {
+    pub pc: u64,
+    /// Canonical frame address: the value of rsp before the call into the frame
+    pub cfa: Option<u64>,
+    pub regs: user_regs_struct,
+    /// The registers whose values in the frame are known, a bit for each
+    /// DWARF number. The innermost frame has every register
+    pub available: u64,
+}
+
+impl Frame {
+    /// Whether the register's value in the frame is known, rather than lost
+    /// to a call that was free to change it
+    pub fn has(&self, reg: &Register) -> bool {
+        match reg.dwarf_no() {
+            Some(no @ 0..64) => self.available & (1 << no) != 0,
+            _ => self.available == u64::MAX,
+        }
+    }
+}
+
+/// The DWARF register number of the return address column in x86-64 CFI
+const RETURN_ADDRESS: u16 = 16;
+
+/// Registers a call leaves as they were, which are the callee-saved rbx, rbp,
+/// r12 to r15 and the segment registers, along with rsp and rip, which
+/// unwinding recovers
+const PRESERVED: u64 =
+    1 << 3 | 1 << 6 | 1 << 7 | 0xf << 12 | 1 << RETURN_ADDRESS | 0x3f << 50 | 0x3 << 58;
+
+/// Stop unwinding runaway stacks, e.g. ones corrupted by a buffer overflow
+const MAX_FRAMES: usize = 1024;
+
+/// Unwinds the stack starting from the given registers, using the CFI in
+/// `.eh_frame` or `.debug_frame` of the module each frame's code lives in
+pub fn backtrace(target: &dyn Target, modules: &[Module], regs: user_regs_struct) -> Vec<Frame> {
+    let mut ctx = UnwindContext::new();
+    let mut frames = Vec::new();
+    let mut frame = Frame {
+        pc: regs.rip,
+        cfa: None,
+        regs,
+        available: u64::MAX,
+    };
+
+    loop {
+        // The return address of a caller points past the call instruction,
+        // which can be the start of another function if the call never returns
+        let addr = if frames.is_empty() {
+            frame.pc
+        } else {
+            frame.pc - 1
+        };
+
+        let caller = unwind(target, modules, &mut ctx, &mut frame, addr);
+        frames.push(frame);
+
+        match caller {
+            Some(caller) if caller.pc != 0 && frames.len() < MAX_FRAMES => frame = caller,
+            _ => break,
+        }
+    }
+
+    frames
+}
+
+/// Computes the caller of `frame`, filling in the frame's CFA on the way
+fn unwind(
+    target: &dyn Target,
+    modules: &[Module],
+    ctx: &mut UnwindContext<usize>,
+    frame: &mut Frame,
+    addr: u64,
+) -> Option<Frame> {
+    let module = module::find(modules, addr)?;
+    let addr = addr - module.bias;
+
+    if let Some(eh_frame) = &module.eh_frame {
+        if let Some(row) = unwind_row(eh_frame, module, ctx, addr) {
+            return apply(target, eh_frame, &row, frame);
+        }
+    }
+    if let Some(debug_frame) = &module.debug_frame {
+        if let Some(row) = unwind_row(debug_frame, module, ctx, addr) {
+            return apply(target, debug_frame, &row, frame);
+        }
+    }
+    None
+}
+
+fn unwind_row<S: UnwindSection<Reader>>(
+    section: &S,
+    module: &Module,
+    ctx: &mut UnwindContext<usize>,
+    addr: u64,
+) -> Option<UnwindTableRow<usize>> {
+    section
+        .unwind_info_for_address(&module.bases, ctx, addr, S::cie_from_offset)
+        .ok()
+        .cloned()
+}
+
+/// Applies the CFA and register rules of an unwind table row to recover the
+/// caller's registers
+fn apply<S: UnwindSection<Reader>>(
+    target: &dyn Target,
+    section: &S,
+    row: &UnwindTableRow<usize>,
+    frame: &mut Frame,
+) -> Option<Frame> {
+    let cfa = match row.cfa() {
+        CfaRule::RegisterAndOffset { register, offset } => {
+            read_register(frame, register.0)?.wrapping_add(*offset as u64)
+        }
+        CfaRule::Expression(expr) => evaluate(target, expr.get(section).ok()?, frame, None)?,
+    };
+    frame.cfa = Some(cfa);
+
+    // The outermost frame marks its return address as undefined
+    if let RegisterRule::Undefined = row.register(gimli::Register(RETURN_ADDRESS)) {
+        return None;
+    }
+
+    // Registers without a rule keep the callee's values, which are only right
+    // for the ones calls preserve
+    let mut regs = frame.regs;
+    let mut available = frame.available & PRESERVED;
+    let mut return_address = None;
+    for (register, rule) in row.registers() {
+        let value = match rule {
+            RegisterRule::Undefined => {
+                available &= !(1 << register.0);
+                continue;
+            }
+            RegisterRule::SameValue => continue,
+            RegisterRule::Offset(offset) => {
+                memory::read_word(target, cfa.wrapping_add(*offset as u64))?
+            }
+            RegisterRule::ValOffset(offset) => cfa.wrapping_add(*offset as u64),
+            RegisterRule::Register(other) => read_register(frame, other.0)?,
+            RegisterRule::Expression(expr) => {
+                let addr = evaluate(target, expr.get(section).ok()?, frame, Some(cfa))?;
+                memory::read_word(target, addr)?
+            }
+            RegisterRule::ValExpression(expr) => {
+                evaluate(target, expr.get(section).ok()?, frame, Some(cfa))?
+            }
+            RegisterRule::Constant(value) => *value,
+            _ => continue,
+        };
+
+        if register.0 == RETURN_ADDRESS {
+            return_address = Some(value);
+        } else if write_register(&mut regs, register.0, value) {
+            available |= 1 << register.0;
+        }
+    }
+
+    let pc = return_address?;
+    regs.rip = pc;
+    regs.rsp = cfa;
+
+    Some(Frame {
+        pc,
+        cfa: None,
+        regs,
+        available: available | 1 << 7 | 1 << RETURN_ADDRESS,
+    })
+}
+
+/// CFI on x86-64 only describes the general purpose registers and the
+/// return address, which are DWARF registers 0 to 16
+fn read_register(frame: &Frame, dwarf_no: u16) -> Option<u64> {
+    if dwarf_no > RETURN_ADDRESS {
+        return None;
+    }
+    let reg = Register::from_selector(RegisterSelector::Dwarf(dwarf_no as i64))?;
+    if !frame.has(&reg) {
+        return None;
+    }
+    reg.read_from(&frame.regs)
+}
+
+/// Gives whether the register was written
+fn write_register(regs: &mut user_regs_struct, dwarf_no: u16, value: u64) -> bool {
+    if dwarf_no > RETURN_ADDRESS {
+        return false;
+    }
+    Register::from_selector(RegisterSelector::Dwarf(dwarf_no as i64))
+        .and_then(|reg| reg.write_to(regs, value))
+        .is_some()
+}
+
+/// Evaluates a DWARF expression from the CFI, which always yields an address
+/// or value rather than a register location
+fn evaluate(
+    target: &dyn Target,
+    expr: Expression<Reader>,
+    frame: &Frame,
+    cfa: Option<u64>,
+) -> Option<u64> {
+    let encoding = Encoding {
+        format: Format::Dwarf32,
+        version: 4,
+        address_size: 8,
+    };
+
+    let mut eval = expr.evaluation(encoding);
+    if let Some(cfa) = cfa {
+        eval.set_initial_value(cfa);
+    }
+
+    let mut result = eval.evaluate().ok()?;
+    loop {
+        result = match result {
+            EvaluationResult::Complete => break,
+            EvaluationResult::RequiresMemory { address, size, .. } => {
+                let word = memory::read_word(target, address)?;
+                let value = match size {
+                    1..=7 => word & ((1 << (size * 8)) - 1),
+                    _ => word,
+                };
+                eval.resume_with_memory(Value::Generic(value)).ok()?
+            }
+            EvaluationResult::RequiresRegister { register, .. } => {
+                let value = read_register(frame, register.0)?;
+                eval.resume_with_register(Value::Generic(value)).ok()?
+            }
+            _ => return None,
+        };
+    }
+
+    match eval.result().first()?.location {
+        Location::Address { address } => Some(address),
+        Location::Value {
+            value: Value::Generic(value),
+        } => Some(value),
+        _ => None,
+    }
+}
@@ -0,0 +1,133 @@
+#![allow(dead_code)]
+
+use std::ffi::OsStr;
+use std::path::{Path, PathBuf};
+use std::process::{Command, Stdio};
+use std::sync::Mutex;
+
+use bkpt::{Debugger, Process};
+
+/// Held while compiling, so tests sharing a fixture don't build it at once
+static COMPILING: Mutex<()> = Mutex::new(());
+
+/// Compiles a fixture from `tests/fixtures` with debug information, giving the
+/// path of the executable. Fixtures are rebuilt when their source changes
+pub fn compile(fixture: &str) -> PathBuf {
+    let source = Path::new(env!("CARGO_MANIFEST_DIR"))
+        .join("tests/fixtures")
+        .join(fixture);
+    let name = source.file_stem().unwrap().to_str().unwrap();
+    let output = Path::new(env!("CARGO_TARGET_TMPDIR")).join(name);
+
+    let _guard = COMPILING.lock().unwrap_or_else(|e| e.into_inner());
+    let modified = |path: &Path| path.metadata().and_then(|meta| meta.modified()).ok();
+    if modified(&output).is_some_and(|built| Some(built) >= modified(&source)) {
+        return output;
+    }
+
+    let mut cmd = match source.extension().and_then(|ext| ext.to_str()) {
+        Some("c") => {
+            let mut cmd = Command::new("cc");
+            cmd.args(["-g", "-O0", "-pthread", "-o"]);
+            cmd
+        }
+        Some("rs") => {
+            let mut cmd = Command::new("rustc");
+            cmd.args(["-g", "-C", "opt-level=0", "-o"]);
+            cmd
+        }
+        _ => panic!("Unknown fixture type {fixture}"),
+    };
+    let status = cmd
+        .arg(&output)
+        .arg(&source)
+        .status()
+        .unwrap_or_else(|e| panic!("Cannot run the compiler for {fixture}: {e}"));
+    assert!(status.success(), "Cannot compile {fixture}");
+    output
+}
+
+/// Starts a debugger on a fixture, stopped before its first instruction
+pub fn launch(fixture: &str) -> Debugger {
+    let path = compile(fixture);
+    let process = Process::launch(path.to_str().unwrap(), &[]).unwrap();
+    Debugger::new(process)
+}
+
+/// Runs the `bkpt` command line on a fixture with commands as its input,
+/// giving everything it printed, with errors last
+pub fn run_script(fixture: &str, commands: &[&str]) -> String {
+    let path = compile(fixture);
+    run_bkpt(&[path.as_os_str()], commands)
+}
+
+/// Runs the `bkpt` command line with arguments and commands as its input,
+/// giving everything it printed, with errors last
+pub fn run_bkpt(args: &[&OsStr], commands: &[&str]) -> String {
+    let output = Command::new(env!("CARGO_BIN_EXE_bkpt"))
+        .args(args)
+        .stdin(Stdio::piped())
+        .stdout(Stdio::piped())
+        .stderr(Stdio::piped())
+        .spawn()
+        .and_then(|mut child| {
+            use std::io::Write;
+            let mut input = commands.join("\n");
+            input.push('\n');
+            child.stdin.take().unwrap().write_all(input.as_bytes())?;
+            child.wait_with_output()
+        })
+        .unwrap();
+    let mut printed = String::from_utf8_lossy(&output.stdout).into_owned();
+    printed.push_str(&String::from_utf8_lossy(&output.stderr));
+    printed
+}
+
+/// Crashes a fixture with core dumps enabled, giving the core the kernel
+/// writes, or `None` if `core_pattern` sends cores elsewhere
+pub fn kernel_core(fixture: &str) -> Option<PathBuf> {
+    let path = compile(fixture);
+    let dir = Path::new(env!("CARGO_TARGET_TMPDIR")).join(format!("core-{fixture}"));
+    let _ = std::fs::remove_dir_all(&dir);
+    std::fs::create_dir_all(&dir).unwrap();
+
+    Command::new("sh")
+        .arg("-c")
+        .arg("ulimit -c unlimited; exec \"$0\"")
+        .arg(&path)
+        .current_dir(&dir)
+        .status()
+        .unwrap();
+    std::fs::read_dir(&dir)
+        .unwrap()
+        .filter_map(|entry| Some(entry.ok()?.path()))
+        .find(|path| {
+            path.file_name()
+                .unwrap()
+                .to_string_lossy()
+                .starts_with("core")
+        })
+}
+
+/// Starts `bkpt --gdbserver` on a fixture, giving the server and the port it
+/// listens on
+pub fn gdbserver(fixture: &str) -> (std::process::Child, u16) {
+    use std::io::BufRead;
+
+    let path = compile(fixture);
+    let mut server = Command::new(env!("CARGO_BIN_EXE_bkpt"))
+        .args(["--gdbserver", "127.0.0.1:0", "--"])
+        .arg(path)
+        .stdin(Stdio::null())
+        .stdout(Stdio::piped())
+        .spawn()
+        .unwrap();
+    let mut lines = std::io::BufReader::new(server.stdout.take().unwrap()).lines();
+    let port = lines
+        .by_ref()
+        .find_map(|line| line.ok()?.strip_prefix("Listening on port ")?.parse().ok())
+        .expect("The server didn't start listening");
+    // Keep reading, so the server can carry on printing
+    std::thread::spawn(move || lines.for_each(drop));
+    (server, port)
+}
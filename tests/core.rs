@@ -0,0 +1,152 @@
+//! Inspects core dumps of crashed fixture programs
+
+mod common;
+
+use std::path::Path;
+
+use bkpt::{CoreFile, Debugger, Error, Location, StopEvent, Target};
+use nix::sys::signal::Signal;
+
+fn open(fixture: &str) -> Option<Debugger> {
+    let Some(core) = common::kernel_core(fixture) else {
+        eprintln!("Skipping, as the kernel didn't write a core file");
+        return None;
+    };
+    let core = CoreFile::open(&core, &common::compile(fixture)).unwrap();
+    assert_eq!(core.signal, Some(Signal::SIGSEGV));
+    Some(Debugger::new(core))
+}
+
+#[test]
+fn core_has_registers_and_memory_of_crash() {
+    let Some(mut dbg) = open("segfault.c") else {
+        return;
+    };
+
+    let crash = dbg.evaluate("crash").unwrap();
+    let pc = dbg.evaluate("$rip").unwrap();
+    assert!((crash..crash + 0x20).contains(&pc));
+    assert_eq!(dbg.evaluate("$rdi").unwrap(), 0);
+    // The return address into main is on the stack
+    let main = dbg.evaluate("main").unwrap();
+    let ret = dbg.evaluate("[$rbp + 8]").unwrap();
+    assert!((main..main + 0x40).contains(&ret));
+}
+
+#[test]
+fn core_is_read_only() {
+    let Some(mut dbg) = open("segfault.c") else {
+        return;
+    };
+
+    assert!(matches!(dbg.continue_(), Err(Error::State(_))));
+    assert!(matches!(dbg.step(), Err(Error::State(_))));
+    assert!(dbg.execute("reg write rax 1").is_err());
+    assert!(dbg.execute("b set main").is_err());
+}
+
+#[test]
+fn core_backtrace_goes_through_main() {
+    let Some(core) = common::kernel_core("segfault.c") else {
+        return;
+    };
+    let output = std::process::Command::new(env!("CARGO_BIN_EXE_bkpt"))
+        .arg("--core")
+        .arg(&core)
+        .arg(common::compile("segfault.c"))
+        .stdin(std::process::Stdio::null())
+        .output()
+        .unwrap();
+    let output = String::from_utf8_lossy(&output.stdout);
+
+    assert!(
+        output.contains("Program terminated with SIGSEGV"),
+        "{output}"
+    );
+    assert!(output.contains("in crash"), "{output}");
+    assert!(output.contains("return *ptr;"), "{output}");
+}
+
+#[test]
+fn non_core_files_are_rejected() {
+    let exe = common::compile("hello.c");
+
+    assert!(matches!(CoreFile::open(&exe, &exe), Err(Error::Elf(_))));
+    assert!(CoreFile::open(Path::new("/nonexistent"), &exe).is_err());
+}
+
+#[test]
+fn gcore_saves_registers_and_memory() {
+    let mut dbg = common::launch("loops.c");
+    dbg.set_breakpoint(Location::Function("body".into()), None)
+        .unwrap();
+    for _ in 0..4 {
+        dbg.continue_().unwrap();
+    }
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("loops.core");
+    dbg.save_core(path.to_str()).unwrap();
+
+    let core = CoreFile::open(&path, &common::compile("loops.c")).unwrap();
+    let process = dbg.target();
+    assert_eq!(core.signal, None);
+    assert_eq!(core.threads.len(), 1);
+    assert_eq!(core.regs().unwrap().rip, process.regs().unwrap().rip);
+    assert_eq!(core.regs().unwrap().rdi, 3);
+    let stack = process.regs().unwrap().rsp;
+    assert_eq!(
+        core.read_memory(stack, 64).unwrap(),
+        process.read_memory(stack, 64).unwrap()
+    );
+    // Code is read from the executable, without the breakpoint
+    let body = dbg.evaluate("body").unwrap();
+    assert_eq!(core.read_memory(body, 1).unwrap(), dbg.read_memory(body, 1));
+    assert_eq!(dbg.read_memory(body, 1), [0x55]);
+
+    // Saving leaves the breakpoint in place
+    assert!(matches!(
+        dbg.continue_().unwrap(),
+        StopEvent::Breakpoint { num: 1, .. }
+    ));
+}
+
+#[test]
+fn gcore_from_the_prompt_can_be_loaded() {
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("recursion.core");
+    let output = common::run_script(
+        "recursion.c",
+        &[
+            "b set depth if $rdi == 0",
+            "c",
+            &format!("gcore {}", path.display()),
+        ],
+    );
+    assert!(output.contains("Saved corefile"), "{output}");
+
+    let mut dbg = Debugger::new(CoreFile::open(&path, &common::compile("recursion.c")).unwrap());
+    assert_eq!(dbg.evaluate("$rdi").unwrap(), 0);
+    // Every level of the recursion is in the saved stack
+    dbg.execute("up 3").unwrap();
+    assert_eq!(dbg.evaluate("n").unwrap(), 3);
+}
+
+#[test]
+fn core_threads_can_be_selected() {
+    let mut dbg = common::launch("threads.c");
+    dbg.set_breakpoint(Location::Function("work".into()), None)
+        .unwrap();
+    dbg.continue_().unwrap();
+    let path = Path::new(env!("CARGO_TARGET_TMPDIR")).join("threads.core");
+    dbg.save_core(path.to_str()).unwrap();
+    let threads = dbg.target().threads().unwrap();
+    assert!(threads.len() > 1);
+
+    let core = CoreFile::open(&path, &common::compile("threads.c")).unwrap();
+    let mut core = Debugger::new(core);
+    // The thread that hit the breakpoint is followed first
+    assert_eq!(core.target().threads().unwrap()[0].tid, threads[0].tid);
+    let other = &threads[1];
+    core.execute(&format!("thread {}", other.tid)).unwrap();
+    assert_eq!(core.target().threads().unwrap()[0].tid, other.tid);
+    assert_eq!(core.evaluate("$rip").unwrap(), other.regs.rip);
+    assert!(matches!(core.execute("thread 0"), Err(Error::NotFound(_))));
+}
@@ -0,0 +1,274 @@
+//! Drives fixture programs through `bkpt --dap` as an editor would
+
+mod common;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::process::{Child, ChildStdin, ChildStdout, Command, Stdio};
+
+use serde_json::{json, Value};
+
+/// Sends requests to the adapter and reads its responses and events
+struct Client {
+    adapter: Child,
+    input: ChildStdin,
+    output: BufReader<ChildStdout>,
+    seq: u64,
+    /// Events read while waiting for something else
+    events: Vec<Value>,
+}
+
+impl Client {
+    /// Starts the adapter and launches a fixture, with breakpoints set by
+    /// `configure` before it runs
+    fn launch(fixture: &str, stop_on_entry: bool, configure: impl FnOnce(&mut Client)) -> Client {
+        let path = common::compile(fixture);
+        let mut adapter = Command::new(env!("CARGO_BIN_EXE_bkpt"))
+            .arg("--dap")
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .spawn()
+            .unwrap();
+        let mut client = Client {
+            input: adapter.stdin.take().unwrap(),
+            output: BufReader::new(adapter.stdout.take().unwrap()),
+            adapter,
+            seq: 0,
+            events: Vec::new(),
+        };
+
+        client.request("initialize", json!({ "adapterID": "bkpt" }));
+        let args = json!({ "program": path, "stopOnEntry": stop_on_entry });
+        client.request("launch", args);
+        client.event("initialized");
+        configure(&mut client);
+        client.request("configurationDone", json!({}));
+        client
+    }
+
+    fn read(&mut self) -> Value {
+        let mut len = 0;
+        loop {
+            let mut header = String::new();
+            assert_ne!(
+                self.output.read_line(&mut header).unwrap(),
+                0,
+                "The adapter exited"
+            );
+            match header.trim().strip_prefix("Content-Length:") {
+                Some(value) => len = value.trim().parse().unwrap(),
+                None if header.trim().is_empty() => break,
+                None => {}
+            }
+        }
+        let mut body = vec![0; len];
+        self.output.read_exact(&mut body).unwrap();
+        serde_json::from_slice(&body).unwrap()
+    }
+
+    /// Sends a request, giving its response
+    fn request(&mut self, command: &str, args: Value) -> Value {
+        self.seq += 1;
+        let body = json!({
+            "seq": self.seq,
+            "type": "request",
+            "command": command,
+            "arguments": args,
+        })
+        .to_string();
+        write!(self.input, "Content-Length: {}\r\n\r\n{body}", body.len()).unwrap();
+        self.input.flush().unwrap();
+
+        loop {
+            let message = self.read();
+            if message["type"] == "response" {
+                assert_eq!(message["request_seq"], self.seq);
+                return message;
+            }
+            self.events.push(message);
+        }
+    }
+
+    /// Sends a request that should succeed, giving its response's body
+    fn body(&mut self, command: &str, args: Value) -> Value {
+        let response = self.request(command, args);
+        assert_eq!(response["success"], true, "{response}");
+        response["body"].clone()
+    }
+
+    /// Waits for an event, giving its body
+    fn event(&mut self, event: &str) -> Value {
+        if let Some(i) = self.events.iter().position(|e| e["event"] == event) {
+            return self.events.remove(i)["body"].clone();
+        }
+        loop {
+            let message = self.read();
+            if message["event"] == event {
+                return message["body"].clone();
+            }
+            self.events.push(message);
+        }
+    }
+
+    /// Waits for the program to stop, giving the thread it stopped in
+    fn stopped(&mut self, reason: &str) -> Value {
+        let stopped = self.event("stopped");
+        assert_eq!(stopped["reason"], reason, "{stopped}");
+        stopped["threadId"].clone()
+    }
+
+    /// The innermost frame's function and line
+    fn location(&mut self, thread: &Value) -> (String, u64) {
+        let stack = self.body("stackTrace", json!({ "threadId": thread }));
+        let frame = &stack["stackFrames"][0];
+        let name = frame["name"].as_str().unwrap().to_string();
+        (name, frame["line"].as_u64().unwrap())
+    }
+
+    fn variables(&mut self, reference: u64) -> Vec<(String, String)> {
+        let body = self.body("variables", json!({ "variablesReference": reference }));
+        body["variables"]
+            .as_array()
+            .unwrap()
+            .iter()
+            .map(|var| {
+                let field = |name: &str| var[name].as_str().unwrap().to_string();
+                (field("name"), field("value"))
+            })
+            .collect()
+    }
+
+    fn disconnect(mut self) {
+        self.request("disconnect", json!({}));
+        assert!(self.adapter.wait().unwrap().success());
+    }
+}
+
+fn source(fixture: &str) -> String {
+    format!("{}/tests/fixtures/{fixture}", env!("CARGO_MANIFEST_DIR"))
+}
+
+#[test]
+fn line_breakpoints_show_the_stack_and_variables() {
+    let mut client = Client::launch("loops.c", false, |client| {
+        let set = client.body(
+            "setBreakpoints",
+            json!({
+                "source": { "path": source("loops.c") },
+                "breakpoints": [{ "line": 2 }, { "line": 100 }],
+            }),
+        );
+        assert_eq!(set["breakpoints"][0]["verified"], true);
+        assert_eq!(set["breakpoints"][1]["verified"], false);
+    });
+
+    let thread = client.stopped("breakpoint");
+    let threads = client.body("threads", json!({}));
+    assert_eq!(threads["threads"][0]["id"], thread);
+    assert_eq!(client.location(&thread), ("body+0x7".into(), 2));
+
+    let scopes = client.body("scopes", json!({ "frameId": 0 }));
+    let args = scopes["scopes"][0]["variablesReference"].as_u64().unwrap();
+    assert_eq!(client.variables(args), [("i".into(), "0".into())]);
+    // The caller's locals
+    let scopes = client.body("scopes", json!({ "frameId": 1 }));
+    let locals = scopes["scopes"][1]["variablesReference"].as_u64().unwrap();
+    assert!(client
+        .variables(locals)
+        .contains(&("total".into(), "0".into())));
+
+    client.body("continue", json!({ "threadId": thread }));
+    client.stopped("breakpoint");
+    assert_eq!(client.variables(args), [("i".into(), "1".into())]);
+
+    // Clearing the file's breakpoints lets the program finish
+    let args = json!({ "source": { "path": source("loops.c") }, "breakpoints": [] });
+    client.body("setBreakpoints", args);
+    client.body("continue", json!({ "threadId": thread }));
+    assert_eq!(client.event("exited")["exitCode"], 0);
+    client.event("terminated");
+    client.disconnect();
+}
+
+#[test]
+fn stepping_moves_between_lines_and_functions() {
+    let mut client = Client::launch("loops.c", false, |client| {
+        let set = client.body(
+            "setFunctionBreakpoints",
+            json!({ "breakpoints": [{ "name": "main" }] }),
+        );
+        assert_eq!(set["breakpoints"][0]["verified"], true);
+    });
+    let thread = client.stopped("breakpoint");
+    assert_eq!(client.location(&thread), ("main".into(), 5));
+
+    let step = |client: &mut Client, command: &str| {
+        client.body(command, json!({ "threadId": thread }));
+        client.stopped("step");
+        client.location(&thread)
+    };
+    assert_eq!(step(&mut client, "next").1, 6);
+    assert_eq!(step(&mut client, "next").1, 7);
+    assert_eq!(step(&mut client, "next").1, 8);
+    assert_eq!(step(&mut client, "stepIn"), ("body".into(), 1));
+    // Back in the middle of the call's line
+    assert_eq!(step(&mut client, "stepOut").1, 8);
+    // The rest of line 8, then the loop's increment
+    assert_eq!(step(&mut client, "next").1, 7);
+    client.disconnect();
+}
+
+#[test]
+fn program_output_and_commands_become_output_events() {
+    let mut client = Client::launch("hello.c", true, |_| {});
+    let thread = client.stopped("entry");
+
+    let result = client.request(
+        "evaluate",
+        json!({ "expression": "frobnicate", "context": "repl" }),
+    );
+    assert_eq!(result["success"], false);
+    assert_eq!(result["message"], "Unknown command");
+
+    client.body("continue", json!({ "threadId": thread }));
+    assert_eq!(client.event("exited")["exitCode"], 0);
+    let output = client.event("output");
+    assert_eq!(output["category"], "stdout");
+    assert_eq!(output["output"], "Hello, world!\n");
+    client.disconnect();
+}
+
+#[test]
+fn unsupported_requests_fail() {
+    let mut client = Client::launch("hello.c", true, |_| {});
+    client.stopped("entry");
+
+    let response = client.request("restartFrame", json!({ "frameId": 0 }));
+    assert_eq!(response["success"], false);
+    assert_eq!(response["message"], "Unsupported request restartFrame");
+    client.disconnect();
+}
+
+#[test]
+fn requests_are_answered_while_the_program_runs() {
+    let mut client = Client::launch("spin.c", false, |_| {});
+    // Long enough to reach its loop
+    std::thread::sleep(std::time::Duration::from_millis(200));
+
+    client.body("pause", json!({ "threadId": 1 }));
+    let thread = client.stopped("pause");
+    assert!(client.location(&thread).0.starts_with("main"));
+
+    // Breakpoints set while it runs are hit without pausing it
+    client.body("continue", json!({ "threadId": thread }));
+    let args = json!({ "source": { "path": source("spin.c") }, "breakpoints": [{ "line": 5 }] });
+    let set = client.body("setBreakpoints", args);
+    assert_eq!(set["breakpoints"][0]["verified"], true);
+    let thread = client.stopped("breakpoint");
+    assert_eq!(client.location(&thread).1, 5);
+
+    // Disconnecting doesn't wait for it to stop
+    let args = json!({ "source": { "path": source("spin.c") }, "breakpoints": [] });
+    client.body("setBreakpoints", args);
+    client.body("continue", json!({ "threadId": thread }));
+    client.disconnect();
+}
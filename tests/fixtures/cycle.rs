@@ -0,0 +1,28 @@
+use std::cell::RefCell;
+use std::rc::Rc;
+
+struct Node {
+    next: RefCell<Option<Rc<Node>>>,
+}
+
+struct Link {
+    value: u32,
+    next: Option<Box<Link>>,
+}
+
+#[inline(never)]
+fn stop(node: &Rc<Node>, list: &Link) -> u32 {
+    Rc::strong_count(node) as u32 + list.value + list.next.is_some() as u32
+}
+
+fn main() {
+    // A node that points back to itself
+    let node = Rc::new(Node { next: RefCell::new(None) });
+    *node.next.borrow_mut() = Some(node.clone());
+
+    let mut list = Link { value: 0, next: None };
+    for value in 1..1000 {
+        list = Link { value, next: Some(Box::new(list)) };
+    }
+    println!("{}", stop(&node, &list));
+}
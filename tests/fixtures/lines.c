@@ -0,0 +1,7 @@
+#include "lines.h"
+
+int main(void) {
+    int total = twice(1);
+    total += twice(2);
+    return total == 6 ? 0 : 1;
+}
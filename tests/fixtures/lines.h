@@ -0,0 +1,5 @@
+// Included by lines.c, so the program has lines in two source files
+__attribute__((noinline)) static int twice(int i) {
+    int doubled = i * 2;
+    return doubled;
+}
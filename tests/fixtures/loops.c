@@ -0,0 +1,12 @@
+__attribute__((noinline)) int body(int i) {
+    return i * 2;
+}
+
+int main(void) {
+    int total = 0;
+    for (int i = 0; i < 10; i++) {
+        total += body(i);
+    }
+    // 2 * (0 + 1 + ... + 9)
+    return total == 90 ? 0 : 1;
+}
@@ -0,0 +1,10 @@
+#[inline(never)]
+fn check(values: &[u32], index: usize) -> u32 {
+    values[index]
+}
+
+fn main() {
+    let values = vec![1, 2, 3];
+    let index = std::env::args().count() + 4;
+    println!("{}", check(&values, index));
+}
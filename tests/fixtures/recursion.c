@@ -0,0 +1,10 @@
+__attribute__((noinline)) int depth(int n) {
+    if (n == 0) {
+        return 0;
+    }
+    return depth(n - 1) + 1;
+}
+
+int main(void) {
+    return depth(3);
+}
@@ -0,0 +1,7 @@
+__attribute__((noinline)) int crash(int *ptr) {
+    return *ptr;
+}
+
+int main(void) {
+    return crash((int *)0);
+}
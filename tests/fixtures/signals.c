@@ -0,0 +1,15 @@
+#include <signal.h>
+
+static volatile sig_atomic_t handled = 0;
+
+static void handler(int signal) {
+    (void)signal;
+    handled = 1;
+}
+
+int main(void) {
+    signal(SIGUSR1, handler);
+    raise(SIGUSR1);
+    // The exit code shows whether the handler ran
+    return handled;
+}
@@ -0,0 +1,7 @@
+int main(void) {
+    volatile unsigned long count = 0;
+    // Runs until it's stopped
+    for (;;) {
+        count++;
+    }
+}
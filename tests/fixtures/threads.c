@@ -0,0 +1,32 @@
+#include <pthread.h>
+
+#define THREADS 4
+
+static int results[THREADS];
+
+static void *work(void *arg) {
+    int n = *(int *)arg;
+    results[n] = n * n;
+    return NULL;
+}
+
+__attribute__((noinline)) int report(int total) {
+    return total;
+}
+
+int main(void) {
+    pthread_t threads[THREADS];
+    int ids[THREADS];
+    for (int i = 0; i < THREADS; i++) {
+        ids[i] = i;
+        pthread_create(&threads[i], NULL, work, &ids[i]);
+    }
+
+    int total = 0;
+    for (int i = 0; i < THREADS; i++) {
+        pthread_join(threads[i], NULL);
+        total += results[i];
+    }
+    // 0 + 1 + 4 + 9
+    return report(total);
+}
@@ -0,0 +1,15 @@
+#include <unistd.h>
+
+__attribute__((noinline)) int body(int i) {
+    return i + 1;
+}
+
+int main(void) {
+    // Runs for about a second, long enough to be attached to
+    int total = 0;
+    for (int i = 0; i < 100; i++) {
+        total = body(total);
+        usleep(10000);
+    }
+    return total == 100 ? 0 : 1;
+}
@@ -0,0 +1,206 @@
+//! Drives fixture programs through `bkpt --gdbserver` with a small client
+
+mod common;
+
+use std::io::{BufRead, BufReader, Read, Write};
+use std::net::TcpStream;
+use std::process::Child;
+
+use object::{Object, ObjectSymbol};
+
+/// Sends packets to the server and reads its replies
+struct Client {
+    stream: TcpStream,
+    reader: BufReader<TcpStream>,
+    server: Child,
+    /// Added to the addresses in the executable to give where they're loaded
+    bias: u64,
+}
+
+/// Index of rdi and rip in `g` packets
+const RDI: usize = 5;
+const RIP: usize = 16;
+
+/// `AT_ENTRY` in the auxiliary vector, the entry point's runtime address
+const AT_ENTRY: u64 = 9;
+
+impl Client {
+    fn connect(fixture: &str) -> Client {
+        let (server, port) = common::gdbserver(fixture);
+        let stream = TcpStream::connect(("127.0.0.1", port)).unwrap();
+        stream.set_nodelay(true).unwrap();
+        let reader = BufReader::new(stream.try_clone().unwrap());
+        let mut client = Client {
+            stream,
+            reader,
+            server,
+            bias: 0,
+        };
+
+        // The executable is position independent, so find where it's loaded
+        // from its entry point, like gdb does
+        let auxv = client.request_bytes("qXfer:auxv:read::0,1000");
+        let auxv = auxv.strip_prefix(b"l").unwrap();
+        let entry = auxv
+            .chunks_exact(16)
+            .map(|pair| {
+                let word = |bytes: &[u8]| u64::from_le_bytes(bytes.try_into().unwrap());
+                (word(&pair[..8]), word(&pair[8..]))
+            })
+            .find(|(key, _)| *key == AT_ENTRY)
+            .unwrap()
+            .1;
+        client.bias = entry - elf(fixture).entry();
+        client
+    }
+
+    fn request(&mut self, packet: &str) -> String {
+        String::from_utf8(self.request_bytes(packet)).unwrap()
+    }
+
+    fn request_bytes(&mut self, packet: &str) -> Vec<u8> {
+        let checksum = packet.bytes().fold(0u8, |sum, byte| sum.wrapping_add(byte));
+        write!(self.stream, "${packet}#{checksum:02x}").unwrap();
+
+        let mut ack = [0];
+        self.reader.read_exact(&mut ack).unwrap();
+        assert_eq!(ack[0], b'+');
+        let mut reply = Vec::new();
+        self.reader.read_until(b'$', &mut reply).unwrap();
+        reply.clear();
+        self.reader.read_until(b'#', &mut reply).unwrap();
+        reply.pop();
+        let mut checksum = [0; 2];
+        self.reader.read_exact(&mut checksum).unwrap();
+        self.stream.write_all(b"+").unwrap();
+
+        // Bytes that frame packets are escaped
+        let mut bytes = reply.into_iter();
+        std::iter::from_fn(|| match bytes.next()? {
+            b'}' => bytes.next().map(|byte| byte ^ 0x20),
+            byte => Some(byte),
+        })
+        .collect()
+    }
+
+    fn symbol(&self, fixture: &str, name: &str) -> u64 {
+        let elf = elf(fixture);
+        let symbol = elf.symbols().find(|sym| sym.name() == Ok(name)).unwrap();
+        symbol.address() + self.bias
+    }
+
+    /// Reads a 64-bit register from a `g` packet
+    fn register(&mut self, index: usize) -> u64 {
+        let regs = self.request("g");
+        let hex = &regs[index * 16..index * 16 + 16];
+        u64::from_str_radix(hex, 16).unwrap().swap_bytes()
+    }
+}
+
+impl Drop for Client {
+    /// Disconnects, which makes the server kill the program and exit
+    fn drop(&mut self) {
+        let _ = self.stream.shutdown(std::net::Shutdown::Both);
+        let _ = self.server.wait();
+    }
+}
+
+fn elf(fixture: &str) -> object::File<'static> {
+    let data = std::fs::read(common::compile(fixture)).unwrap();
+    object::File::parse(&*data.leak()).unwrap()
+}
+
+#[test]
+fn breakpoints_stop_the_program() {
+    let mut client = Client::connect("loops.c");
+    let body = client.symbol("loops.c", "body");
+
+    assert!(client
+        .request("qSupported:swbreak+")
+        .contains("qXfer:features:read+"));
+    assert!(client.request("?").starts_with("T05"));
+    assert_eq!(client.request(&format!("Z0,{body:x},1")), "OK");
+
+    for i in 0..3 {
+        let stop = client.request("vCont;c");
+        assert!(stop.starts_with("T05swbreak:;"), "{stop}");
+        assert_eq!(client.register(RIP), body);
+        assert_eq!(client.register(RDI), i);
+    }
+    // The breakpoint's int3 is hidden
+    assert_eq!(client.request(&format!("m{body:x},1")), "55");
+
+    assert_eq!(client.request(&format!("z0,{body:x},1")), "OK");
+    assert_eq!(client.request("c"), "W00");
+    assert!(client.server.wait().unwrap().success());
+}
+
+#[test]
+fn registers_and_memory_can_be_written() {
+    let mut client = Client::connect("loops.c");
+    let body = client.symbol("loops.c", "body");
+    client.request(&format!("Z0,{body:x},1"));
+    client.request("c");
+
+    assert_eq!(client.request("P5=0700000000000000"), "OK");
+    assert_eq!(client.register(RDI), 7);
+    assert_eq!(client.request("p5"), "0700000000000000");
+
+    let stack = client.register(7);
+    assert_eq!(client.request(&format!("M{stack:x},4:deadbeef")), "OK");
+    assert_eq!(client.request(&format!("m{stack:x},4")), "deadbeef");
+    assert!(client.request("m0,4").starts_with('E'));
+}
+
+#[test]
+fn single_steps_and_kill() {
+    let mut client = Client::connect("loops.c");
+    let body = client.symbol("loops.c", "body");
+    client.request(&format!("Z0,{body:x},1"));
+    client.request("c");
+
+    // Steps off the breakpoint, which stays inserted
+    assert!(client.request("s").starts_with("T05"));
+    assert_eq!(client.register(RIP), body + 1);
+    assert!(client.request("c").starts_with("T05"));
+    assert_eq!(client.register(RIP), body);
+
+    write!(client.stream, "$k#6b").unwrap();
+    assert!(client.server.wait().unwrap().success());
+}
+
+#[test]
+fn target_description_is_served() {
+    let mut client = Client::connect("hello.c");
+
+    let xml = client.request("qXfer:features:read:target.xml:0,10000");
+    assert!(xml.starts_with("l<?xml"), "{xml}");
+    assert!(xml.contains("<reg name=\"rip\" bitsize=\"64\""));
+    assert!(xml.contains("<feature name=\"org.gnu.gdb.i386.sse\">"));
+
+    // Read in parts, the first of which says there's more
+    assert!(client
+        .request("qXfer:features:read:target.xml:0,10")
+        .starts_with('m'));
+}
+
+#[test]
+fn malformed_packets_get_errors() {
+    let mut client = Client::connect("hello.c");
+
+    // Ranges that run past the end of the address space
+    assert_eq!(client.request("mffffffffffffffff,10"), "E01");
+    assert_eq!(client.request("Mffffffffffffffff,2:9090"), "E01");
+    assert_eq!(
+        client.request("qXfer:features:read:target.xml:10,ffffffffffffffff"),
+        "E01"
+    );
+    // Packets that aren't ASCII aren't understood
+    assert_eq!(client.request("\u{e9}"), "");
+    // Resume actions that are empty or unknown
+    assert_eq!(client.request("vCont;"), "E01");
+    assert_eq!(client.request("vCont;:1"), "E01");
+    assert_eq!(client.request("vCont;\u{e9}"), "E01");
+    // The server is still there
+    assert!(client.request("?").starts_with("T05"));
+}
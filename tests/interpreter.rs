@@ -0,0 +1,113 @@
+//! Drives fixture programs through `bkpt --interpreter=json`, checking the
+//! objects tools read
+
+mod common;
+
+use serde_json::Value;
+
+/// Runs commands on a fixture, giving the objects printed, one per line
+fn run_json(fixture: &str, commands: &[&str]) -> Vec<Value> {
+    let path = common::compile(fixture);
+    let output = common::run_bkpt(&["--interpreter=json".as_ref(), path.as_os_str()], commands);
+    output
+        .lines()
+        .map(|line| serde_json::from_str(line).unwrap_or_else(|e| panic!("{e}: {line}")))
+        .collect()
+}
+
+#[test]
+fn every_command_gives_one_object() {
+    let objects = run_json(
+        "loops.c",
+        &[
+            "b set body",
+            "c",
+            "info args",
+            "frobnicate",
+            "b unset 1",
+            "c",
+        ],
+    );
+
+    let types: Vec<&str> = objects
+        .iter()
+        .map(|object| object["type"].as_str().unwrap())
+        .collect();
+    assert_eq!(
+        types,
+        ["started", "result", "stop", "result", "error", "result", "stop"]
+    );
+    assert!(objects[0]["pid"].is_u64());
+
+    let set = &objects[1];
+    assert_eq!(set["command"], "b set body");
+    assert_eq!(set["breakpoint"]["number"], 1);
+    let addr = set["breakpoint"]["address"].clone();
+
+    let stop = &objects[2];
+    assert_eq!(stop["reason"], "breakpoint");
+    assert_eq!(stop["breakpoint"], 1);
+    assert_eq!(stop["address"], addr);
+    assert_eq!(stop["frame"]["function"], "body");
+    assert_eq!(stop["frame"]["line"], 1);
+    assert_eq!(stop["instruction"]["address"], addr);
+
+    let args = &objects[3]["variables"];
+    assert_eq!(args[0]["name"], "i");
+    assert_eq!(args[0]["value"], "0");
+
+    assert_eq!(objects[4]["message"], "Unknown command");
+
+    assert_eq!(objects[6]["reason"], "exited");
+    assert_eq!(objects[6]["code"], 0);
+}
+
+#[test]
+fn program_output_comes_before_the_stop() {
+    let objects = run_json("hello.c", &["c"]);
+
+    assert_eq!(objects[1]["type"], "output", "{objects:?}");
+    assert_eq!(objects[1]["output"], "Hello, world!\n");
+    assert_eq!(objects[2]["type"], "stop");
+    assert_eq!(objects[2]["reason"], "exited");
+}
+
+#[test]
+fn lists_are_arrays_even_when_empty() {
+    let objects = run_json("loops.c", &["b list", "b set main", "c", "bt", "next"]);
+
+    assert_eq!(objects[1]["breakpoints"], Value::Array(Vec::new()));
+    let frames = objects[4]["frames"].as_array().unwrap();
+    assert_eq!(frames[0]["function"], "main");
+    assert_eq!(frames[0]["level"], 0);
+
+    let next = &objects[5];
+    assert_eq!(next["type"], "stop");
+    assert_eq!(next["reason"], "step");
+    assert_eq!(next["frame"]["line"], 6);
+    let current: Vec<&Value> = next["source"]
+        .as_array()
+        .unwrap()
+        .iter()
+        .filter(|line| line["current"] == true)
+        .collect();
+    assert_eq!(current.len(), 1);
+    assert_eq!(current[0]["line"], 6);
+}
+
+#[test]
+fn failing_to_start_is_an_error() {
+    let objects = run_json("loops.c", &[]);
+    assert_eq!(objects.len(), 1);
+
+    let output = common::run_bkpt(
+        &[
+            "--interpreter=json".as_ref(),
+            "--core=/nonexistent".as_ref(),
+            "/nonexistent".as_ref(),
+        ],
+        &[],
+    );
+    let object: Value = serde_json::from_str(output.trim()).unwrap();
+    assert_eq!(object["type"], "error", "{output}");
+}
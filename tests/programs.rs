@@ -0,0 +1,284 @@
+//! Drives fixture programs through the library API
+
+mod common;
+
+use std::process::Command;
+use std::thread;
+use std::time::Duration;
+
+use bkpt::{Debugger, Location, Process, Register, RegisterSelector, StopEvent};
+use nix::sys::signal::Signal;
+use nix::unistd::Pid;
+
+fn function(name: &str) -> Location {
+    Location::Function(name.to_string())
+}
+
+#[test]
+fn hello_runs_to_exit() {
+    let mut dbg = common::launch("hello.c");
+
+    assert_eq!(dbg.continue_().unwrap(), StopEvent::Exited(0));
+}
+
+#[test]
+fn other_children_are_left_to_be_waited_for() {
+    let mut child = Command::new("true").spawn().unwrap();
+    // Exited, with its status waiting to be collected
+    thread::sleep(Duration::from_millis(100));
+    let mut dbg = common::launch("loops.c");
+
+    assert_eq!(dbg.continue_().unwrap(), StopEvent::Exited(0));
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn detaching_leaves_the_program_running_without_breakpoints() {
+    let path = common::compile("ticks.c");
+    let mut child = Command::new(path).spawn().unwrap();
+    // Past the exec, so its own code is mapped
+    thread::sleep(Duration::from_millis(100));
+    let process = Process::attach(Pid::from_raw(child.id() as i32)).unwrap();
+    let mut dbg = Debugger::new(process);
+    let addr = dbg.set_breakpoint(function("body"), None).unwrap().addr;
+    dbg.set_breakpoint(function("usleep"), None).unwrap();
+
+    assert_eq!(
+        dbg.continue_().unwrap(),
+        StopEvent::Breakpoint { num: 1, addr }
+    );
+    dbg.detach().unwrap();
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn dropping_the_debugger_detaches_from_an_attached_program() {
+    let path = common::compile("ticks.c");
+    let mut child = Command::new(path).spawn().unwrap();
+    thread::sleep(Duration::from_millis(100));
+    let process = Process::attach(Pid::from_raw(child.id() as i32)).unwrap();
+    let mut dbg = Debugger::new(process);
+    dbg.set_breakpoint(function("body"), None).unwrap();
+
+    drop(dbg);
+    assert!(child.wait().unwrap().success());
+}
+
+#[test]
+fn breakpoint_on_main_is_hit() {
+    let mut dbg = common::launch("hello.c");
+    let addr = dbg.set_breakpoint(function("main"), None).unwrap().addr;
+
+    assert_eq!(
+        dbg.continue_().unwrap(),
+        StopEvent::Breakpoint { num: 1, addr }
+    );
+    let rip = Register::from_selector(RegisterSelector::Name("rip")).unwrap();
+    assert_eq!(dbg.read_register(&rip).unwrap(), addr as u128);
+    assert_eq!(dbg.continue_().unwrap(), StopEvent::Exited(0));
+}
+
+#[test]
+fn breakpoint_on_another_name_for_a_function_is_hit() {
+    let mut dbg = common::launch("hello.c");
+    dbg.set_breakpoint(function("main"), None).unwrap();
+    dbg.continue_().unwrap();
+
+    // glibc's `_IO_puts` is the same function as `puts`
+    let addr = dbg.set_breakpoint(function("_IO_puts"), None).unwrap().addr;
+
+    assert_eq!(dbg.evaluate("puts").unwrap(), addr);
+    assert_eq!(
+        dbg.continue_().unwrap(),
+        StopEvent::Breakpoint { num: 2, addr }
+    );
+}
+
+#[test]
+fn breakpoint_in_loop_is_hit_each_iteration() {
+    let mut dbg = common::launch("loops.c");
+    dbg.set_breakpoint(function("body"), None).unwrap();
+
+    for i in 0..10 {
+        assert!(matches!(
+            dbg.continue_().unwrap(),
+            StopEvent::Breakpoint { num: 1, .. }
+        ));
+        assert_eq!(dbg.evaluate("$rdi").unwrap(), i);
+    }
+    assert_eq!(dbg.continue_().unwrap(), StopEvent::Exited(0));
+}
+
+#[test]
+fn conditional_breakpoint_skips_other_hits() {
+    let mut dbg = common::launch("loops.c");
+    dbg.set_breakpoint(function("body"), Some("$rdi == 7".to_string()))
+        .unwrap();
+
+    assert!(matches!(
+        dbg.continue_().unwrap(),
+        StopEvent::Breakpoint { num: 1, .. }
+    ));
+    assert_eq!(dbg.evaluate("$rdi").unwrap(), 7);
+    assert_eq!(dbg.continue_().unwrap(), StopEvent::Exited(0));
+}
+
+#[test]
+fn recursive_calls_hit_breakpoint_at_each_depth() {
+    let mut dbg = common::launch("recursion.c");
+    dbg.set_breakpoint(function("depth"), None).unwrap();
+
+    for n in (0..=3).rev() {
+        assert!(matches!(
+            dbg.continue_().unwrap(),
+            StopEvent::Breakpoint { num: 1, .. }
+        ));
+        assert_eq!(dbg.evaluate("$rdi").unwrap(), n);
+    }
+    assert_eq!(dbg.continue_().unwrap(), StopEvent::Exited(3));
+}
+
+#[test]
+fn stepping_moves_through_instructions() {
+    let mut dbg = common::launch("recursion.c");
+    let addr = dbg.set_breakpoint(function("depth"), None).unwrap().addr;
+    dbg.continue_().unwrap();
+
+    let StopEvent::Step { pc } = dbg.step().unwrap() else {
+        panic!("Expected a step");
+    };
+    assert!(pc > addr);
+    assert_eq!(dbg.evaluate("$rip").unwrap(), pc);
+}
+
+#[test]
+fn line_steps_and_finish_follow_the_source() {
+    let mut dbg = common::launch("loops.c");
+    dbg.set_breakpoint(Location::FileLine("loops.c".into(), 8), None)
+        .unwrap();
+    dbg.continue_().unwrap();
+    let line = |dbg: &mut bkpt::Debugger| dbg.stack().unwrap()[0].source.clone().unwrap().1;
+    let symbol = |dbg: &mut bkpt::Debugger| dbg.stack().unwrap()[0].function.clone().unwrap();
+
+    assert!(matches!(
+        dbg.step_line(false).unwrap(),
+        StopEvent::Step { .. }
+    ));
+    assert_eq!(symbol(&mut dbg), ("body".to_string(), 0));
+    dbg.finish().unwrap();
+    assert_eq!(line(&mut dbg), 8);
+    // The rest of the line, then the loop's increment
+    dbg.step_line(true).unwrap();
+    assert_eq!(line(&mut dbg), 7);
+    // Onto the breakpoint at the start of the line
+    assert!(matches!(
+        dbg.step_line(true).unwrap(),
+        StopEvent::Breakpoint { num: 1, .. }
+    ));
+    // Stepping over a call still stops at breakpoints in it
+    dbg.set_breakpoint(function("body"), None).unwrap();
+    assert!(matches!(
+        dbg.step_line(true).unwrap(),
+        StopEvent::Breakpoint { num: 2, .. }
+    ));
+}
+
+#[test]
+fn finish_returns_to_the_calling_recursion() {
+    let mut dbg = common::launch("recursion.c");
+    dbg.set_breakpoint(function("depth"), Some("$rdi == 0".into()))
+        .unwrap();
+    dbg.continue_().unwrap();
+
+    // Each return is to the same address, one level of recursion up
+    for depth in 0..3 {
+        assert!(matches!(dbg.finish().unwrap(), StopEvent::Step { .. }));
+        assert_eq!(dbg.evaluate("$rax").unwrap(), depth);
+        assert_eq!(dbg.evaluate("n").unwrap(), depth + 1);
+    }
+    dbg.finish().unwrap();
+    assert_eq!(dbg.stack().unwrap()[0].function.clone().unwrap().0, "main");
+}
+
+#[test]
+fn register_writes_change_the_program() {
+    let mut dbg = common::launch("recursion.c");
+    dbg.set_breakpoint(function("depth"), None).unwrap();
+    dbg.continue_().unwrap();
+    dbg.unset_breakpoint(1).unwrap();
+
+    dbg.execute("reg write rdi 5").unwrap();
+
+    assert_eq!(dbg.continue_().unwrap(), StopEvent::Exited(5));
+}
+
+#[test]
+fn threaded_program_exits_with_result() {
+    let mut dbg = common::launch("threads.c");
+    dbg.set_breakpoint(function("report"), None).unwrap();
+
+    assert!(matches!(
+        dbg.continue_().unwrap(),
+        StopEvent::Breakpoint { num: 1, .. }
+    ));
+    assert_eq!(dbg.evaluate("$rdi").unwrap(), 14);
+    assert_eq!(dbg.continue_().unwrap(), StopEvent::Exited(14));
+}
+
+#[test]
+fn breakpoint_in_worker_threads_is_hit_by_each() {
+    let mut dbg = common::launch("threads.c");
+    dbg.set_breakpoint(function("work"), None).unwrap();
+
+    let mut ids = Vec::new();
+    let mut tids = Vec::new();
+    for _ in 0..4 {
+        assert!(matches!(
+            dbg.continue_().unwrap(),
+            StopEvent::Breakpoint { num: 1, .. }
+        ));
+        let arg = dbg.evaluate("$rdi").unwrap();
+        let id = dbg.read_memory(arg, 4);
+        ids.push(i32::from_le_bytes(id.try_into().unwrap()));
+        tids.push(dbg.target().threads().unwrap()[0].tid);
+    }
+    ids.sort();
+    assert_eq!(ids, [0, 1, 2, 3]);
+    tids.sort();
+    tids.dedup();
+    assert_eq!(tids.len(), 4);
+    assert_eq!(dbg.continue_().unwrap(), StopEvent::Exited(14));
+}
+
+#[test]
+fn signals_stop_the_program_and_are_delivered_on_continue() {
+    let mut dbg = common::launch("signals.c");
+
+    assert_eq!(dbg.continue_().unwrap(), StopEvent::Signal(Signal::SIGUSR1));
+    // The handler runs, so the program exits with 1
+    assert_eq!(dbg.continue_().unwrap(), StopEvent::Exited(1));
+}
+
+#[test]
+fn segfault_stops_at_faulting_instruction() {
+    let mut dbg = common::launch("segfault.c");
+
+    assert_eq!(dbg.continue_().unwrap(), StopEvent::Signal(Signal::SIGSEGV));
+    let crash = dbg.evaluate("crash").unwrap();
+    let pc = dbg.evaluate("$rip").unwrap();
+    assert!((crash..crash + 0x20).contains(&pc));
+    // The null pointer argument
+    assert_eq!(dbg.evaluate("$rdi").unwrap(), 0);
+}
+
+#[test]
+fn memory_reads_hide_breakpoints() {
+    let mut dbg = common::launch("hello.c");
+    let main = dbg.evaluate("main").unwrap();
+    let before = dbg.read_memory(main, 16);
+
+    let addr = dbg.set_breakpoint(function("main"), None).unwrap().addr;
+
+    assert_eq!(dbg.read_memory(addr, 16), before);
+    assert_eq!(dbg.target().read_memory(addr, 1).unwrap(), [0xcc]);
+}
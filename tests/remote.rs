@@ -0,0 +1,80 @@
+//! Debugs fixture programs through a stub with `target remote`, using
+//! `bkpt --gdbserver` as the stub
+
+mod common;
+
+use bkpt::{Debugger, Location, NoTarget, Register, RegisterSelector, StopEvent};
+
+fn connect(fixture: &str) -> (Debugger, std::process::Child) {
+    let (server, port) = common::gdbserver(fixture);
+    let mut dbg = Debugger::new(NoTarget);
+    dbg.connect(&format!("127.0.0.1:{port}")).unwrap();
+    (dbg, server)
+}
+
+#[test]
+fn breakpoints_are_hit_through_the_stub() {
+    let (mut dbg, mut server) = connect("loops.c");
+    dbg.set_breakpoint(Location::Function("body".into()), None)
+        .unwrap();
+    let rdi = Register::from_selector(RegisterSelector::Name("rdi")).unwrap();
+
+    for i in 0..10 {
+        assert!(matches!(
+            dbg.continue_().unwrap(),
+            StopEvent::Breakpoint { num: 1, .. }
+        ));
+        assert_eq!(dbg.read_register(&rdi).unwrap(), i);
+    }
+    assert_eq!(dbg.continue_().unwrap(), StopEvent::Exited(0));
+    server.wait().unwrap();
+}
+
+#[test]
+fn registers_are_written_through_the_stub() {
+    let (mut dbg, mut server) = connect("loops.c");
+    let num = dbg
+        .set_breakpoint(Location::Function("body".into()), None)
+        .unwrap()
+        .num;
+    dbg.continue_().unwrap();
+
+    // The first call then returns 90 rather than 0, so the total isn't 90 and
+    // the program exits with 1
+    dbg.execute("reg write rdi 45").unwrap();
+    dbg.unset_breakpoint(num).unwrap();
+    assert_eq!(dbg.continue_().unwrap(), StopEvent::Exited(1));
+    server.wait().unwrap();
+}
+
+#[test]
+fn target_remote_debugs_without_an_executable() {
+    let (mut server, port) = common::gdbserver("loops.c");
+    let output = common::run_bkpt(
+        &[],
+        &[
+            &format!("target remote 127.0.0.1:{port}"),
+            "b set body",
+            "c",
+            "reg read rdi",
+            "bt",
+            "c",
+            "reg read rdi",
+        ],
+    );
+
+    assert!(output.contains("Remote debugging using"), "{output}");
+    assert!(output.contains("Hit breakpoint 1"), "{output}");
+    assert!(output.contains("rdi 0x0\n"), "{output}");
+    assert!(output.contains("in main"), "{output}");
+    assert!(output.contains("rdi 0x1\n"), "{output}");
+    server.wait().unwrap();
+}
+
+#[test]
+fn connecting_to_nothing_is_an_error() {
+    let output = common::run_bkpt(&[], &["target remote 127.0.0.1:1", "reg read rip"]);
+
+    assert!(output.contains("Cannot connect to 127.0.0.1:1"), "{output}");
+    assert!(output.contains("The program is not being run"), "{output}");
+}
@@ -0,0 +1,158 @@
+//! Drives fixture programs through the command line with scripted input
+
+mod common;
+
+#[test]
+fn hello_prints_and_exits() {
+    let output = common::run_script("hello.c", &["c"]);
+
+    assert!(output.contains("Hello, world!"), "{output}");
+    assert!(output.contains("Program exited with code 0"), "{output}");
+}
+
+#[test]
+fn breakpoint_hit_shows_source() {
+    let output = common::run_script("loops.c", &["b set body", "c"]);
+
+    assert!(output.contains("Breakpoint 1 set at"), "{output}");
+    assert!(output.contains("Hit breakpoint 1 at"), "{output}");
+    assert!(output.contains("int body(int i)"), "{output}");
+}
+
+#[test]
+fn same_line_in_two_files_sets_two_breakpoints() {
+    let output = common::run_script(
+        "lines.c",
+        &[
+            "list lines.h:1",
+            "b set 4",
+            "list lines.c:1",
+            "b set 4",
+            "c",
+            "c",
+            "c",
+            "c",
+        ],
+    );
+
+    assert!(output.contains("Breakpoint 2 set at"), "{output}");
+    assert!(output.contains("Hit breakpoint 2 at"), "{output}");
+    assert!(output.contains("Hit breakpoint 1 at"), "{output}");
+    assert!(!output.contains("SIGTRAP"), "{output}");
+    assert!(output.contains("Program exited with code 0"), "{output}");
+}
+
+#[test]
+fn backtrace_shows_recursive_frames() {
+    let output = common::run_script("recursion.c", &["b set depth if $rdi == 0", "c", "bt"]);
+
+    let frames = output
+        .lines()
+        .filter(|line| line.contains(" in depth"))
+        .count();
+    assert_eq!(frames, 4, "{output}");
+    assert!(output.contains(" in main"), "{output}");
+}
+
+#[test]
+fn registers_and_expressions_are_printed() {
+    let output = common::run_script(
+        "recursion.c",
+        &["b set depth", "c", "reg read rdi", "p $rdi * 2", "p $1 + 1"],
+    );
+
+    assert!(output.contains("rdi 0x3"), "{output}");
+    assert!(output.contains("$1 = 6 (0x6)"), "{output}");
+    assert!(output.contains("$2 = 7 (0x7)"), "{output}");
+}
+
+#[test]
+fn outer_frames_only_have_preserved_registers() {
+    let output = common::run_script(
+        "recursion.c",
+        &[
+            "b set depth",
+            "c",
+            "up",
+            "reg read rip",
+            "reg read rbp",
+            "reg read rdi",
+        ],
+    );
+
+    assert!(output.contains("rip 0x"), "{output}");
+    assert!(output.contains("rbp 0x"), "{output}");
+    assert!(
+        output.contains("Value of rdi is not available in frame 1"),
+        "{output}"
+    );
+}
+
+#[test]
+fn errors_are_reported_without_ending_the_session() {
+    let output = common::run_script(
+        "segfault.c",
+        &["frobnicate", "b unset 4", "c", "reg read nope", "c", "c"],
+    );
+
+    assert!(output.contains("Unknown command"), "{output}");
+    assert!(output.contains("No breakpoint number 4"), "{output}");
+    assert!(output.contains("Program received SIGSEGV"), "{output}");
+    assert!(output.contains("Invalid register nope"), "{output}");
+}
+
+#[test]
+fn commands_after_exit_report_no_process() {
+    let output = common::run_script("hello.c", &["c", "reg read rip"]);
+
+    assert!(output.contains("The program is not being run"), "{output}");
+}
+
+#[test]
+fn cycles_and_long_lists_are_cut_short() {
+    let output = common::run_script(
+        "cycle.rs",
+        &["b set cycle::stop", "c", "up", "info locals", "c"],
+    );
+
+    assert!(output.contains("list = Link { value: 999"), "{output}");
+    assert!(output.contains("node = Rc(strong=2, weak=0)"), "{output}");
+    assert!(output.contains("Box(...)"), "{output}");
+    assert!(output.contains("Rc(strong=2, weak=0) ...)"), "{output}");
+    assert!(output.contains("Program exited with code 0"), "{output}");
+}
+
+#[test]
+fn rust_panics_are_caught() {
+    let output = common::run_script("panic.rs", &["catch panic", "c", "bt"]);
+
+    assert!(output.contains("Caught panic: panicked at"), "{output}");
+    assert!(output.contains("index out of bounds"), "{output}");
+    assert!(output.contains("panic::check"), "{output}");
+}
+
+#[test]
+fn list_centers_on_lines_and_continues() {
+    let output = common::run_script(
+        "threads.c",
+        &[
+            "list threads.c:1",
+            "list",
+            "list 20",
+            "list threads.c:30",
+            "list",
+            "list 2",
+        ],
+    );
+
+    let listed: Vec<u64> = output
+        .lines()
+        .filter_map(|line| line.split_whitespace().next()?.parse().ok())
+        .collect();
+    let expected: Vec<u64> = [1..=10, 11..=20, 15..=24, 25..=32, 1..=10]
+        .into_iter()
+        .flatten()
+        .collect();
+    assert_eq!(listed, expected, "{output}");
+    assert!(output.contains("Line number 33 out of range"), "{output}");
+}